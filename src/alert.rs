@@ -0,0 +1,281 @@
+//! Operator alerting and relay notifications.
+//! Alerts are always logged and optionally posted to a webhook.
+
+use serde::Serialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Compact record of a confirmed relay posted to the success webhook
+#[derive(Debug, Serialize, Clone)]
+pub struct RelayNotification {
+    pub nonce: u64,
+    pub signature: String,
+    pub amount: u64,
+    pub destination: String,
+    pub slot: Option<u64>,
+}
+
+pub struct Alerter {
+    client: reqwest::Client,
+    webhook_url: Option<String>,
+    success_webhook_url: Option<String>,
+    batch: Option<Batch>,
+}
+
+/// Alerts awaiting the next batch flush, counted by message
+#[derive(Clone)]
+struct Batch {
+    window: Duration,
+    alerts: Arc<Mutex<BTreeMap<String, usize>>>,
+}
+
+impl Alerter {
+    /// Creates an alerter. With a batch window, webhook alerts are collected
+    /// and posted as one summary per window by a background task.
+    pub fn new(
+        webhook_url: Option<String>,
+        success_webhook_url: Option<String>,
+        batch_window: Option<Duration>,
+    ) -> Self {
+        let client = reqwest::Client::new();
+        let batch = match (&webhook_url, batch_window) {
+            (Some(url), Some(window)) => {
+                let batch = Batch {
+                    window,
+                    alerts: Arc::new(Mutex::new(BTreeMap::new())),
+                };
+                tokio::spawn(flush_batches(client.clone(), url.clone(), batch.clone()));
+                Some(batch)
+            }
+            _ => None,
+        };
+
+        Self {
+            client,
+            webhook_url,
+            success_webhook_url,
+            batch,
+        }
+    }
+
+    /// Logs an alert and posts it to the webhook if one is configured,
+    /// batched when a batch window is set. Delivery failures are logged and
+    /// never propagated.
+    pub async fn send(&self, message: &str) {
+        println!("ALERT: {}", message);
+
+        if let Some(batch) = &self.batch {
+            *batch
+                .alerts
+                .lock()
+                .unwrap()
+                .entry(message.to_string())
+                .or_default() += 1;
+            return;
+        }
+        if let Some(url) = &self.webhook_url {
+            post(&self.client, url, &json!({ "text": message })).await;
+        }
+    }
+
+    /// Logs an alert and posts it immediately, bypassing batching
+    pub async fn send_critical(&self, message: &str) {
+        println!("ALERT: {}", message);
+
+        if let Some(url) = &self.webhook_url {
+            post(&self.client, url, &json!({ "text": message })).await;
+        }
+    }
+
+    /// Posts the alerts batched so far without waiting for the window to
+    /// end, so none are lost on shutdown
+    pub async fn flush(&self) {
+        if let (Some(batch), Some(url)) = (&self.batch, &self.webhook_url) {
+            post_batch(&self.client, url, batch).await;
+        }
+    }
+
+    /// Whether successful relays are posted anywhere
+    pub fn notifies_success(&self) -> bool {
+        self.success_webhook_url.is_some()
+    }
+
+    /// Posts confirmed relays to the success webhook, a single relay as an
+    /// object and a batch as an array. Delivery failures are logged and never propagated.
+    pub async fn notify_success(&self, notifications: &[RelayNotification]) {
+        let url = match &self.success_webhook_url {
+            Some(url) => url,
+            None => return,
+        };
+
+        match notifications {
+            [] => {}
+            [notification] => post(&self.client, url, notification).await,
+            notifications => post(&self.client, url, &notifications).await,
+        }
+    }
+}
+
+/// Posts the alerts collected during each window as one summary
+async fn flush_batches(client: reqwest::Client, url: String, batch: Batch) {
+    loop {
+        tokio::time::sleep(batch.window).await;
+        post_batch(&client, &url, &batch).await;
+    }
+}
+
+/// Posts the pending alerts of `batch` as one summary, duplicates collapsed
+/// into a count
+async fn post_batch(client: &reqwest::Client, url: &str, batch: &Batch) {
+    let alerts = std::mem::take(&mut *batch.alerts.lock().unwrap());
+    if alerts.is_empty() {
+        return;
+    }
+
+    let total: usize = alerts.values().sum();
+    let mut text = format!("{} alerts in the last {}s:", total, batch.window.as_secs());
+    for (message, count) in &alerts {
+        text.push_str(&format!("\n- ({}x) {}", count, message));
+    }
+    post(client, url, &json!({ "text": text })).await;
+}
+
+async fn post(client: &reqwest::Client, url: &str, payload: &impl Serialize) {
+    let result = client
+        .post(url)
+        .json(payload)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status());
+    if let Err(err) = result {
+        println!("Failed to deliver webhook to {}: {}", url, err);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    /// Local webhook answering every POST with 200, returning its URL and
+    /// the JSON bodies it received
+    pub(crate) async fn webhook() -> (String, mpsc::UnboundedReceiver<serde_json::Value>) {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = Vec::new();
+                let mut buf = [0u8; 4096];
+                // 读到请求头结束，再按 Content-Length 读完请求体
+                let body = loop {
+                    let len = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..len]);
+                    let text = String::from_utf8_lossy(&request).to_string();
+                    if let Some(end) = text.find("\r\n\r\n") {
+                        let length: usize = text[..end]
+                            .lines()
+                            .find_map(|line| {
+                                let (name, value) = line.split_once(':')?;
+                                name.eq_ignore_ascii_case("content-length")
+                                    .then(|| value.trim().parse().unwrap())
+                            })
+                            .unwrap_or(0);
+                        if request.len() >= end + 4 + length {
+                            break request[end + 4..end + 4 + length].to_vec();
+                        }
+                    }
+                };
+                stream
+                    .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+                    .await
+                    .unwrap();
+                let _ = sender.send(serde_json::from_slice(&body).unwrap());
+            }
+        });
+        (url, receiver)
+    }
+
+    fn notification(nonce: u64) -> RelayNotification {
+        RelayNotification {
+            nonce,
+            signature: "sig".to_string(),
+            amount: 3000,
+            destination: "dest".to_string(),
+            slot: Some(42),
+        }
+    }
+
+    #[tokio::test]
+    async fn posts_single_relays_as_objects_and_batches_as_arrays() {
+        let (url, mut received) = webhook().await;
+        let alerter = Alerter::new(None, Some(url), None);
+        assert!(alerter.notifies_success());
+
+        alerter.notify_success(&[notification(7)]).await;
+        assert_eq!(
+            received.recv().await.unwrap(),
+            json!({
+                "nonce": 7,
+                "signature": "sig",
+                "amount": 3000,
+                "destination": "dest",
+                "slot": 42,
+            })
+        );
+
+        alerter.notify_success(&[]).await;
+        alerter
+            .notify_success(&[notification(8), notification(9)])
+            .await;
+        let batch = received.recv().await.unwrap();
+        assert_eq!(batch.as_array().unwrap().len(), 2);
+        assert_eq!(batch[0]["nonce"], 8);
+        assert_eq!(batch[1]["nonce"], 9);
+    }
+
+    #[tokio::test]
+    async fn batches_alerts_by_message_and_sends_critical_ones_at_once() {
+        let (url, mut received) = webhook().await;
+        let alerter = Alerter::new(Some(url), None, Some(Duration::from_secs(3600)));
+
+        alerter.send("rpc down").await;
+        alerter.send("rpc down").await;
+        alerter.send("nonce stuck").await;
+        alerter.send_critical("circuit breaker tripped").await;
+        assert_eq!(
+            received.recv().await.unwrap(),
+            json!({ "text": "circuit breaker tripped" })
+        );
+
+        alerter.flush().await;
+        assert_eq!(
+            received.recv().await.unwrap(),
+            json!({ "text": "3 alerts in the last 3600s:\n- (1x) nonce stuck\n- (2x) rpc down" })
+        );
+        // 已发送的告警不会重复发送
+        alerter.flush().await;
+        alerter.send_critical("done").await;
+        assert_eq!(received.recv().await.unwrap(), json!({ "text": "done" }));
+    }
+
+    #[tokio::test]
+    async fn flushes_each_batch_window_in_the_background() {
+        let (url, mut received) = webhook().await;
+        let alerter = Alerter::new(Some(url), None, Some(Duration::from_millis(50)));
+
+        alerter.send("rpc down").await;
+        let summary = tokio::time::timeout(Duration::from_secs(5), received.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(summary["text"]
+            .as_str()
+            .unwrap()
+            .ends_with("- (1x) rpc down"));
+    }
+}
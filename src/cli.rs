@@ -0,0 +1,164 @@
+//! Command line parsing for the relayer binary.
+
+use anyhow::Result;
+use std::path::PathBuf;
+
+/// Parsed command line
+pub struct Args {
+    /// Config file given with `--config <path>`
+    pub config_path: Option<PathBuf>,
+    pub command: Command,
+}
+
+impl Args {
+    /// Parses the process arguments, excluding the binary name. `--config`
+    /// may appear anywhere on the command line
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self> {
+        let mut config_path = None;
+        let mut rest = Vec::new();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if arg == "--config" {
+                let path = args
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("Usage: --config <path>"))?;
+                config_path = Some(PathBuf::from(path));
+            } else {
+                rest.push(arg);
+            }
+        }
+
+        Ok(Self {
+            config_path,
+            command: Command::parse(rest)?,
+        })
+    }
+}
+
+/// Subcommand selected on the command line
+pub enum Command {
+    /// Monitor the watched account and relay messages (default)
+    Run,
+    /// Record a nonce as permanently skipped in the persisted state, of the
+    /// named nonce stream when `nonce_streams` is configured
+    SkipNonce { nonce: u64, stream: Option<String> },
+    /// Validate the config and check the configured programs are deployed
+    VerifyConfig,
+    /// Lift an emergency pause recorded in the persisted state, of the named
+    /// nonce stream when `nonce_streams` is configured
+    Resume { stream: Option<String> },
+    /// Simulate every pending nonce and report which would succeed
+    SimulateBacklog,
+    /// Build and sign relays for nonces `from..to` into a file without submitting
+    BuildAndSign { from: u64, to: u64, out: PathBuf },
+    /// Submit the relays in a file written by `build-and-sign`
+    SubmitSigned { path: PathBuf },
+}
+
+impl Command {
+    /// Parses the process arguments, excluding the binary name
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self> {
+        let mut args = args.into_iter();
+
+        match args.next().as_deref() {
+            None | Some("run") => Ok(Self::Run),
+            Some("skip-nonce") => {
+                const USAGE: &str = "Usage: skip-nonce --nonce <n> [--stream <name>]";
+                let (mut nonce, mut stream) = (None, None);
+                while let Some(flag) = args.next() {
+                    let value = args.next().ok_or_else(|| anyhow::anyhow!(USAGE))?;
+                    match flag.as_str() {
+                        "--nonce" => nonce = Some(parse_nonce(&value)?),
+                        "--stream" => stream = Some(value),
+                        _ => return Err(anyhow::anyhow!(USAGE)),
+                    }
+                }
+                let nonce = nonce.ok_or_else(|| anyhow::anyhow!(USAGE))?;
+                Ok(Self::SkipNonce { nonce, stream })
+            }
+            Some("verify-config") => Ok(Self::VerifyConfig),
+            Some("resume") => match (args.next().as_deref(), args.next()) {
+                (None, _) => Ok(Self::Resume { stream: None }),
+                (Some("--stream"), Some(stream)) => Ok(Self::Resume {
+                    stream: Some(stream),
+                }),
+                _ => Err(anyhow::anyhow!("Usage: resume [--stream <name>]")),
+            },
+            Some("simulate-backlog") => Ok(Self::SimulateBacklog),
+            Some("build-and-sign") => {
+                const USAGE: &str = "Usage: build-and-sign --from <n> --to <n> --out <path>";
+                let (mut from, mut to, mut out) = (None, None, None);
+                while let Some(flag) = args.next() {
+                    let value = args.next().ok_or_else(|| anyhow::anyhow!(USAGE))?;
+                    match flag.as_str() {
+                        "--from" => from = Some(parse_nonce(&value)?),
+                        "--to" => to = Some(parse_nonce(&value)?),
+                        "--out" => out = Some(PathBuf::from(value)),
+                        _ => return Err(anyhow::anyhow!(USAGE)),
+                    }
+                }
+                match (from, to, out) {
+                    (Some(from), Some(to), Some(out)) if from < to => {
+                        Ok(Self::BuildAndSign { from, to, out })
+                    }
+                    _ => Err(anyhow::anyhow!(USAGE)),
+                }
+            }
+            Some("submit-signed") => match args.next() {
+                Some(path) => Ok(Self::SubmitSigned {
+                    path: PathBuf::from(path),
+                }),
+                None => Err(anyhow::anyhow!("Usage: submit-signed <file>")),
+            },
+            Some(other) => Err(anyhow::anyhow!("Unknown command: {}", other)),
+        }
+    }
+}
+
+fn parse_nonce(value: &str) -> Result<u64> {
+    value
+        .parse()
+        .map_err(|e| anyhow::anyhow!("Invalid nonce '{}': {}", value, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command> {
+        Command::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parses_skip_nonce_with_optional_stream() {
+        assert!(matches!(
+            parse(&["skip-nonce", "--nonce", "7"]).unwrap(),
+            Command::SkipNonce {
+                nonce: 7,
+                stream: None
+            }
+        ));
+        match parse(&["skip-nonce", "--stream", "usdc", "--nonce", "7"]).unwrap() {
+            Command::SkipNonce { nonce, stream } => {
+                assert_eq!((nonce, stream.as_deref()), (7, Some("usdc")))
+            }
+            _ => panic!("expected skip-nonce"),
+        }
+        assert!(parse(&["skip-nonce", "--stream", "usdc"]).is_err());
+        assert!(parse(&["skip-nonce", "--nonce"]).is_err());
+    }
+
+    #[test]
+    fn parses_resume_with_optional_stream() {
+        assert!(matches!(
+            parse(&["resume"]).unwrap(),
+            Command::Resume { stream: None }
+        ));
+        match parse(&["resume", "--stream", "usdc"]).unwrap() {
+            Command::Resume { stream } => assert_eq!(stream.as_deref(), Some("usdc")),
+            _ => panic!("expected resume"),
+        }
+        assert!(parse(&["resume", "--stream"]).is_err());
+        assert!(parse(&["resume", "--nonce", "7"]).is_err());
+    }
+}
@@ -0,0 +1,82 @@
+//! Deadman switch for the monitor loop.
+//! The loop records a heartbeat after every cycle, relayed nonce, startup
+//! retry and confirmation poll; a watchdog task exits the process once none arrives in time,
+//! turning a silent hang into a crash the orchestrator restarts.
+
+use crate::alert::Alerter;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::{self, Instant};
+
+/// Time of the last sign of progress, shared with the watchdog
+#[derive(Debug, Clone)]
+pub struct Heartbeat(Arc<Mutex<Instant>>);
+
+impl Heartbeat {
+    pub fn new() -> Self {
+        Self(Arc::new(Mutex::new(Instant::now())))
+    }
+
+    /// Records progress: a completed cycle, relayed nonce, startup retry or
+    /// a relay still awaiting confirmation
+    pub fn beat(&self) {
+        *self.0.lock().unwrap() = Instant::now();
+    }
+
+    /// Time since the last recorded progress
+    pub fn elapsed(&self) -> Duration {
+        self.0.lock().unwrap().elapsed()
+    }
+}
+
+/// Exits the process once `heartbeat` goes `timeout` without a beat,
+/// alerting first
+pub async fn watch(heartbeat: Heartbeat, timeout: Duration, alerter: Alerter) {
+    let stalled_for = stalled(&heartbeat, timeout).await;
+    let message = format!(
+        "FATAL: no relay progress in {:?}, exiting so the relayer gets restarted",
+        stalled_for
+    );
+    alerter.send_critical(&message).await;
+    std::process::exit(1);
+}
+
+/// Resolves once `heartbeat` goes `timeout` without a beat, with the time
+/// since the last one
+async fn stalled(heartbeat: &Heartbeat, timeout: Duration) -> Duration {
+    // 按超时的一部分轮询，超时后最多延迟这么久才触发
+    let mut interval = time::interval((timeout / 10).max(Duration::from_secs(1)));
+    loop {
+        interval.tick().await;
+        let stalled_for = heartbeat.elapsed();
+        if stalled_for >= timeout {
+            return stalled_for;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn trips_once_no_beat_arrives_in_time() {
+        let heartbeat = Heartbeat::new();
+        let timeout = Duration::from_secs(120);
+        let started = Instant::now();
+
+        // 前半段持续有进展，之后卡住
+        let beating = heartbeat.clone();
+        tokio::spawn(async move {
+            for _ in 0..6 {
+                time::sleep(Duration::from_secs(10)).await;
+                beating.beat();
+            }
+        });
+        let stalled_for = stalled(&heartbeat, timeout).await;
+
+        assert!(stalled_for >= timeout);
+        assert!(started.elapsed() >= Duration::from_secs(60) + timeout);
+        assert!(started.elapsed() < Duration::from_secs(60) + timeout + timeout / 10);
+    }
+}
@@ -0,0 +1,76 @@
+//! Per-nonce decision trace for post-incident audits.
+//! With `decision_trace` set, every nonce the relayer considers produces one
+//! JSON line on stdout recording what was read, which policies were
+//! consulted and why the nonce was relayed, skipped or failed.
+
+use crate::ndjson;
+use serde::Serialize;
+
+/// Final action taken for a nonce
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Relayed,
+    Skipped,
+    Failed,
+    /// Left for a later cycle, e.g. while a previous relay is unconfirmed
+    Deferred,
+}
+
+/// Decision path of one nonce, serialized as a single JSON line:
+///
+/// `{"type":"decision","nonce":7,"pda":"4Zk..","pda_exists":true,"amount":1000,"destination":"9xQ..","policies":["skip_set","zero_amount_policy"],"action":"relayed","reason":null,"signature":"5Vf.."}`
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct Decision {
+    /// Event type, always `decision`
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub nonce: u64,
+    pub pda: Option<String>,
+    pub pda_exists: Option<bool>,
+    pub amount: Option<u64>,
+    pub destination: Option<String>,
+    /// Policies consulted, in order
+    pub policies: Vec<&'static str>,
+    pub action: Action,
+    pub reason: Option<String>,
+    /// Relay transaction, for relayed nonces
+    pub signature: Option<String>,
+}
+
+impl Decision {
+    pub fn new(nonce: u64) -> Self {
+        Self {
+            kind: "decision",
+            nonce,
+            pda: None,
+            pda_exists: None,
+            amount: None,
+            destination: None,
+            policies: Vec::new(),
+            action: Action::Deferred,
+            reason: None,
+            signature: None,
+        }
+    }
+
+    /// Records the final action, keeping the first one set
+    pub fn decide(&mut self, action: Action, reason: impl Into<String>) {
+        if self.action == Action::Deferred && self.reason.is_none() {
+            self.action = action;
+            self.reason = Some(reason.into());
+        }
+    }
+
+    /// Writes the decision to stdout as one JSON line
+    pub fn emit(&self) {
+        match serde_json::to_string(self) {
+            Ok(line) => {
+                if let Err(err) = ndjson::write_line(&line) {
+                    println!("Warning: failed to write decision trace: {}", err);
+                }
+            }
+            Err(err) => println!("Warning: failed to serialize decision trace: {}", err),
+        }
+    }
+}
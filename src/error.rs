@@ -0,0 +1,221 @@
+//! Relayer error classification.
+//! Maps RPC client errors onto categories that drive retries and operator hints.
+
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind},
+    rpc_custom_error::{
+        JSON_RPC_SERVER_ERROR_MIN_CONTEXT_SLOT_NOT_REACHED, JSON_RPC_SERVER_ERROR_NODE_UNHEALTHY,
+    },
+    rpc_request::RpcError,
+};
+use solana_sdk::transaction::TransactionError;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum RelayerError {
+    BlockhashNotFound(String),
+    AccountNotFound(String),
+    NodeBehind(String),
+    RateLimited(String),
+    InsufficientFunds(String),
+    /// The transaction executed and failed on chain
+    Transaction(String),
+    Rpc(String),
+}
+
+impl RelayerError {
+    /// Classifies an RPC client error by its transaction error, JSON-RPC code or message
+    pub fn classify(err: &ClientError) -> Self {
+        let message = err.to_string();
+
+        match err.get_transaction_error() {
+            Some(TransactionError::BlockhashNotFound) => return Self::BlockhashNotFound(message),
+            Some(TransactionError::InsufficientFundsForFee)
+            | Some(TransactionError::InsufficientFundsForRent { .. }) => {
+                return Self::InsufficientFunds(message)
+            }
+            Some(TransactionError::AccountNotFound) => return Self::AccountNotFound(message),
+            Some(_) => return Self::Transaction(message),
+            None => {}
+        }
+
+        match err.kind() {
+            ClientErrorKind::RpcError(RpcError::RpcResponseError { code, .. })
+                if *code == JSON_RPC_SERVER_ERROR_NODE_UNHEALTHY
+                    || *code == JSON_RPC_SERVER_ERROR_MIN_CONTEXT_SLOT_NOT_REACHED =>
+            {
+                return Self::NodeBehind(message)
+            }
+            ClientErrorKind::Reqwest(reqwest_err)
+                if reqwest_err.status() == Some(reqwest::StatusCode::TOO_MANY_REQUESTS) =>
+            {
+                return Self::RateLimited(message)
+            }
+            _ => {}
+        }
+
+        let lowercase = message.to_lowercase();
+        if lowercase.contains("blockhash not found") {
+            Self::BlockhashNotFound(message)
+        } else if lowercase.contains("accountnotfound")
+            || lowercase.contains("could not find account")
+        {
+            Self::AccountNotFound(message)
+        } else if lowercase.contains("node is behind") || lowercase.contains("node is unhealthy") {
+            Self::NodeBehind(message)
+        } else if lowercase.contains("429") || lowercase.contains("too many requests") {
+            Self::RateLimited(message)
+        } else if lowercase.contains("insufficient funds")
+            || lowercase.contains("insufficient lamports")
+        {
+            Self::InsufficientFunds(message)
+        } else {
+            Self::Rpc(message)
+        }
+    }
+
+    /// Whether the same request is worth retrying on a later cycle
+    pub fn is_retryable(&self) -> bool {
+        !matches!(
+            self,
+            Self::AccountNotFound(_) | Self::InsufficientFunds(_) | Self::Transaction(_)
+        )
+    }
+
+    /// Operator-facing suggestion for resolving the error
+    pub fn hint(&self) -> &'static str {
+        match self {
+            Self::BlockhashNotFound(_) => {
+                "the blockhash expired or the node is lagging, the transaction will be rebuilt"
+            }
+            Self::AccountNotFound(_) => {
+                "check the configured account addresses and that they exist on this cluster"
+            }
+            Self::NodeBehind(_) => "the RPC node is behind the cluster, consider another endpoint",
+            Self::RateLimited(_) => {
+                "the RPC provider is rate limiting requests, lower the request rate or upgrade the plan"
+            }
+            Self::InsufficientFunds(_) => "fund the relayer wallet with enough SOL for fees",
+            Self::Transaction(_) => "the L2 program rejected the transaction, inspect its logs",
+            Self::Rpc(_) => "check RPC endpoint connectivity",
+        }
+    }
+
+    fn message(&self) -> &str {
+        match self {
+            Self::BlockhashNotFound(message)
+            | Self::AccountNotFound(message)
+            | Self::NodeBehind(message)
+            | Self::RateLimited(message)
+            | Self::InsufficientFunds(message)
+            | Self::Transaction(message)
+            | Self::Rpc(message) => message,
+        }
+    }
+}
+
+impl fmt::Display for RelayerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (hint: {})", self.message(), self.hint())
+    }
+}
+
+impl std::error::Error for RelayerError {}
+
+impl From<ClientError> for RelayerError {
+    fn from(err: ClientError) -> Self {
+        Self::classify(&err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_client::rpc_request::RpcResponseErrorData;
+
+    fn variant(err: &RelayerError) -> &'static str {
+        match err {
+            RelayerError::BlockhashNotFound(_) => "BlockhashNotFound",
+            RelayerError::AccountNotFound(_) => "AccountNotFound",
+            RelayerError::NodeBehind(_) => "NodeBehind",
+            RelayerError::RateLimited(_) => "RateLimited",
+            RelayerError::InsufficientFunds(_) => "InsufficientFunds",
+            RelayerError::Transaction(_) => "Transaction",
+            RelayerError::Rpc(_) => "Rpc",
+        }
+    }
+
+    fn response_error(code: i64) -> ClientError {
+        ClientErrorKind::RpcError(RpcError::RpcResponseError {
+            code,
+            message: "server error".to_string(),
+            data: RpcResponseErrorData::Empty,
+        })
+        .into()
+    }
+
+    fn custom(message: &str) -> ClientError {
+        ClientErrorKind::Custom(message.to_string()).into()
+    }
+
+    #[test]
+    fn classifies_client_errors() {
+        let cases: Vec<(ClientError, &str, bool)> = vec![
+            (
+                TransactionError::BlockhashNotFound.into(),
+                "BlockhashNotFound",
+                true,
+            ),
+            (
+                TransactionError::InsufficientFundsForFee.into(),
+                "InsufficientFunds",
+                false,
+            ),
+            (
+                TransactionError::InsufficientFundsForRent { account_index: 0 }.into(),
+                "InsufficientFunds",
+                false,
+            ),
+            (
+                TransactionError::AccountNotFound.into(),
+                "AccountNotFound",
+                false,
+            ),
+            (TransactionError::AccountInUse.into(), "Transaction", false),
+            (
+                response_error(JSON_RPC_SERVER_ERROR_NODE_UNHEALTHY),
+                "NodeBehind",
+                true,
+            ),
+            (
+                response_error(JSON_RPC_SERVER_ERROR_MIN_CONTEXT_SLOT_NOT_REACHED),
+                "NodeBehind",
+                true,
+            ),
+            (custom("Blockhash not found"), "BlockhashNotFound", true),
+            (custom("could not find account"), "AccountNotFound", false),
+            (custom("Node is behind by 120 slots"), "NodeBehind", true),
+            (
+                custom("HTTP status client error (429 Too Many Requests)"),
+                "RateLimited",
+                true,
+            ),
+            (
+                custom("insufficient lamports 10, need 5000"),
+                "InsufficientFunds",
+                false,
+            ),
+            (custom("connection refused"), "Rpc", true),
+        ];
+
+        for (err, expected, retryable) in cases {
+            let classified = RelayerError::classify(&err);
+            assert_eq!(variant(&classified), expected, "{}", err);
+            assert_eq!(classified.is_retryable(), retryable, "{}", err);
+            assert_eq!(
+                classified.to_string(),
+                format!("{} (hint: {})", err, classified.hint())
+            );
+        }
+    }
+}
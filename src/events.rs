@@ -0,0 +1,139 @@
+//! Relay event stream for indexers.
+//! Each confirmed relay is written as one line of geyser-style JSON, so
+//! downstream pipelines can consume relay activity without parsing logs.
+
+use crate::config::{EventSinkKind, RelayerConfig};
+use crate::ndjson;
+use anyhow::Result;
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+
+/// One confirmed relay, serialized as a single JSON line:
+///
+/// `{"type":"relay","slot":123,"signature":"5Vf..","transfer":{"nonce":7,"amount":1000,"destination":"9xQ.."}}`
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct RelayEvent {
+    /// Event type, always `relay`
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    /// Slot the relay transaction landed in, if it could be looked up
+    pub slot: Option<u64>,
+    pub signature: String,
+    pub transfer: TransferEvent,
+}
+
+/// Decoded transfer carried by a relay transaction
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct TransferEvent {
+    /// L1 message nonce, the highest one for aggregated relays
+    pub nonce: u64,
+    pub amount: u64,
+    pub destination: String,
+}
+
+impl RelayEvent {
+    pub fn new(
+        slot: Option<u64>,
+        signature: String,
+        nonce: u64,
+        amount: u64,
+        destination: String,
+    ) -> Self {
+        Self {
+            kind: "relay",
+            slot,
+            signature,
+            transfer: TransferEvent {
+                nonce,
+                amount,
+                destination,
+            },
+        }
+    }
+}
+
+/// Destination of the relay event stream
+pub enum EventSink {
+    Stdout,
+    File(File),
+}
+
+impl EventSink {
+    /// Opens the sink selected by `event_sink`, `None` if disabled
+    pub fn from_config(config: &RelayerConfig) -> Result<Option<Self>> {
+        match config.event_sink {
+            EventSinkKind::Disabled => Ok(None),
+            EventSinkKind::Stdout => Ok(Some(EventSink::Stdout)),
+            EventSinkKind::File => {
+                let path = config.event_sink_path.as_deref().ok_or_else(|| {
+                    anyhow::anyhow!("event_sink = \"file\" requires event_sink_path")
+                })?;
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|e| anyhow::anyhow!("Failed to open event sink {}: {}", path, e))?;
+                Ok(Some(EventSink::File(file)))
+            }
+        }
+    }
+
+    /// Writes the event as one JSON line
+    pub fn emit(&mut self, event: &RelayEvent) -> Result<()> {
+        let line = serde_json::to_string(event)?;
+        match self {
+            EventSink::Stdout => ndjson::write_line(&line)?,
+            EventSink::File(file) => writeln!(file, "{}", line)?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serializes_relays_in_the_documented_schema() {
+        let event = RelayEvent::new(Some(123), "5Vf".to_string(), 7, 1000, "9xQ".to_string());
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"type":"relay","slot":123,"signature":"5Vf","transfer":{"nonce":7,"amount":1000,"destination":"9xQ"}}"#
+        );
+
+        let unknown_slot = RelayEvent::new(None, "5Vf".to_string(), 7, 1000, "9xQ".to_string());
+        assert_eq!(
+            serde_json::to_value(&unknown_slot).unwrap()["slot"],
+            serde_json::Value::Null
+        );
+    }
+
+    #[test]
+    fn file_sink_appends_one_line_per_relay() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.jsonl");
+        std::fs::write(&path, "{\"type\":\"relay\"}\n").unwrap();
+        let mut sink = EventSink::File(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .unwrap(),
+        );
+
+        for nonce in [7, 8] {
+            let event = RelayEvent::new(Some(1), "sig".to_string(), nonce, 5, "to".to_string());
+            sink.emit(&event).unwrap();
+        }
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = written
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[1]["transfer"]["nonce"], 7);
+        assert_eq!(lines[2]["transfer"]["nonce"], 8);
+    }
+}
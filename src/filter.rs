@@ -0,0 +1,169 @@
+//! Relay filter for selective bridging.
+//! Built from the `relay_filter` config table; transfers outside it are
+//! skipped, e.g. to bridge only some destinations during a phased rollout.
+
+use crate::config::{NonceParity, RelayFilterConfig};
+use crate::models::message::TransferInfo;
+use anyhow::Result;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashSet;
+use std::str::FromStr;
+
+/// Compiled `relay_filter`, every configured dimension must match
+#[derive(Debug, Clone)]
+pub struct RelayFilter {
+    min_amount: Option<u64>,
+    max_amount: Option<u64>,
+    destinations: Option<HashSet<Pubkey>>,
+    nonce_parity: Option<NonceParity>,
+    min_nonce: Option<u64>,
+    max_nonce: Option<u64>,
+    /// Move the cursor past filtered nonces instead of revisiting them
+    pub advance_past: bool,
+}
+
+impl RelayFilter {
+    pub fn from_config(config: &RelayFilterConfig) -> Result<Self> {
+        let destinations = if config.destinations.is_empty() {
+            None
+        } else {
+            Some(
+                config
+                    .destinations
+                    .iter()
+                    .map(|destination| {
+                        Pubkey::from_str(destination).map_err(|e| {
+                            anyhow::anyhow!("Invalid filter destination {}: {}", destination, e)
+                        })
+                    })
+                    .collect::<Result<_>>()?,
+            )
+        };
+
+        Ok(Self {
+            min_amount: config.min_amount,
+            max_amount: config.max_amount,
+            destinations,
+            nonce_parity: config.nonce_parity,
+            min_nonce: config.min_nonce,
+            max_nonce: config.max_nonce,
+            advance_past: config.advance_past,
+        })
+    }
+
+    /// The first dimension the transfer falls outside of, `None` if it
+    /// should be relayed
+    pub fn rejects(&self, nonce: u64, transfer_info: &TransferInfo) -> Option<String> {
+        if let Some(min) = self.min_amount.filter(|&min| transfer_info.amount < min) {
+            return Some(format!("amount {} below {}", transfer_info.amount, min));
+        }
+        if let Some(max) = self.max_amount.filter(|&max| transfer_info.amount > max) {
+            return Some(format!("amount {} above {}", transfer_info.amount, max));
+        }
+        // 代币模式下按 PDA 中存储的钱包地址匹配
+        let destination = transfer_info.owner.unwrap_or(transfer_info.to);
+        if self
+            .destinations
+            .as_ref()
+            .is_some_and(|destinations| !destinations.contains(&destination))
+        {
+            return Some(format!("destination {} not allowed", destination));
+        }
+        let even = nonce.is_multiple_of(2);
+        match self.nonce_parity {
+            Some(NonceParity::Even) if !even => return Some("odd nonce".to_string()),
+            Some(NonceParity::Odd) if even => return Some("even nonce".to_string()),
+            _ => {}
+        }
+        if self.min_nonce.is_some_and(|min| nonce < min)
+            || self.max_nonce.is_some_and(|max| nonce > max)
+        {
+            return Some("nonce outside the allowed range".to_string());
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(config: serde_json::Value) -> RelayFilter {
+        RelayFilter::from_config(&serde_json::from_value(config).unwrap()).unwrap()
+    }
+
+    fn transfer(amount: u64, to: Pubkey) -> TransferInfo {
+        TransferInfo {
+            amount,
+            to,
+            selector: None,
+            owner: None,
+        }
+    }
+
+    #[test]
+    fn filters_by_amount() {
+        let filter = filter(serde_json::json!({ "min_amount": 10, "max_amount": 100 }));
+        let to = Pubkey::new_unique();
+        assert_eq!(
+            filter.rejects(1, &transfer(9, to)).as_deref(),
+            Some("amount 9 below 10")
+        );
+        assert_eq!(filter.rejects(1, &transfer(10, to)), None);
+        assert_eq!(filter.rejects(1, &transfer(100, to)), None);
+        assert_eq!(
+            filter.rejects(1, &transfer(101, to)).as_deref(),
+            Some("amount 101 above 100")
+        );
+    }
+
+    #[test]
+    fn filters_by_destination() {
+        let (allowed, other) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let filter = filter(serde_json::json!({ "destinations": [allowed.to_string()] }));
+        assert_eq!(filter.rejects(1, &transfer(5, allowed)), None);
+        assert_eq!(
+            filter.rejects(1, &transfer(5, other)),
+            Some(format!("destination {} not allowed", other))
+        );
+
+        // 代币模式下按钱包地址匹配，而不是关联代币账户
+        let token_transfer = TransferInfo {
+            owner: Some(allowed),
+            ..transfer(5, other)
+        };
+        assert_eq!(filter.rejects(1, &token_transfer), None);
+
+        let config = serde_json::from_value(serde_json::json!({ "destinations": ["nope"] }));
+        assert!(RelayFilter::from_config(&config.unwrap()).is_err());
+    }
+
+    #[test]
+    fn filters_by_nonce_parity_and_range() {
+        let to = Pubkey::new_unique();
+        let even = filter(serde_json::json!({ "nonce_parity": "even" }));
+        assert_eq!(even.rejects(4, &transfer(5, to)), None);
+        assert_eq!(
+            even.rejects(5, &transfer(5, to)).as_deref(),
+            Some("odd nonce")
+        );
+        let odd = filter(serde_json::json!({ "nonce_parity": "odd" }));
+        assert_eq!(odd.rejects(5, &transfer(5, to)), None);
+        assert_eq!(
+            odd.rejects(4, &transfer(5, to)).as_deref(),
+            Some("even nonce")
+        );
+
+        let range = filter(serde_json::json!({ "min_nonce": 10, "max_nonce": 20 }));
+        for (nonce, allowed) in [(9, false), (10, true), (20, true), (21, false)] {
+            assert_eq!(range.rejects(nonce, &transfer(5, to)).is_none(), allowed);
+        }
+    }
+
+    #[test]
+    fn empty_filter_relays_everything() {
+        let filter = filter(serde_json::json!({}));
+        assert_eq!(filter.rejects(3, &transfer(0, Pubkey::new_unique())), None);
+        assert!(!filter.advance_past);
+    }
+}
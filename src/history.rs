@@ -0,0 +1,136 @@
+//! SQLite relay history.
+//! With `sqlite_path` set, each confirmed relay is written as a row of the
+//! `relays` table, giving operators queryable history without Prometheus.
+
+use crate::events::RelayEvent;
+use anyhow::Result;
+use rusqlite::{params, Connection};
+
+/// Relay outcome recorded in the `status` column once a relay confirms
+pub const STATUS_CONFIRMED: &str = "confirmed";
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS relays (
+        id INTEGER PRIMARY KEY,
+        nonce INTEGER NOT NULL,
+        signature TEXT NOT NULL,
+        amount INTEGER NOT NULL,
+        destination TEXT NOT NULL,
+        slot INTEGER,
+        status TEXT NOT NULL,
+        timestamp INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS relays_nonce ON relays (nonce);
+";
+
+/// Open relay history database
+pub struct RelayHistory {
+    connection: Connection,
+}
+
+impl RelayHistory {
+    /// Opens the database at `path`, creating the file and schema if absent
+    pub fn open(path: &str) -> Result<Self> {
+        let connection = Connection::open(path)
+            .map_err(|e| anyhow::anyhow!("Failed to open relay history {}: {}", path, e))?;
+        connection
+            .execute_batch(SCHEMA)
+            .map_err(|e| anyhow::anyhow!("Failed to create relay history schema: {}", e))?;
+        Ok(Self { connection })
+    }
+
+    /// Writes one relay outcome, `timestamp` in Unix seconds
+    pub fn record(&self, event: &RelayEvent, status: &str, timestamp: i64) -> Result<()> {
+        // SQLite 整数为 i64，超出范围的 u64 按位存储
+        self.connection.execute(
+            "INSERT INTO relays (nonce, signature, amount, destination, slot, status, timestamp)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                event.transfer.nonce as i64,
+                event.signature,
+                event.transfer.amount as i64,
+                event.transfer.destination,
+                event.slot.map(|slot| slot as i64),
+                status,
+                timestamp,
+            ],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_relays_queryable_by_nonce() {
+        let history = RelayHistory::open(":memory:").unwrap();
+        let event = RelayEvent::new(Some(123), "5Vf".to_string(), 7, 1000, "9xQ".to_string());
+        history
+            .record(&event, STATUS_CONFIRMED, 1_700_000_000)
+            .unwrap();
+
+        let row = history
+            .connection
+            .query_row(
+                "SELECT nonce, signature, amount, destination, slot, status, timestamp
+                 FROM relays WHERE nonce = ?1",
+                [7],
+                |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, i64>(2)?,
+                        row.get::<_, String>(3)?,
+                        row.get::<_, Option<i64>>(4)?,
+                        row.get::<_, String>(5)?,
+                        row.get::<_, i64>(6)?,
+                    ))
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            row,
+            (
+                7,
+                "5Vf".to_string(),
+                1000,
+                "9xQ".to_string(),
+                Some(123),
+                STATUS_CONFIRMED.to_string(),
+                1_700_000_000
+            )
+        );
+
+        let index: String = history
+            .connection
+            .query_row(
+                "SELECT sql FROM sqlite_master WHERE type = 'index' AND name = 'relays_nonce'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert!(index.contains("(nonce)"));
+    }
+
+    #[test]
+    fn reopening_keeps_existing_rows() {
+        let path = std::env::temp_dir().join(format!("relay-history-{}.db", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+
+        let event = RelayEvent::new(None, "5Vf".to_string(), 7, 1000, "9xQ".to_string());
+        RelayHistory::open(path)
+            .unwrap()
+            .record(&event, STATUS_CONFIRMED, 0)
+            .unwrap();
+        let history = RelayHistory::open(path).unwrap();
+        let count: i64 = history
+            .connection
+            .query_row("SELECT COUNT(*) FROM relays", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 1);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,270 @@
+//! Anchor IDL account layouts.
+//! Derives field offsets and the account discriminator from an IDL so the
+//! relayer doesn't depend on hardcoded offsets.
+
+use crate::config::Endianness;
+use crate::models::message::read_bytes;
+use anyhow::Result;
+use serde::Deserialize;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+#[derive(Deserialize)]
+struct Idl {
+    #[serde(default)]
+    accounts: Vec<IdlAccount>,
+    #[serde(default)]
+    types: Vec<IdlTypeDef>,
+}
+
+#[derive(Deserialize)]
+struct IdlAccount {
+    name: String,
+    #[serde(default)]
+    discriminator: Option<Vec<u8>>,
+    /// Older IDLs inline the account type here instead of under `types`
+    #[serde(rename = "type", default)]
+    ty: Option<IdlTypeBody>,
+}
+
+#[derive(Deserialize)]
+struct IdlTypeDef {
+    name: String,
+    #[serde(rename = "type")]
+    ty: IdlTypeBody,
+}
+
+#[derive(Deserialize, Clone)]
+struct IdlTypeBody {
+    kind: String,
+    #[serde(default)]
+    fields: Vec<IdlField>,
+}
+
+#[derive(Deserialize, Clone)]
+struct IdlField {
+    name: String,
+    #[serde(rename = "type")]
+    ty: Value,
+}
+
+/// Where the nonce fields live inside the L2 nonce account
+#[derive(Debug, Clone)]
+pub struct NonceLayout {
+    /// Expected account discriminator, if known
+    pub discriminator: Option<[u8; 8]>,
+    /// Offset of the `l1_nonce` field, the relayed cursor
+    pub l1_nonce_offset: usize,
+    /// Offset of the `l2_nonce` field
+    pub l2_nonce_offset: usize,
+    /// Byte order of both nonce fields
+    pub endianness: Endianness,
+}
+
+impl Default for NonceLayout {
+    fn default() -> Self {
+        Self {
+            discriminator: None,
+            l1_nonce_offset: 8,
+            l2_nonce_offset: 16,
+            endianness: Endianness::Le,
+        }
+    }
+}
+
+impl NonceLayout {
+    /// Shortest account data that holds both nonce fields
+    pub fn min_len(&self) -> usize {
+        self.l1_nonce_offset.max(self.l2_nonce_offset) + 8
+    }
+
+    /// Reads the relayed cursor from nonce account data
+    pub fn read_l1_nonce(&self, data: &[u8]) -> Result<u64> {
+        if data.len() < self.min_len() {
+            return Err(anyhow::anyhow!(
+                "Invalid nonce account data length: expected at least {} bytes, got {}",
+                self.min_len(),
+                data.len()
+            ));
+        }
+
+        Ok(self
+            .endianness
+            .read_u64(read_bytes(data, self.l1_nonce_offset)?))
+    }
+
+    /// Locates the `l1_nonce` and `l2_nonce` fields of `account_name` in an Anchor IDL file
+    pub fn from_idl<P: AsRef<Path>>(path: P, account_name: &str) -> Result<Self> {
+        let path = path.as_ref();
+        let data = fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read IDL {}: {}", path.display(), e))?;
+        let idl: Idl = serde_json::from_str(&data)
+            .map_err(|e| anyhow::anyhow!("Invalid IDL {}: {}", path.display(), e))?;
+
+        let account = idl
+            .accounts
+            .iter()
+            .find(|account| account.name == account_name)
+            .ok_or_else(|| anyhow::anyhow!("Account {} not found in IDL", account_name))?;
+        let discriminator = match &account.discriminator {
+            Some(bytes) => bytes.as_slice().try_into().map_err(|_| {
+                anyhow::anyhow!("Account {} has an invalid discriminator", account_name)
+            })?,
+            // 旧版 IDL 不包含 discriminator，按 Anchor 规则计算
+            None => {
+                let hash = solana_sdk::hash::hash(format!("account:{}", account_name).as_bytes());
+                let mut discriminator = [0u8; 8];
+                discriminator.copy_from_slice(&hash.to_bytes()[..8]);
+                discriminator
+            }
+        };
+
+        let body = match &account.ty {
+            Some(body) => body.clone(),
+            None => find_type(&idl, account_name)?.clone(),
+        };
+        let offsets = field_offsets(&idl, &body, account_name)?;
+        let offset_of = |field: &str| {
+            offsets
+                .iter()
+                .find(|(name, _)| name == field)
+                .and_then(|(_, offset)| *offset)
+                .ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "Account {} has no fixed-offset {} field",
+                        account_name,
+                        field
+                    )
+                })
+        };
+
+        Ok(Self {
+            discriminator: Some(discriminator),
+            l1_nonce_offset: offset_of("l1_nonce")?,
+            l2_nonce_offset: offset_of("l2_nonce")?,
+            endianness: Endianness::Le,
+        })
+    }
+}
+
+fn find_type<'a>(idl: &'a Idl, name: &str) -> Result<&'a IdlTypeBody> {
+    idl.types
+        .iter()
+        .find(|def| def.name == name)
+        .map(|def| &def.ty)
+        .ok_or_else(|| anyhow::anyhow!("Type {} not found in IDL", name))
+}
+
+/// Offsets of each struct field after the 8-byte discriminator, `None` once a
+/// variable-length field makes later offsets data dependent
+fn field_offsets(
+    idl: &Idl,
+    body: &IdlTypeBody,
+    name: &str,
+) -> Result<Vec<(String, Option<usize>)>> {
+    if body.kind != "struct" {
+        return Err(anyhow::anyhow!("Account {} is not a struct", name));
+    }
+
+    let mut offset = Some(8);
+    let mut offsets = Vec::with_capacity(body.fields.len());
+    for field in &body.fields {
+        offsets.push((field.name.clone(), offset));
+        offset = match (offset, type_size(idl, &field.ty, 0)?) {
+            (Some(offset), Some(size)) => Some(offset + size),
+            _ => None,
+        };
+    }
+    Ok(offsets)
+}
+
+/// Borsh-encoded size of an IDL type, `None` if it varies with the data
+fn type_size(idl: &Idl, ty: &Value, depth: usize) -> Result<Option<usize>> {
+    if depth > 16 {
+        return Err(anyhow::anyhow!("IDL type nesting too deep"));
+    }
+
+    if let Some(primitive) = ty.as_str() {
+        return Ok(match primitive {
+            "bool" | "u8" | "i8" => Some(1),
+            "u16" | "i16" => Some(2),
+            "u32" | "i32" | "f32" => Some(4),
+            "u64" | "i64" | "f64" => Some(8),
+            "u128" | "i128" => Some(16),
+            "pubkey" | "publicKey" => Some(32),
+            "string" | "bytes" => None,
+            other => return Err(anyhow::anyhow!("Unsupported IDL type {}", other)),
+        });
+    }
+
+    if let Some(array) = ty.get("array").and_then(Value::as_array) {
+        let len = array.get(1).and_then(Value::as_u64);
+        return match (array.first(), len) {
+            (Some(inner), Some(len)) => {
+                Ok(type_size(idl, inner, depth + 1)?.map(|size| size * len as usize))
+            }
+            _ => Err(anyhow::anyhow!("Invalid IDL array type {}", ty)),
+        };
+    }
+    if ty.get("vec").is_some() || ty.get("option").is_some() {
+        return Ok(None);
+    }
+    if let Some(defined) = ty.get("defined") {
+        // 新版 IDL 为 {"defined": {"name": ...}}，旧版为 {"defined": "..."}
+        let name = defined
+            .as_str()
+            .or_else(|| defined.get("name").and_then(Value::as_str))
+            .ok_or_else(|| anyhow::anyhow!("Invalid IDL defined type {}", ty))?;
+        let body = find_type(idl, name)?;
+        if body.kind != "struct" {
+            return Ok(None);
+        }
+        let mut size = 0;
+        for field in &body.fields {
+            match type_size(idl, &field.ty, depth + 1)? {
+                Some(field_size) => size += field_size,
+                None => return Ok(None),
+            }
+        }
+        return Ok(Some(size));
+    }
+
+    Err(anyhow::anyhow!("Unsupported IDL type {}", ty))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn length_check_covers_both_nonce_fields() {
+        for (l1_nonce_offset, l2_nonce_offset) in [(8, 16), (24, 8), (9, 9)] {
+            let layout = NonceLayout {
+                l1_nonce_offset,
+                l2_nonce_offset,
+                ..NonceLayout::default()
+            };
+            let mut data = vec![0u8; layout.min_len()];
+            data[l1_nonce_offset..l1_nonce_offset + 8].copy_from_slice(&42u64.to_le_bytes());
+            assert_eq!(layout.read_l1_nonce(&data).unwrap(), 42);
+
+            data.pop();
+            let err = layout.read_l1_nonce(&data).unwrap_err();
+            assert!(err
+                .to_string()
+                .contains("Invalid nonce account data length"));
+        }
+    }
+
+    #[test]
+    fn reads_big_endian_nonces() {
+        let layout = NonceLayout {
+            endianness: Endianness::Be,
+            ..NonceLayout::default()
+        };
+        let mut data = vec![0u8; layout.min_len()];
+        data[8..16].copy_from_slice(&42u64.to_be_bytes());
+        assert_eq!(layout.read_l1_nonce(&data).unwrap(), 42);
+    }
+}
@@ -0,0 +1,189 @@
+//! Anchor events parsed from program logs.
+//! Used by the `program_logs` source mode, where the L1 program emits an
+//! event per message instead of the relayer polling a nonce counter.
+
+use crate::models::message::read_bytes;
+use solana_sdk::pubkey::Pubkey;
+
+const PROGRAM_DATA: &str = "Program data: ";
+
+/// Borsh-encoded size of a message event after its discriminator
+const MESSAGE_EVENT_LEN: usize = 8 + 32 + 8;
+
+/// Message emitted by the L1 program, laid out as
+/// `discriminator | nonce: u64 | to: Pubkey | amount: u64`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageEvent {
+    pub nonce: u64,
+    pub to: Pubkey,
+    pub amount: u64,
+}
+
+/// Anchor event discriminator, the first 8 bytes of `sha256("event:<name>")`
+pub fn event_discriminator(name: &str) -> [u8; 8] {
+    let hash = solana_sdk::hash::hash(format!("event:{}", name).as_bytes());
+    let mut discriminator = [0u8; 8];
+    discriminator.copy_from_slice(&hash.to_bytes()[..8]);
+    discriminator
+}
+
+/// Parses the message events `program_id` emitted in a transaction's logs.
+///
+/// Only `Program data:` lines logged while `program_id` is the innermost
+/// executing program are considered, so another program invoked through
+/// CPI can't forge events.
+pub fn parse_message_events(
+    logs: &[String],
+    program_id: &Pubkey,
+    discriminator: &[u8; 8],
+) -> Vec<MessageEvent> {
+    let program_id = program_id.to_string();
+    let mut call_stack: Vec<&str> = Vec::new();
+    let mut events = Vec::new();
+
+    for log in logs {
+        if let Some(data) = log.strip_prefix(PROGRAM_DATA) {
+            if call_stack.last() == Some(&program_id.as_str()) {
+                if let Some(event) = decode_message_event(data, discriminator) {
+                    events.push(event);
+                }
+            }
+            continue;
+        }
+
+        // 根据 invoke/success/failed 日志跟踪当前执行的程序
+        let mut parts = log.split_whitespace();
+        if let (Some("Program"), Some(id), Some(status)) =
+            (parts.next(), parts.next(), parts.next())
+        {
+            match status {
+                "invoke" => call_stack.push(id),
+                "success" | "failed:" => {
+                    call_stack.pop();
+                }
+                _ => {}
+            }
+        }
+    }
+    events
+}
+
+fn decode_message_event(data: &str, discriminator: &[u8; 8]) -> Option<MessageEvent> {
+    let bytes = base64::decode(data.trim()).ok()?;
+    let body = bytes.strip_prefix(discriminator.as_slice())?;
+    if body.len() < MESSAGE_EVENT_LEN {
+        return None;
+    }
+
+    let nonce = u64::from_le_bytes(read_bytes(body, 0).ok()?);
+    let to = Pubkey::new_from_array(read_bytes(body, 8).ok()?);
+    let amount = u64::from_le_bytes(read_bytes(body, 40).ok()?);
+    Some(MessageEvent { nonce, to, amount })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event_data(discriminator: &[u8; 8], event: &MessageEvent) -> String {
+        let mut bytes = discriminator.to_vec();
+        bytes.extend_from_slice(&event.nonce.to_le_bytes());
+        bytes.extend_from_slice(event.to.as_ref());
+        bytes.extend_from_slice(&event.amount.to_le_bytes());
+        base64::encode(bytes)
+    }
+
+    fn event(nonce: u64) -> MessageEvent {
+        MessageEvent {
+            nonce,
+            to: Pubkey::new_unique(),
+            amount: 1_000 + nonce,
+        }
+    }
+
+    #[test]
+    fn decodes_little_endian_fields() {
+        let discriminator = event_discriminator("MessageSent");
+        let event = MessageEvent {
+            nonce: 0x0102_0304_0506_0708,
+            to: Pubkey::new_unique(),
+            amount: u64::MAX - 1,
+        };
+        let data = event_data(&discriminator, &event);
+        assert_eq!(
+            decode_message_event(&data, &discriminator),
+            Some(event.clone())
+        );
+
+        let bytes = base64::decode(&data).unwrap();
+        assert_eq!(bytes[8], 0x08);
+        assert_eq!(bytes[15], 0x01);
+    }
+
+    #[test]
+    fn ignores_malformed_events() {
+        let discriminator = event_discriminator("MessageSent");
+        let data = event_data(&discriminator, &event(1));
+
+        assert_eq!(
+            decode_message_event(&data, &event_discriminator("Other")),
+            None
+        );
+        assert_eq!(decode_message_event("not base64!", &discriminator), None);
+        let mut short = base64::decode(&data).unwrap();
+        short.pop();
+        assert_eq!(
+            decode_message_event(&base64::encode(short), &discriminator),
+            None
+        );
+    }
+
+    #[test]
+    fn keeps_only_events_logged_by_the_program_itself() {
+        let discriminator = event_discriminator("MessageSent");
+        let program_id = Pubkey::new_unique();
+        let inner_program = Pubkey::new_unique();
+        let (outer, forged, after_cpi) = (event(1), event(2), event(3));
+
+        let logs: Vec<String> = vec![
+            format!("Program {} invoke [1]", program_id),
+            format!("Program data: {}", event_data(&discriminator, &outer)),
+            format!("Program {} invoke [2]", inner_program),
+            // 被 CPI 调用的程序伪造的事件
+            format!("Program data: {}", event_data(&discriminator, &forged)),
+            format!(
+                "Program {} consumed 1200 of 200000 compute units",
+                inner_program
+            ),
+            format!("Program {} success", inner_program),
+            format!("Program data: {}", event_data(&discriminator, &after_cpi)),
+            format!("Program {} success", program_id),
+            format!("Program data: {}", event_data(&discriminator, &forged)),
+        ];
+        assert_eq!(
+            parse_message_events(&logs, &program_id, &discriminator),
+            vec![outer, after_cpi]
+        );
+    }
+
+    #[test]
+    fn tracks_the_program_when_invoked_through_cpi() {
+        let discriminator = event_discriminator("MessageSent");
+        let program_id = Pubkey::new_unique();
+        let caller = Pubkey::new_unique();
+        let relayed = event(4);
+
+        let logs: Vec<String> = vec![
+            format!("Program {} invoke [1]", caller),
+            format!("Program data: {}", event_data(&discriminator, &event(5))),
+            format!("Program {} invoke [2]", program_id),
+            format!("Program data: {}", event_data(&discriminator, &relayed)),
+            format!("Program {} failed: custom program error: 0x1", program_id),
+            format!("Program data: {}", event_data(&discriminator, &event(6))),
+        ];
+        assert_eq!(
+            parse_message_events(&logs, &program_id, &discriminator),
+            vec![relayed]
+        );
+    }
+}
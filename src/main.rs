@@ -1,180 +1,6487 @@
 //! Solana L1 to L2 bridge relayer implementation.
 //! This module provides functionality to monitor L1 accounts and relay messages to L2.
 
+mod alert;
+mod cli;
 mod config;
+mod deadman;
+mod decision;
+mod error;
+mod events;
+mod filter;
+mod history;
+mod idl;
+mod logs;
+mod maintenance;
+mod memo;
+mod metrics;
 mod models;
+mod ndjson;
+mod offline;
 mod pda;
+mod reload;
+mod rpc;
+mod signer;
+mod state;
+mod telemetry;
+mod throttle;
 mod transaction;
 
 use crate::{
-    config::RelayerConfig, models::message::NonceStatus, pda::PdaManager,
-    transaction::TransactionBuilder,
+    alert::{Alerter, RelayNotification},
+    cli::{Args, Command},
+    config::{
+        parse_discriminator, parse_hex, ConfirmationStrategy, CursorDivergencePolicy,
+        DiscriminatorMismatchPolicy, Endianness, MissingDestinationPolicy, NonceStreamConfig,
+        ProcessingOrder, RelayerConfig, SourceMode, TransferErrorPolicy, ZeroAmountPolicy,
+    },
+    deadman::Heartbeat,
+    decision::{Action, Decision},
+    error::RelayerError,
+    events::{EventSink, RelayEvent},
+    filter::RelayFilter,
+    history::{RelayHistory, STATUS_CONFIRMED},
+    idl::NonceLayout,
+    logs::MessageEvent,
+    maintenance::MaintenanceWindow,
+    memo::MemoKey,
+    metrics::{Metrics, SkipReason},
+    models::message::{
+        check_discriminator, DiscriminatorMismatch, InsufficientAccountData,
+        NonceAccountOwnerMismatch, NonceStatus, TransferInfo, NONCE_OFFSET,
+    },
+    offline::SignedRelay,
+    pda::PdaManager,
+    reload::ConfigCell,
+    signer::RelaySigner,
+    state::{PendingConfirmation, PendingTransfer, RelayerState},
+    telemetry::{cycle_span, relay_span},
+    throttle::LatencyThrottle,
+    transaction::{
+        BlockhashCache, BuildOptions, ClosePda, DurableNonce, FeeEscalation, InitNonceAccount,
+        TransactionBuilder,
+    },
 };
 
 use anyhow::Result;
-use solana_client::rpc_client::RpcClient;
+use solana_account_decoder::{UiAccountEncoding, UiDataSliceConfig};
+use solana_client::{
+    client_error::{ClientErrorKind, Result as ClientResult},
+    pubsub_client::{PubsubClient, SignatureSubscription},
+    rpc_client::RpcClient,
+    rpc_config::{
+        RpcAccountInfoConfig, RpcSignatureSubscribeConfig, RpcTransactionConfig,
+        RpcTransactionLogsConfig, RpcTransactionLogsFilter,
+    },
+    rpc_response::RpcSignatureResult,
+};
 use solana_sdk::{
+    account::Account,
     commitment_config::CommitmentConfig,
+    hash::Hash,
+    instruction::Instruction,
     pubkey::Pubkey,
-    signature::{read_keypair_file, Keypair, Signer},
-    transaction::Transaction,
+    signature::{read_keypair_file, Keypair, Signature, Signer},
+    system_instruction,
+    transaction::{Transaction, TransactionError},
+};
+use solana_transaction_status::UiTransactionEncoding;
+use spl_associated_token_account::instruction::create_associated_token_account_idempotent;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
-use std::{str::FromStr, time::Duration};
-use tokio::time;
+use tokio::{
+    sync::{watch, Semaphore},
+    time,
+};
+use tracing::Instrument;
+
+/// How long lagging endpoints are polled for a confirmation quorum when
+/// `confirm_timeout_secs` isn't set
+const DEFAULT_QUORUM_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// `getTransaction` attempts for `verify_tx_success`, the transaction
+/// detail can lag its confirmation
+const VERIFY_TX_ATTEMPTS: u32 = 5;
+const VERIFY_TX_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Exit code once `max_uptime_secs` is reached, asking for a restart
+/// (`EX_TEMPFAIL`)
+const RESTART_EXIT_CODE: u8 = 75;
+
+/// Cursors of the L2 nonce accounts, the next nonce each one expects
+#[derive(Debug, Clone, Default, PartialEq)]
+struct L2Cursors {
+    /// One per nonce shard. With selector routes a single one, the cursor
+    /// of the slowest route
+    shards: Vec<u64>,
+    /// One per selector route
+    routes: BTreeMap<u8, u64>,
+}
+
+impl L2Cursors {
+    /// Every nonce below it was handled on all shards and routes
+    fn min(&self) -> u64 {
+        self.shards.iter().copied().min().unwrap_or(0)
+    }
+
+    /// Cursor of the route `selector` picks, else of nonce shard `shard`
+    fn destination(&self, shard: usize, selector: Option<u8>) -> u64 {
+        selector
+            .and_then(|selector| self.routes.get(&selector))
+            .copied()
+            .unwrap_or(self.shards[shard])
+    }
+}
+
+/// Why the monitor loop stopped
+#[derive(Debug, PartialEq)]
+enum Exit {
+    /// SIGTERM or Ctrl-C
+    Terminated,
+    /// `max_uptime_secs` reached
+    Restart,
+}
+
+impl Exit {
+    fn code(&self) -> ExitCode {
+        match self {
+            Exit::Terminated => ExitCode::SUCCESS,
+            Exit::Restart => ExitCode::from(RESTART_EXIT_CODE),
+        }
+    }
+}
+
+/// Transfers to one destination summed into a single relay
+struct AggregatedTransfer {
+    nonces: Vec<u64>,
+    amount: u64,
+    /// Highest nonce of the group, the one the relay carries
+    nonce: u64,
+    bump: u8,
+}
+
+impl AggregatedTransfer {
+    /// Groups loaded transfers by selector route and destination, summing
+    /// their amounts
+    fn group(
+        transfers: impl IntoIterator<Item = (u64, TransferInfo, u8)>,
+    ) -> Result<BTreeMap<(Option<u8>, Pubkey), AggregatedTransfer>> {
+        let mut groups: BTreeMap<(Option<u8>, Pubkey), AggregatedTransfer> = BTreeMap::new();
+        for (nonce, transfer_info, bump) in transfers {
+            let group = groups
+                .entry((transfer_info.selector, transfer_info.to))
+                .or_insert(AggregatedTransfer {
+                    nonces: Vec::new(),
+                    amount: 0,
+                    nonce,
+                    bump,
+                });
+            group.amount = group
+                .amount
+                .checked_add(transfer_info.amount)
+                .ok_or_else(|| {
+                    anyhow::anyhow!("Aggregated amount for {} overflows", transfer_info.to)
+                })?;
+            if nonce >= group.nonce {
+                group.nonce = nonce;
+                group.bump = bump;
+            }
+            group.nonces.push(nonce);
+        }
+        Ok(groups)
+    }
+}
+
+/// Outcome of waiting for a submitted relay transaction
+enum Confirmation {
+    Confirmed(Signature),
+    /// The blockhash expired first, the transaction can no longer land
+    Expired,
+    /// Still unconfirmed after `confirm_timeout_secs`, it may yet land
+    TimedOut(Signature),
+}
 
 struct Relayer {
     l1_client: RpcClient,
     l2_client: RpcClient,
+    verifier_client: Option<RpcClient>,
+    verifier_nonce_tolerance: u64,
+    slot_regression_tolerance: Option<u64>,
+    /// Highest L1 and L2 slots seen, for `slot_regression_tolerance`
+    highest_slots: (u64, u64),
+    /// Independent L2 endpoints confirmations are cross-checked against
+    quorum_clients: Vec<RpcClient>,
+    /// Endpoints, counting `l2_client`, that must report a relay confirmed
+    confirmation_quorum: usize,
     watched_account: Pubkey,
-    keypair: Keypair,
+    signer: Box<dyn RelaySigner>,
+    authorities: Vec<Keypair>,
     last_nonce: Option<u64>,
     pda_manager: PdaManager,
     transaction_builder: TransactionBuilder,
+    routes: BTreeMap<u8, TransactionBuilder>,
+    cursors: L2Cursors,
+    post_submit_delay: Duration,
+    confirm_timeout: Option<Duration>,
+    max_clock_skew_secs: Option<u64>,
+    halt_on_clock_skew: bool,
+    /// Measured distance between the local and cluster clocks, added to timeouts
+    clock_skew: Duration,
+    pending_confirmations: BTreeMap<u64, PendingConfirmation>,
+    max_pending_confirmations: Option<usize>,
+    state_path: Option<PathBuf>,
+    cursor_reconciled: bool,
+    /// Startup rescan window with its jitter applied
+    startup_rescan_window: u64,
+    /// Nonces below the cursor the startup rescan has yet to re-check, kept
+    /// across cycles until each was re-checked
+    rescan_queue: BTreeSet<u64>,
+    aggregate_by_destination: bool,
+    /// Relay a failed aggregated transfer's nonces individually
+    split_failed_batches: bool,
+    min_gap_to_relay: u64,
+    verify_state_change: bool,
+    expected_log_substring: Option<String>,
+    verify_tx_success: bool,
+    startup_retries: u32,
+    startup_retry_delay: Duration,
+    /// Uptime after which the relayer exits for a restart
+    max_uptime: Option<Duration>,
+    /// Set once the gap threshold is met, until the backlog is drained
+    draining: bool,
+    emergency_lag_threshold: Option<u64>,
+    /// Relaying stopped on excessive lag, until an operator resumes it
+    emergency_paused: bool,
+    /// Resumed by an operator, the threshold is ignored until lag drops under it
+    emergency_resumed: bool,
+    /// Read the L2 nonce account only every `l2_reconcile_every` cycles
+    trust_local_cursor: bool,
+    l2_reconcile_every: u64,
+    /// Cycles since the L2 nonce account was last read
+    cycles_since_reconcile: u64,
+    post_catchup_cooldown: Option<Duration>,
+    /// Set when the last cycle found a catch-up drained, until the cooldown runs
+    caught_up: bool,
+    cursor_divergence_threshold: u64,
+    cursor_divergence_policy: CursorDivergencePolicy,
+    watched_account_discriminator: Option<[u8; 8]>,
+    /// Offset of the relayed nonce counter in the watched account
+    watched_nonce_offset: usize,
+    /// Byte order of the watched account's nonce
+    nonce_endianness: Endianness,
+    /// Check the L2 nonce accounts are owned by their L2 program
+    verify_nonce_account_owner: bool,
+    /// Name of the nonce stream relayed, with `nonce_streams`
+    stream: Option<String>,
+    nonce_layout: NonceLayout,
+    discriminator_mismatch_policy: DiscriminatorMismatchPolicy,
+    alerter: Alerter,
+    metrics: Arc<Metrics>,
+    /// Requests in flight allowed across every RPC client, with `max_rpc_connections`
+    rpc_connections: Option<Arc<Semaphore>>,
+    /// Beaten after every cycle, relayed nonce and startup attempt for the
+    /// deadman watchdog
+    heartbeat: Heartbeat,
+    /// Running config, live fields updated on reload
+    config: RelayerConfig,
+    /// Config reloaded on SIGHUP, applied between cycles
+    config_cell: ConfigCell,
+    l1_program_id: Pubkey,
+    l1_ws_url: String,
+    /// L2 websocket endpoint relays are confirmed through, if enabled
+    ws_confirmation_url: Option<String>,
+    /// Websocket endpoint of the L2 node, for `confirm_state_change`
+    l2_ws_url: Option<String>,
+    /// Discriminator of the message event relayed in `program_logs` mode
+    message_event_discriminator: [u8; 8],
+    cache_missing_pdas: bool,
+    pda_read_retries: u32,
+    zero_amount_policy: ZeroAmountPolicy,
+    pda_read_retry_delay: Duration,
+    missing_pdas: BTreeSet<u64>,
+    /// Offset and bytes selecting message PDAs, with `bulk_pda_fetch`
+    bulk_pda_filter: Option<(usize, Vec<u8>)>,
+    /// Pending message PDAs fetched in bulk this cycle
+    prefetched_pdas: HashMap<Pubkey, Account>,
+    pda_missing_grace: Option<Duration>,
+    /// When each pending nonce was first seen, its age sets the priority fee
+    nonce_first_seen: BTreeMap<u64, Instant>,
+    /// Priority fee raise on resending an expired relay
+    fee_escalation: Option<FeeEscalation>,
+    /// Escalated resends of each pending nonce
+    fee_escalations: BTreeMap<u64, u32>,
+    /// When each nonce's message PDA was first found missing, during the grace period
+    missing_first_seen: BTreeMap<u64, Instant>,
+    /// Missing PDAs that outlasted the grace period and were alerted on
+    confirmed_gaps: BTreeSet<u64>,
+    skipped_nonces: BTreeSet<u64>,
+    completed_nonces: BTreeSet<u64>,
+    processing_order: ProcessingOrder,
+    max_nonces_per_run: Option<u64>,
+    inter_transfer_delay: Duration,
+    /// Throttle on recent confirmation latency, with `latency_throttle_threshold_ms`
+    latency_throttle: Option<LatencyThrottle>,
+    /// Wait after a log notification for more to arrive before relaying
+    coalesce_window: Duration,
+    on_transfer_error: TransferErrorPolicy,
+    on_missing_destination: MissingDestinationPolicy,
+    relay_filter: Option<RelayFilter>,
+    maintenance_windows: Vec<MaintenanceWindow>,
+    /// Inside a maintenance window as of the last cycle
+    in_maintenance: bool,
+    /// Rent-exempt minimum of an empty account, fetched when first needed
+    destination_rent_exempt: Option<u64>,
+    destination_mint: Option<Pubkey>,
+    create_destination_ata: bool,
+    /// Funds created destination accounts in place of the fee payer
+    rent_payer: Option<Keypair>,
+    /// Signs and pays for L1 PDA closes, with `close_pda_after_relay`
+    l1_wallet: Option<Keypair>,
+    /// Destination token accounts already known to exist
+    known_atas: HashSet<Pubkey>,
+    /// Nonces that failed under `retry_later`, retried first next cycle
+    retry_queue: BTreeSet<u64>,
+    fee_payer_fee_reserve: u64,
+    /// Computed from the rent-exempt minimum when monitoring starts
+    min_fee_payer_balance: Option<u64>,
+    low_balance_alerted: bool,
+    confirmation_strategy: ConfirmationStrategy,
+    confirmation_retries: u32,
+    watched_account_size: Option<usize>,
+    skip_unchanged_watched_account: bool,
+    relay_on_finalized_l1: bool,
+    /// Last watched account read and the nonce parsed from it
+    watched_account_cache: Option<(Account, u64)>,
+    /// Context slot of the last unchanged check, the oldest state the next
+    /// one accepts
+    watched_account_slot: Option<u64>,
+    success_webhook_batch: bool,
+    pending_notifications: Vec<RelayNotification>,
+    event_sink: Option<EventSink>,
+    /// Relay history written with `sqlite_path`
+    relay_history: Option<RelayHistory>,
+    decision_trace: bool,
+    /// Decision path of the nonce being processed, when tracing decisions
+    decision: Option<Decision>,
+    max_initial_nonce_jump: Option<u64>,
+    allow_large_initial_nonce: bool,
 }
 
 impl Relayer {
-    pub fn new(config: &RelayerConfig) -> Result<Self> {
-        let l1_client =
-            RpcClient::new_with_commitment(config.l1_url.clone(), CommitmentConfig::confirmed());
-        let l2_client =
-            RpcClient::new_with_commitment(config.l2_url.clone(), CommitmentConfig::confirmed());
+    /// Creates a relayer whose RPC clients share the `rpc_connections` limit
+    pub fn new(config: &RelayerConfig, rpc_connections: Option<Arc<Semaphore>>) -> Result<Self> {
+        if !config.rpc_headers.is_empty() {
+            println!("RPC headers: {}", rpc::mask_headers(&config.rpc_headers));
+        }
+        let metrics = Arc::new(Metrics::new(
+            config.metrics_exemplars,
+            config.max_destination_labels,
+        ));
+        let rpc_metrics = config.rpc_endpoint_metrics.then(|| metrics.clone());
+        let l1_client = rpc::new_rpc_client(
+            &config.l1_url,
+            &config.rpc_headers,
+            rpc_connections.clone(),
+            CommitmentConfig::confirmed(),
+            rpc_metrics.clone(),
+            config.otlp_endpoint.is_some(),
+        )?;
+        let l2_client = rpc::new_rpc_client(
+            &config.l2_url,
+            &config.rpc_headers,
+            rpc_connections.clone(),
+            CommitmentConfig::confirmed(),
+            rpc_metrics.clone(),
+            config.otlp_endpoint.is_some(),
+        )?;
+        let verifier_client = config
+            .verifier_l1_url
+            .as_ref()
+            .map(|url| {
+                rpc::new_rpc_client(
+                    url,
+                    &config.rpc_headers,
+                    rpc_connections.clone(),
+                    CommitmentConfig::confirmed(),
+                    rpc_metrics.clone(),
+                    config.otlp_endpoint.is_some(),
+                )
+            })
+            .transpose()?;
+        let quorum_clients = config
+            .confirmation_quorum_urls
+            .iter()
+            .map(|url| {
+                rpc::new_rpc_client(
+                    url,
+                    &config.rpc_headers,
+                    rpc_connections.clone(),
+                    CommitmentConfig::confirmed(),
+                    rpc_metrics.clone(),
+                    config.otlp_endpoint.is_some(),
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
         let watched_account = Pubkey::from_str(&config.watched_account)
             .map_err(|e| anyhow::anyhow!("Invalid watched account: {}", e))?;
-        let keypair = read_keypair_file(&config.wallet_path)
-            .map_err(|e| anyhow::anyhow!("Failed to read keypair file: {}", e))?;
+        let signer = signer::load_signer(config)?;
+        println!("Fee payer: {}", signer.describe());
+        let authorities = config
+            .authority_paths
+            .iter()
+            .map(|path| {
+                read_keypair_file(path).map_err(|e| {
+                    anyhow::anyhow!("Failed to read authority keypair file {}: {}", path, e)
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        // 手续费支付者总是第一个签名者，不能在 authority 中重复出现
+        if authorities
+            .iter()
+            .any(|authority| authority.pubkey() == signer.pubkey())
+        {
+            return Err(anyhow::anyhow!(
+                "authority_paths must not repeat the fee payer wallet {}",
+                signer.pubkey()
+            ));
+        }
+        let rent_payer = config
+            .rent_payer_path
+            .as_ref()
+            .map(|path| {
+                read_keypair_file(path).map_err(|e| {
+                    anyhow::anyhow!("Failed to read rent payer keypair file {}: {}", path, e)
+                })
+            })
+            .transpose()?;
+        if let Some(rent_payer) = &rent_payer {
+            if rent_payer.pubkey() == signer.pubkey() {
+                return Err(anyhow::anyhow!(
+                    "rent_payer_path must not repeat the fee payer wallet {}, leave it unset instead",
+                    signer.pubkey()
+                ));
+            }
+            println!("Rent payer: {}", rent_payer.pubkey());
+        }
+        let l1_wallet = config
+            .l1_wallet_path
+            .as_ref()
+            .map(|path| {
+                read_keypair_file(path).map_err(|e| {
+                    anyhow::anyhow!("Failed to read L1 wallet keypair file {}: {}", path, e)
+                })
+            })
+            .transpose()?;
+        if let Some(l1_wallet) = &l1_wallet {
+            println!("L1 wallet: {}", l1_wallet.pubkey());
+        }
         let l1_program_id = Pubkey::from_str(&config.l1_program_id)
             .map_err(|e| anyhow::anyhow!("Invalid L1 program ID: {}", e))?;
         let l2_program_id = Pubkey::from_str(&config.l2_program_id)
             .map_err(|e| anyhow::anyhow!("Invalid L2 program ID: {}", e))?;
 
+        let mut data_template = transaction::compile_template(
+            &config.instruction_template,
+            config.include_bump,
+            config
+                .instruction_discriminator
+                .as_deref()
+                .map(parse_discriminator)
+                .transpose()?,
+        )?;
+        if let Some(version) = config.instruction_version {
+            transaction::add_version(
+                &mut data_template,
+                version,
+                config.instruction_version_position,
+            );
+        }
+        let build_options = BuildOptions {
+            max_transaction_size: config.max_transaction_size,
+            data_template,
+            blockhash_cache: Arc::new(BlockhashCache::new(
+                config.blockhash_max_age_slots,
+                config.max_blockhash_age_slots,
+                config.blockhash_commitment.into(),
+            )),
+            priority_fee: config
+                .priority_fee_micro_lamports
+                .map(|base| transaction::PriorityFee {
+                    base,
+                    increment_per_sec: config.priority_fee_increment_per_sec,
+                    max: config.max_priority_fee_micro_lamports,
+                }),
+            durable_nonce: match &config.durable_nonce_account {
+                Some(account) => {
+                    let account = Pubkey::from_str(account)
+                        .map_err(|e| anyhow::anyhow!("Invalid durable nonce account: {}", e))?;
+                    let authority = match &config.durable_nonce_authority {
+                        Some(authority) => Pubkey::from_str(authority).map_err(|e| {
+                            anyhow::anyhow!("Invalid durable nonce authority: {}", e)
+                        })?,
+                        None => signer.pubkey(),
+                    };
+                    // nonce authority 必须是交易签名者之一
+                    if authority != signer.pubkey()
+                        && !authorities.iter().any(|a| a.pubkey() == authority)
+                    {
+                        return Err(anyhow::anyhow!(
+                            "durable_nonce_authority {} must be the fee payer or a relay authority",
+                            authority
+                        ));
+                    }
+                    Some(DurableNonce { account, authority })
+                }
+                None => None,
+            },
+            close_pda: config
+                .close_pda_after_relay
+                .then(|| -> Result<_> {
+                    Ok(ClosePda {
+                        program_id: l1_program_id,
+                        discriminator: match &config.close_pda_discriminator {
+                            Some(discriminator) => parse_discriminator(discriminator)?,
+                            None => transaction::close_message_discriminator(),
+                        },
+                    })
+                })
+                .transpose()?,
+            init_nonce_account: config
+                .auto_init_nonce_account
+                .then(|| -> Result<_> {
+                    Ok(InitNonceAccount {
+                        discriminator: match &config.init_nonce_discriminator {
+                            Some(discriminator) => parse_discriminator(discriminator)?,
+                            None => transaction::initialize_discriminator(),
+                        },
+                        accounts: config
+                            .init_nonce_accounts
+                            .iter()
+                            .map(|account| Pubkey::from_str(account))
+                            .collect::<Result<_, _>>()?,
+                    })
+                })
+                .transpose()?,
+            // 密钥只从环境变量读取，不写入配置和日志
+            memo_key: match &config.memo_hmac_key_env {
+                Some(name) => Some(MemoKey::new(
+                    std::env::var(name)
+                        .map_err(|_| anyhow::anyhow!("memo_hmac_key_env: {} is not set", name))?
+                        .into_bytes(),
+                )),
+                None => None,
+            },
+        };
+
+        let mut routes = BTreeMap::new();
+        for (selector, route) in &config.routes {
+            let selector: u8 = selector
+                .parse()
+                .map_err(|e| anyhow::anyhow!("Invalid route selector '{}': {}", selector, e))?;
+            let route_program_id = Pubkey::from_str(&route.l2_program_id).map_err(|e| {
+                anyhow::anyhow!("Invalid L2 program ID for route {}: {}", selector, e)
+            })?;
+            let route_nonce_account = Pubkey::from_str(&route.nonce_account).map_err(|e| {
+                anyhow::anyhow!("Invalid nonce account for route {}: {}", selector, e)
+            })?;
+            routes.insert(
+                selector,
+                TransactionBuilder::new(
+                    route_program_id,
+                    vec![route_nonce_account],
+                    build_options.clone(),
+                ),
+            );
+        }
+
+        // 每个 selector 取值都必须有对应的路由
+        match config.selector_offset {
+            Some(_) if routes.is_empty() => {
+                return Err(anyhow::anyhow!(
+                    "selector_offset is set but no routes are configured"
+                ));
+            }
+            Some(_) => {
+                let max_selector = config
+                    .max_selector
+                    .ok_or_else(|| anyhow::anyhow!("routes require max_selector"))?;
+                if let Some(missing) =
+                    (0..=max_selector).find(|selector| !routes.contains_key(selector))
+                {
+                    return Err(anyhow::anyhow!(
+                        "Routes must cover selectors 0..={}, missing route for selector {}",
+                        max_selector,
+                        missing
+                    ));
+                }
+                if let Some(selector) = routes.keys().find(|selector| **selector > max_selector) {
+                    return Err(anyhow::anyhow!(
+                        "Route for selector {} is above max_selector ({})",
+                        selector,
+                        max_selector
+                    ));
+                }
+            }
+            None if !routes.is_empty() => {
+                return Err(anyhow::anyhow!(
+                    "routes are configured but selector_offset is not set"
+                ));
+            }
+            None => {}
+        }
+
+        let nonce_accounts = if config.nonce_shards.is_empty() {
+            vec![Pubkey::from_str(&config.nonce_account)
+                .map_err(|e| anyhow::anyhow!("Invalid nonce account: {}", e))?]
+        } else {
+            if !routes.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "nonce_shards cannot be combined with selector routes"
+                ));
+            }
+            config
+                .nonce_shards
+                .iter()
+                .enumerate()
+                .map(|(shard, account)| {
+                    Pubkey::from_str(account).map_err(|e| {
+                        anyhow::anyhow!("Invalid nonce account for shard {}: {}", shard, e)
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?
+        };
+
+        // 合并转账需要 L2 程序支持，只能通过自定义指令模板启用
+        if config.aggregate_by_destination {
+            if config.instruction_template.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "aggregate_by_destination requires an instruction_template for a program that accepts aggregated relays"
+                ));
+            }
+            if nonce_accounts.len() > 1 {
+                return Err(anyhow::anyhow!(
+                    "aggregate_by_destination cannot be combined with nonce_shards"
+                ));
+            }
+        }
+
+        let watched_account_discriminator = config
+            .watched_account_discriminator
+            .as_deref()
+            .map(parse_discriminator)
+            .transpose()?;
+        let pda_discriminator = config
+            .pda_discriminator
+            .as_deref()
+            .map(parse_discriminator)
+            .transpose()?;
+        let bulk_pda_filter = match (&config.bulk_pda_filter, pda_discriminator) {
+            _ if !config.bulk_pda_fetch => None,
+            (Some(filter), _) => Some((config.bulk_pda_filter_offset, parse_hex(filter)?)),
+            (None, Some(discriminator)) => {
+                Some((config.bulk_pda_filter_offset, discriminator.to_vec()))
+            }
+            (None, None) => None,
+        };
+        let mut nonce_layout = match &config.nonce_account_idl {
+            Some(idl_path) => {
+                let layout = NonceLayout::from_idl(
+                    idl_path,
+                    config
+                        .nonce_account_type
+                        .as_deref()
+                        .unwrap_or("NonceStatus"),
+                )?;
+                println!("Nonce account layout from {}: {:?}", idl_path, layout);
+                layout
+            }
+            None => NonceLayout::default(),
+        };
+        nonce_layout.endianness = config.nonce_endianness;
+
+        let destination_mint = config
+            .destination_mint
+            .as_deref()
+            .map(Pubkey::from_str)
+            .transpose()
+            .map_err(|e| anyhow::anyhow!("Invalid destination mint: {}", e))?;
+
+        let state_path = config.state_path.as_ref().map(PathBuf::from);
+        let persisted_state = match &state_path {
+            Some(path) => RelayerState::load(path)?.unwrap_or_default(),
+            None => RelayerState::default(),
+        };
+
         Ok(Self {
             l1_client,
             l2_client,
+            verifier_client,
+            verifier_nonce_tolerance: config.verifier_nonce_tolerance,
+            slot_regression_tolerance: config.slot_regression_tolerance,
+            highest_slots: (0, 0),
+            confirmation_quorum: config
+                .confirmation_quorum
+                .unwrap_or(quorum_clients.len() + 1),
+            quorum_clients,
             watched_account,
-            keypair,
-            last_nonce: None,
-            pda_manager: PdaManager::new(l1_program_id, watched_account),
+            signer,
+            authorities,
+            last_nonce: persisted_state.last_nonce,
+            pda_manager: PdaManager::new(
+                l1_program_id,
+                watched_account,
+                config.selector_offset,
+                pda_discriminator,
+                destination_mint,
+                config.verify_pda_nonce.then_some(config.pda_nonce_offset),
+                config.seed_nonce_endianness,
+            ),
             transaction_builder: TransactionBuilder::new(
                 l2_program_id,
-                Pubkey::from_str(&config.nonce_account)
-                    .map_err(|e| anyhow::anyhow!("Invalid nonce account: {}", e))?,
+                nonce_accounts,
+                build_options,
             ),
+            routes,
+            cursors: L2Cursors::default(),
+            post_submit_delay: Duration::from_millis(config.post_submit_delay_ms),
+            confirm_timeout: config.confirm_timeout_secs.map(Duration::from_secs),
+            max_clock_skew_secs: config.max_clock_skew_secs,
+            halt_on_clock_skew: config.halt_on_clock_skew,
+            clock_skew: Duration::ZERO,
+            pending_confirmations: persisted_state.pending_confirmations,
+            max_pending_confirmations: config.max_pending_confirmations,
+            state_path,
+            cursor_reconciled: false,
+            startup_rescan_window: rescan_window(
+                config.startup_rescan_window,
+                config.startup_rescan_jitter,
+                &mut rand::thread_rng(),
+            ),
+            rescan_queue: BTreeSet::new(),
+            aggregate_by_destination: config.aggregate_by_destination,
+            split_failed_batches: config.split_failed_batches,
+            min_gap_to_relay: config.min_gap_to_relay,
+            verify_state_change: config.verify_state_change,
+            expected_log_substring: config.expected_log_substring.clone(),
+            verify_tx_success: config.verify_tx_success,
+            startup_retries: config.startup_retries,
+            startup_retry_delay: Duration::from_secs(config.startup_retry_delay_secs),
+            max_uptime: config.max_uptime_secs.map(Duration::from_secs),
+            draining: false,
+            emergency_lag_threshold: config.emergency_lag_threshold,
+            emergency_paused: persisted_state.emergency_paused,
+            emergency_resumed: persisted_state.emergency_resumed,
+            trust_local_cursor: config.trust_local_cursor,
+            l2_reconcile_every: config.l2_reconcile_every_n_cycles,
+            cycles_since_reconcile: 0,
+            post_catchup_cooldown: config.post_catchup_cooldown_secs.map(Duration::from_secs),
+            caught_up: false,
+            max_nonces_per_run: config.max_nonces_per_run,
+            inter_transfer_delay: Duration::from_millis(config.inter_transfer_delay_ms),
+            latency_throttle: config.latency_throttle_threshold_ms.map(|threshold| {
+                LatencyThrottle::new(
+                    Duration::from_millis(threshold),
+                    config.latency_throttle_window,
+                )
+            }),
+            coalesce_window: Duration::from_millis(config.coalesce_window_ms),
+            on_transfer_error: config.on_transfer_error,
+            on_missing_destination: config.on_missing_destination,
+            relay_filter: config
+                .relay_filter
+                .as_ref()
+                .map(RelayFilter::from_config)
+                .transpose()?,
+            maintenance_windows: config
+                .maintenance_windows
+                .iter()
+                .map(MaintenanceWindow::from_config)
+                .collect::<Result<_>>()?,
+            in_maintenance: false,
+            destination_rent_exempt: None,
+            l1_program_id,
+            l1_ws_url: config
+                .l1_ws_url
+                .clone()
+                .unwrap_or_else(|| rpc::websocket_url(&config.l1_url)),
+            message_event_discriminator: logs::event_discriminator(&config.message_event_name),
+            l2_ws_url: config.l2_ws_url.clone(),
+            ws_confirmation_url: config
+                .l2_ws_url
+                .clone()
+                .filter(|_| config.use_ws_confirmation),
+            destination_mint,
+            create_destination_ata: config.create_destination_ata,
+            rent_payer,
+            l1_wallet,
+            known_atas: HashSet::new(),
+            retry_queue: BTreeSet::new(),
+            fee_payer_fee_reserve: config.fee_payer_fee_reserve_lamports,
+            min_fee_payer_balance: None,
+            low_balance_alerted: false,
+            cursor_divergence_threshold: config.cursor_divergence_threshold,
+            cursor_divergence_policy: config.cursor_divergence_policy,
+            watched_account_discriminator,
+            watched_nonce_offset: NONCE_OFFSET,
+            nonce_endianness: config.nonce_endianness,
+            verify_nonce_account_owner: config.verify_nonce_account_owner,
+            stream: None,
+            nonce_layout,
+            discriminator_mismatch_policy: config.discriminator_mismatch_policy,
+            alerter: Alerter::new(
+                config.alert_webhook_url.clone(),
+                config.success_webhook_url.clone(),
+                config.alert_batch_window_secs.map(Duration::from_secs),
+            ),
+            metrics,
+            rpc_connections,
+            heartbeat: Heartbeat::new(),
+            config: config.clone(),
+            config_cell: ConfigCell::default(),
+            cache_missing_pdas: config.cache_missing_pdas,
+            bulk_pda_filter,
+            prefetched_pdas: HashMap::new(),
+            pda_missing_grace: config.pda_missing_grace_secs.map(Duration::from_secs),
+            missing_first_seen: BTreeMap::new(),
+            nonce_first_seen: BTreeMap::new(),
+            fee_escalation: config
+                .fee_escalation_step_micro_lamports
+                .map(|step| FeeEscalation {
+                    step,
+                    max_attempts: config.max_fee_escalations,
+                    max: config.max_priority_fee_micro_lamports,
+                }),
+            fee_escalations: BTreeMap::new(),
+            confirmed_gaps: BTreeSet::new(),
+            pda_read_retries: config.pda_read_retries,
+            zero_amount_policy: config.zero_amount_policy,
+            pda_read_retry_delay: Duration::from_millis(config.pda_read_retry_delay_ms),
+            missing_pdas: if config.cache_missing_pdas {
+                persisted_state.missing_pdas
+            } else {
+                BTreeSet::new()
+            },
+            skipped_nonces: persisted_state.skipped_nonces,
+            completed_nonces: BTreeSet::new(),
+            processing_order: config.processing_order,
+            confirmation_strategy: config.confirmation_strategy,
+            confirmation_retries: config.confirmation_retries,
+            watched_account_size: None,
+            skip_unchanged_watched_account: config.skip_unchanged_watched_account,
+            relay_on_finalized_l1: config.relay_on_finalized_l1,
+            watched_account_cache: None,
+            watched_account_slot: None,
+            success_webhook_batch: config.success_webhook_batch,
+            pending_notifications: Vec::new(),
+            event_sink: EventSink::from_config(config)?,
+            relay_history: config
+                .sqlite_path
+                .as_deref()
+                .map(RelayHistory::open)
+                .transpose()?,
+            decision_trace: config.decision_trace || config.ndjson_stdout,
+            decision: None,
+            max_initial_nonce_jump: config.max_initial_nonce_jump,
+            allow_large_initial_nonce: config.allow_large_initial_nonce,
         })
     }
 
-    async fn monitor_and_relay(&mut self) -> Result<()> {
+    /// Parses the relayed cursor from a fetched nonce account
+    fn parse_l2_nonce(&self, nonce_account: &Pubkey, account: Option<Account>) -> Result<u64> {
+        let nonce_account_data = match account {
+            Some(account) => account.data,
+            None => {
+                return Err(RelayerError::AccountNotFound(format!(
+                    "Nonce account {} not found",
+                    nonce_account
+                ))
+                .into())
+            }
+        };
+
+        check_discriminator(
+            nonce_account,
+            &nonce_account_data,
+            self.nonce_layout.discriminator.as_ref(),
+        )?;
+        self.nonce_layout.read_l1_nonce(&nonce_account_data)
+    }
+
+    /// Relay transaction signers, the fee payer first followed by the authorities
+    fn signers(&self) -> Vec<&dyn Signer> {
+        let mut signers = vec![self.signer.as_ref() as &dyn Signer];
+        signers.extend(
+            self.authorities
+                .iter()
+                .map(|authority| authority as &dyn Signer),
+        );
+        signers
+    }
+
+    /// Re-signs a relay under a new blockhash with the signers it was built
+    /// with, given by their pubkeys
+    fn resign(
+        &self,
+        transaction: &mut Transaction,
+        signer_keys: &[Pubkey],
+        recent_blockhash: Hash,
+    ) -> Result<()> {
+        let mut available = self.signers();
+        available.extend(
+            self.rent_payer
+                .iter()
+                .map(|rent_payer| rent_payer as &dyn Signer),
+        );
+        let signers = signer_keys
+            .iter()
+            .map(|key| {
+                available
+                    .iter()
+                    .find(|signer| signer.pubkey() == *key)
+                    .copied()
+                    .ok_or_else(|| anyhow::anyhow!("No signer loaded for relay signer {}", key))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        transaction.try_sign(&signers, recent_blockhash)?;
+        Ok(())
+    }
+
+    /// Signer funding created destination accounts, the rent payer if
+    /// configured, else the fee payer
+    fn rent_payer(&self) -> &dyn Signer {
+        match &self.rent_payer {
+            Some(rent_payer) => rent_payer,
+            None => self.signer.as_ref(),
+        }
+    }
+
+    /// Picks the transaction builder for a message's destination selector
+    fn builder_for(&self, selector: Option<u8>) -> Result<&TransactionBuilder> {
+        select_route(&self.transaction_builder, &self.routes, selector)
+    }
+
+    /// Relays every message event the L1 program logs, resubscribing
+    /// whenever the websocket connection drops
+    async fn relay_program_logs(&mut self) -> Result<()> {
+        self.check_clock_skew()?;
+        self.min_fee_payer_balance = Some(self.fee_payer_threshold()?);
+        // retry_later 策略下失败的事件，在下一批日志到达时重试
+        let mut failed_events: BTreeMap<u64, MessageEvent> = BTreeMap::new();
         loop {
-            // 获取 L1 watched account 的 nonce
-            let account_data = self.l1_client.get_account_data(&self.watched_account)?;
-            let nonce_status = NonceStatus::from_bytes(&account_data)?;
-            let l1_watched_nonce = nonce_status.nonce;
+            let (_subscription, receiver) = match PubsubClient::logs_subscribe(
+                &self.l1_ws_url,
+                RpcTransactionLogsFilter::Mentions(vec![self.l1_program_id.to_string()]),
+                RpcTransactionLogsConfig {
+                    commitment: Some(self.l1_client.commitment()),
+                },
+            ) {
+                Ok(subscription) => subscription,
+                Err(err) => {
+                    println!(
+                        "Failed to subscribe to program logs at {}: {}",
+                        self.l1_ws_url, err
+                    );
+                    time::sleep(Duration::from_secs(5)).await;
+                    continue;
+                }
+            };
+            println!(
+                "Subscribed to logs of {} at {}",
+                self.l1_program_id, self.l1_ws_url
+            );
+
+            // 连接断开后 recv 返回错误，退出内层循环重新订阅
+            while let Ok(response) = tokio::task::block_in_place(|| receiver.recv()) {
+                self.apply_reload()?;
+                let responses = coalesce(response, self.coalesce_window, |remaining| {
+                    tokio::task::block_in_place(|| receiver.recv_timeout(remaining)).ok()
+                });
+                if responses.len() > 1 {
+                    println!("Coalesced {} log notifications", responses.len());
+                }
+                let events: Vec<MessageEvent> = responses
+                    .iter()
+                    // 失败的交易不会产生有效消息
+                    .filter(|response| response.value.err.is_none())
+                    .flat_map(|response| {
+                        logs::parse_message_events(
+                            &response.value.logs,
+                            &self.l1_program_id,
+                            &self.message_event_discriminator,
+                        )
+                    })
+                    .collect();
+                let retries = std::mem::take(&mut failed_events).into_values();
+                // 待确认数量达到上限后，本批剩余事件留到下一批
+                let mut paused = false;
+                for event in retries.chain(events) {
+                    if paused || self.at_pending_limit().await? {
+                        paused = true;
+                        failed_events.insert(event.nonce, event);
+                        continue;
+                    }
+                    self.pace_submission(false).await;
+                    self.begin_decision(event.nonce);
+                    let result = self.relay_event(&event).await;
+                    self.finish_decision(&result);
+                    if let Err(err) = result {
+                        self.handle_transfer_error(event.nonce, err).await?;
+                        if self.retry_queue.remove(&event.nonce) {
+                            failed_events.insert(event.nonce, event);
+                        }
+                    }
+                }
+
+                let notifications = std::mem::take(&mut self.pending_notifications);
+                self.alerter.notify_success(&notifications).await;
+            }
+            println!("Program log subscription closed, resubscribing...");
+            time::sleep(Duration::from_secs(5)).await;
+        }
+    }
+
+    /// Applies a config reloaded on SIGHUP and waiting in `config_cell`
+    fn apply_reload(&mut self) -> Result<()> {
+        let reloaded = self.config_cell.lock().unwrap().take();
+        match reloaded {
+            Some(reloaded) => self.reload(&reloaded),
+            None => Ok(()),
+        }
+    }
+
+    /// Applies the live fields of a reloaded config, warning about changes
+    /// that need a restart. A nonce stream applies its own view of it
+    fn reload(&mut self, reloaded: &RelayerConfig) -> Result<()> {
+        let stream_view;
+        let reloaded = match &self.stream {
+            Some(name) => match reloaded
+                .nonce_streams
+                .iter()
+                .find(|stream| &stream.name == name)
+            {
+                Some(stream) => {
+                    stream_view = stream_config(reloaded, stream);
+                    &stream_view
+                }
+                None => {
+                    println!(
+                        "Warning: nonce stream {} is missing from the reloaded config, keeping its running config",
+                        name
+                    );
+                    return Ok(());
+                }
+            },
+            None => reloaded,
+        };
+        let reload = reload::merge(&self.config, reloaded)?;
+        for field in &reload.ignored {
+            println!(
+                "Warning: {} changed but only takes effect after a restart, ignoring",
+                field
+            );
+        }
+        if reload.applied.is_empty() {
+            println!("Config reloaded, no live changes");
+            return Ok(());
+        }
+
+        let config = reload.config;
+        self.post_submit_delay = Duration::from_millis(config.post_submit_delay_ms);
+        self.confirm_timeout = config.confirm_timeout_secs.map(Duration::from_secs);
+        self.max_pending_confirmations = config.max_pending_confirmations;
+        self.emergency_lag_threshold = config.emergency_lag_threshold;
+        self.confirmation_strategy = config.confirmation_strategy;
+        self.confirmation_retries = config.confirmation_retries;
+        self.min_gap_to_relay = config.min_gap_to_relay;
+        self.max_nonces_per_run = config.max_nonces_per_run;
+        self.inter_transfer_delay = Duration::from_millis(config.inter_transfer_delay_ms);
+        self.coalesce_window = Duration::from_millis(config.coalesce_window_ms);
+        self.post_catchup_cooldown = config.post_catchup_cooldown_secs.map(Duration::from_secs);
+        self.processing_order = config.processing_order;
+        self.on_transfer_error = config.on_transfer_error;
+        self.on_missing_destination = config.on_missing_destination;
+        self.verify_state_change = config.verify_state_change;
+        self.expected_log_substring = config.expected_log_substring.clone();
+        self.cursor_divergence_threshold = config.cursor_divergence_threshold;
+        self.cursor_divergence_policy = config.cursor_divergence_policy;
+        self.discriminator_mismatch_policy = config.discriminator_mismatch_policy;
+        self.zero_amount_policy = config.zero_amount_policy;
+        self.pda_read_retries = config.pda_read_retries;
+        self.pda_read_retry_delay = Duration::from_millis(config.pda_read_retry_delay_ms);
+        self.pda_missing_grace = config.pda_missing_grace_secs.map(Duration::from_secs);
+        self.verifier_nonce_tolerance = config.verifier_nonce_tolerance;
+        self.slot_regression_tolerance = config.slot_regression_tolerance;
+        self.success_webhook_batch = config.success_webhook_batch;
+        self.decision_trace = config.decision_trace || config.ndjson_stdout;
+        self.relay_filter = config
+            .relay_filter
+            .as_ref()
+            .map(RelayFilter::from_config)
+            .transpose()?;
+        self.maintenance_windows = config
+            .maintenance_windows
+            .iter()
+            .map(MaintenanceWindow::from_config)
+            .collect::<Result<_>>()?;
+        if self.fee_payer_fee_reserve != config.fee_payer_fee_reserve_lamports {
+            self.fee_payer_fee_reserve = config.fee_payer_fee_reserve_lamports;
+            if self.min_fee_payer_balance.is_some() {
+                self.min_fee_payer_balance = Some(self.fee_payer_threshold()?);
+            }
+        }
+        self.config = config;
+
+        println!("Applied live config changes: {}", reload.applied.join(", "));
+        Ok(())
+    }
+
+    async fn monitor_and_relay(&mut self, terminate: watch::Receiver<bool>) -> Result<Exit> {
+        monitor_streams(std::slice::from_mut(self), terminate).await
+    }
+
+    /// Startup checks and the reads of the first cycle, failing fast before
+    /// the monitor loop starts
+    fn start(&mut self) -> Result<()> {
+        self.check_clock_skew()?;
+        if self
+            .transaction_builder
+            .options
+            .init_nonce_account
+            .is_some()
+        {
+            self.init_missing_nonce_accounts()?;
+        }
+        // 启动时先读取一次，配置错误的 nonce 账户在首轮之前报错
+        self.read_cursors()?;
+        self.l1_client
+            .get_account(&self.watched_account)
+            .map_err(RelayerError::from)?;
+        self.min_fee_payer_balance = Some(self.fee_payer_threshold()?);
+        Ok(())
+    }
 
-            // 获取 L2 nonce account 的状态
-            let nonce_account = self
+    /// Creates every nonce account that doesn't exist yet on L2, with
+    /// `auto_init_nonce_account`
+    fn init_missing_nonce_accounts(&self) -> Result<()> {
+        let nonce_accounts = &self.transaction_builder.nonce_accounts;
+        let accounts = rpc::get_multiple_accounts(&self.l2_client, nonce_accounts)?;
+        for (nonce_account, account) in nonce_accounts.iter().zip(accounts) {
+            if account.is_some() {
+                continue;
+            }
+            println!("Nonce account {} not found, initializing it", nonce_account);
+            let recent_blockhash = self
+                .transaction_builder
+                .options
+                .blockhash_cache
+                .get(&self.l2_client)?;
+            let transaction = self.transaction_builder.build_init_nonce_account(
+                nonce_account,
+                self.signer.as_ref(),
+                recent_blockhash,
+            )?;
+            let signature = self
                 .l2_client
-                .get_account_data(&self.transaction_builder.nonce_account)?;
-
-            let l2_nonce_status = if nonce_account.len() >= 24 {
-                let l1_nonce_bytes: [u8; 8] = nonce_account[8..16].try_into()?;
-                let l2_nonce_bytes: [u8; 8] = nonce_account[16..24].try_into()?;
-                let l1_nonce = u64::from_le_bytes(l1_nonce_bytes);
-                let l2_nonce = u64::from_le_bytes(l2_nonce_bytes);
-                l1_nonce
-            } else {
-                return Err(anyhow::anyhow!(
-                    "Invalid nonce account data length: expected at least 24 bytes, got {}",
-                    nonce_account.len()
-                ));
-            };
+                .send_and_confirm_transaction(&transaction)
+                .map_err(RelayerError::from)?;
+            println!(
+                "Initialized nonce account {}, transaction: {}",
+                nonce_account, signature
+            );
+        }
+        Ok(())
+    }
 
-            // 更新 last_nonce 为 L2 nonce account 中的值
-            if self.last_nonce != Some(l2_nonce_status) {
-                println!(
-                    "Updating last_nonce from {} to {}",
-                    self.last_nonce.unwrap_or(0),
-                    l2_nonce_status
-                );
-                self.last_nonce = Some(l2_nonce_status);
+    /// Runs `start`, retrying RPC failures up to `startup_retries` times
+    async fn start_with_retries(&mut self) -> Result<()> {
+        let mut attempt = 0;
+        loop {
+            match self.start() {
+                Ok(()) => return Ok(()),
+                Err(err) if attempt < self.startup_retries && err.is::<RelayerError>() => {
+                    attempt += 1;
+                    println!(
+                        "Startup reads failed, retrying in {:?} ({}/{}): {}",
+                        self.startup_retry_delay, attempt, self.startup_retries, err
+                    );
+                    time::sleep(self.startup_retry_delay).await;
+                    // 启动重试期间 watchdog 已在运行，每次重试都算作进展
+                    self.heartbeat.beat();
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Runs one monitor cycle, returning an error only if the relayer should stop
+    async fn monitor_cycle(&mut self) -> Result<()> {
+        if let Some(stream) = &self.stream {
+            println!("\nNonce stream {}:", stream);
+        }
+        let span = cycle_span(&self.stream);
+        let result = self.run_cycle().instrument(span).await;
+        self.heartbeat.beat();
+        // catch-up 期间累积的成功通知在每轮结束后统一发送
+        let notifications = std::mem::take(&mut self.pending_notifications);
+        self.alerter.notify_success(&notifications).await;
+
+        if let Err(err) = result {
+            // 临时性的 RPC 错误等到下一轮重试，其余错误终止 relayer
+            match err.downcast_ref::<RelayerError>() {
+                Some(rpc_error) if rpc_error.is_retryable() => {
+                    println!("Cycle failed, retrying next cycle: {}", rpc_error);
+                }
+                _ => return Err(err),
             }
+        }
+        Ok(())
+    }
+
+    async fn run_cycle(&mut self) -> Result<()> {
+        // 合并运行期间通过 skip-nonce 命令写入的跳过记录
+        if let Some(state_path) = &self.state_path {
+            if let Some(state) = RelayerState::load(state_path)? {
+                self.skipped_nonces.extend(state.skipped_nonces);
+                // resume 命令会清除紧急暂停标记
+                if self.emergency_paused && !state.emergency_paused {
+                    println!("Emergency pause lifted, resuming relaying");
+                    self.emergency_paused = false;
+                    self.emergency_resumed = state.emergency_resumed;
+                }
+            }
+        }
+        self.metrics.set_paused(self.emergency_paused);
+        if self.emergency_paused {
+            println!("Relaying paused on excessive lag, run `resume` to continue");
+            return Ok(());
+        }
+        self.update_maintenance();
 
-            // 如果 L1 watched account 的 nonce 大于当前处理的 nonce
-            if l1_watched_nonce > l2_nonce_status {
-                println!("\nProcessing nonce change...");
-                println!("Current nonce from watched account: {}", l1_watched_nonce);
-                println!("Current nonce from nonce account: {}", l2_nonce_status);
+        self.recheck_pending_confirmations().await?;
+        self.check_fee_payer_balance().await?;
+        if self.slot_regressed()? {
+            return Ok(());
+        }
 
-                // 处理从 L2 nonce 到 L1 nonce 之间的所有交易
-                for nonce in l2_nonce_status..l1_watched_nonce {
-                    self.send_l2_transfer(nonce).await?;
+        // 获取 L1 watched account 的 nonce，账户未变化时不读取完整数据
+        let l1_watched_nonce = match self.unchanged_watched_nonce()? {
+            Some(nonce) => nonce,
+            None => {
+                let account = self
+                    .l1_client
+                    .get_account(&self.watched_account)
+                    .map_err(RelayerError::from)?;
+                match self.watched_nonce(account).await? {
+                    Some(nonce) => nonce,
+                    None => return Ok(()),
                 }
             }
+        };
+        if !self.verify_watched_nonce(l1_watched_nonce).await? {
+            return Ok(());
+        }
+        let cursors = match self.local_cursor() {
+            Some(cursor) => L2Cursors {
+                shards: vec![cursor],
+                routes: BTreeMap::new(),
+            },
+            None => match self.read_cursors() {
+                Err(err) if err.is::<NonceAccountOwnerMismatch>() => {
+                    self.alerter
+                        .send_critical(&format!("{}, stopping the relayer", err))
+                        .await;
+                    return Err(err);
+                }
+                result => result?,
+            },
+        };
+        if cursors.shards.len() > 1 && self.cursors.shards != cursors.shards {
+            println!("Shard cursors: {:?}", cursors.shards);
+        }
+        if cursors.routes.len() > 1 && self.cursors.routes != cursors.routes {
+            println!("Route cursors: {:?}", cursors.routes);
+        }
+        self.cursors = cursors;
+
+        // 所有分片和路由中最小的 cursor 之前的 nonce 都已处理完毕
+        let l2_nonce_status = self.cursors.min();
+        self.metrics.set_nonces(l1_watched_nonce, l2_nonce_status);
+        if self.in_maintenance {
+            println!(
+                "In a maintenance window, not relaying (lag {} nonces)",
+                l1_watched_nonce.saturating_sub(l2_nonce_status)
+            );
+            return Ok(());
+        }
+        // 指标使用 confirmed 读数，relay 只处理 L1 上已 finalized 的 nonce
+        let l1_watched_nonce = match self.finalized_watched_nonce(l1_watched_nonce)? {
+            Some(nonce) => nonce,
+            None => return Ok(()),
+        };
+
+        let rescan_from = (!self.cursor_reconciled && self.startup_rescan_window > 0)
+            .then(|| l2_nonce_status.saturating_sub(self.startup_rescan_window));
+        if !self.cursor_reconciled {
+            // 首轮用链上 nonce 校验本地持久化的 cursor
+            self.reconcile_cursor(l2_nonce_status)?;
+            self.cursor_reconciled = true;
+        } else if self.last_nonce != Some(l2_nonce_status) {
+            // 更新 last_nonce 为 L2 nonce account 中的值
+            println!(
+                "Updating last_nonce from {} to {}",
+                self.last_nonce.unwrap_or(0),
+                l2_nonce_status
+            );
+            self.last_nonce = Some(l2_nonce_status);
+        }
+        let cursor = self.last_nonce.unwrap_or(l2_nonce_status);
+
+        // 丢弃 cursor 之前的缺失记录
+        self.missing_pdas = self.missing_pdas.split_off(&cursor);
+        self.missing_first_seen = self.missing_first_seen.split_off(&cursor);
+        self.nonce_first_seen = self.nonce_first_seen.split_off(&cursor);
+        self.fee_escalations = self.fee_escalations.split_off(&cursor);
+        self.confirmed_gaps = self.confirmed_gaps.split_off(&cursor);
+        self.completed_nonces = self.completed_nonces.split_off(&cursor);
+        self.save_state()?;
+
+        // 首轮重新检查 cursor 之前的窗口，已上链的 nonce 会在模拟时被拒绝
+        if let Some(rescan_from) = rescan_from.filter(|&from| from < cursor) {
+            println!(
+                "Rescanning nonces {} to {} on startup",
+                rescan_from,
+                cursor - 1
+            );
+            self.rescan_queue = (rescan_from..cursor).collect();
+        }
+        if !self.continue_rescan().await? {
+            return Ok(());
+        }
+
+        // 积压达到 min_gap_to_relay 后开始处理，并持续到全部处理完毕
+        let gap = l1_watched_nonce.saturating_sub(cursor);
+        if self.emergency_pause(gap).await? {
+            return Ok(());
+        }
+        if !self.gap_reached(gap) {
+            return Ok(());
+        }
+
+        // 如果 L1 watched account 的 nonce 大于当前处理的 nonce
+        if l1_watched_nonce > cursor {
+            println!("\nProcessing nonce change...");
+            println!("Current nonce from watched account: {}", l1_watched_nonce);
+            println!("Current nonce from nonce account: {}", l2_nonce_status);
+
+            // 处理从 cursor 到 L1 nonce 之间的所有交易
+            let end = self.run_end(cursor, l1_watched_nonce);
+            let pending = self.pending_nonces(cursor, end);
+            let now = Instant::now();
+            for &nonce in &pending {
+                self.nonce_first_seen.entry(nonce).or_insert(now);
+            }
+            self.prefetch_pdas(&pending);
+            if self.aggregate_by_destination {
+                return self.relay_aggregated(&pending).await;
+            }
+            self.relay_pending(pending).await?;
+        }
+
+        Ok(())
+    }
 
-            time::sleep(Duration::from_secs(60)).await;
+    /// Relays `pending` one nonce at a time, applying `on_transfer_error` to
+    /// failures and stopping early at the pending confirmation limit
+    async fn relay_pending(&mut self, pending: Vec<u64>) -> Result<()> {
+        let mut sent_any = false;
+        for nonce in pending {
+            // 该 nonce 所在分片已经处理过
+            if nonce < self.cursors.shards[self.transaction_builder.shard_for(nonce)] {
+                self.advance_cursor(nonce)?;
+                continue;
+            }
+            // 上一轮已完成，只是 cursor 被之前的 nonce 挡住
+            if self.completed_nonces.contains(&nonce) {
+                continue;
+            }
+            if self.at_pending_limit().await? {
+                break;
+            }
+            self.pace_submission(sent_any).await;
+            if let Err(err) = self.send_l2_transfer(nonce).await {
+                self.handle_transfer_error(nonce, err).await?;
+            }
+            sent_any = true;
         }
+        Ok(())
     }
 
-    async fn send_l2_transfer(&self, nonce: u64) -> Result<()> {
-        println!("\nPreparing L2 transfer for nonce: {}", nonce);
-        let (pda, _) = self.pda_manager.find_address(nonce);
+    /// Pauses relaying when a backlog of `gap` nonces exceeds
+    /// `emergency_lag_threshold`, unless an operator resumed past it.
+    /// Returns whether relaying was paused
+    async fn emergency_pause(&mut self, gap: u64) -> Result<bool> {
+        let threshold = match self.emergency_lag_threshold {
+            Some(threshold) => threshold,
+            None => return Ok(false),
+        };
+        // 积压过大通常说明系统性故障，暂停等待人工确认，而不是批量提交
+        if gap <= threshold {
+            self.emergency_resumed = false;
+            return Ok(false);
+        }
+        if self.emergency_resumed {
+            return Ok(false);
+        }
+        self.emergency_paused = true;
+        self.metrics.set_paused(true);
+        self.save_state()?;
+        self.alerter
+            .send_critical(&format!(
+                "Lag of {} nonces exceeds emergency_lag_threshold {}, relaying paused until an operator runs `resume`",
+                gap, threshold
+            ))
+            .await;
+        Ok(true)
+    }
 
-        // 检查PDA账户是否存在
-        if self.l1_client.get_account(&pda).is_err() {
-            return Ok(());  // 如果账户不存在，跳过这个nonce
+    /// Whether a backlog of `gap` nonces should be relayed this cycle. Once
+    /// it reaches `min_gap_to_relay` the backlog drains fully
+    fn gap_reached(&mut self, gap: u64) -> bool {
+        if gap == 0 {
+            self.caught_up = self.draining;
+            self.draining = false;
+            return true;
         }
+        if !self.draining && gap < self.min_gap_to_relay {
+            println!(
+                "Deferring {} pending nonces until the gap reaches {}",
+                gap, self.min_gap_to_relay
+            );
+            return false;
+        }
+        self.draining = true;
+        true
+    }
 
-        // 获取转账信息
-        let (transfer_amount, transfer_to_address) = self.pda_manager.get_transfer_info(&self.l1_client, &pda).await?;
+    /// Nonces from `cursor` up to `end` in `processing_order`, the ones that
+    /// failed last cycle first
+    fn pending_nonces(&mut self, cursor: u64, end: u64) -> Vec<u64> {
+        let mut pending: Vec<u64> = (cursor..end).collect();
+        if self.processing_order == ProcessingOrder::Descending {
+            pending.reverse();
+        }
+        // 上一轮失败的 nonce 优先重试
+        let retries: Vec<u64> = std::mem::take(&mut self.retry_queue)
+            .into_iter()
+            .filter(|&nonce| nonce >= cursor)
+            .collect();
+        if !retries.is_empty() {
+            println!("Retrying {} nonces that failed last cycle", retries.len());
+            pending.retain(|nonce| !retries.contains(nonce));
+            pending.splice(0..0, retries);
+        }
+        pending
+    }
 
-        // 构建并发送交易
-        let transaction = self.transaction_builder.build_transfer_transaction(
-            transfer_amount,
-            nonce,
-            &transfer_to_address,
-            &self.keypair,
-            &self.l2_client,
+    /// Fetches the pending message PDAs in one request with `bulk_pda_fetch`,
+    /// leaving `load_transfer` to fetch only those not returned
+    fn prefetch_pdas(&mut self, pending: &[u64]) {
+        self.prefetched_pdas.clear();
+        let (offset, filter) = match &self.bulk_pda_filter {
+            Some(bulk_pda_filter) => bulk_pda_filter,
+            None => return,
+        };
+        let pdas: HashSet<Pubkey> = pending
+            .iter()
+            .map(|&nonce| self.pda_manager.find_address(nonce).0)
+            .collect();
+        // 响应过大或节点禁用 getProgramAccounts 时退回逐个读取
+        match self
+            .pda_manager
+            .fetch_program_accounts(&self.l1_client, &pdas, *offset, filter)
+        {
+            Ok(accounts) => {
+                println!(
+                    "Fetched {} of {} pending message PDAs in bulk",
+                    accounts.len(),
+                    pdas.len()
+                );
+                self.prefetched_pdas = accounts;
+            }
+            Err(err) => println!(
+                "Warning: bulk PDA fetch failed, fetching message PDAs one by one: {}",
+                err
+            ),
+        }
+    }
+
+    /// With `skip_unchanged_watched_account`, the last parsed nonce while the
+    /// watched account's lamports and nonce bytes are unchanged. Only those
+    /// bytes are read, from a state no older than the previous check
+    fn unchanged_watched_nonce(&mut self) -> Result<Option<u64>> {
+        let (cached, nonce) = match &self.watched_account_cache {
+            Some(cache) => cache,
+            None => return Ok(None),
+        };
+        let offset = self.watched_nonce_offset;
+        let config = RpcAccountInfoConfig {
+            encoding: Some(UiAccountEncoding::Base64),
+            data_slice: Some(UiDataSliceConfig { offset, length: 8 }),
+            commitment: Some(self.l1_client.commitment()),
+            min_context_slot: self.watched_account_slot,
+        };
+        let response = self
+            .l1_client
+            .get_account_with_config(&self.watched_account, config)
+            .map_err(RelayerError::from)?;
+        let unchanged = response.value.is_some_and(|account| {
+            account.lamports == cached.lamports
+                && cached.data.get(offset..offset + 8) == Some(account.data.as_slice())
+        });
+        let nonce = *nonce;
+        self.watched_account_slot = Some(response.context.slot);
+        Ok(unchanged.then_some(nonce))
+    }
+
+    /// Checks the watched account and parses its nonce, `None` if the cycle
+    /// should stop on a discriminator mismatch or the account holds no data
+    /// yet. With `skip_unchanged_watched_account` the parse is cached for
+    /// `unchanged_watched_nonce`
+    async fn watched_nonce(&mut self, account: Account) -> Result<Option<u64>> {
+        let account_data = &account.data;
+        // 刚创建、尚未初始化的账户没有数据，不是布局错误
+        if account_data.is_empty() {
+            println!(
+                "Watched account {} has no data yet, nothing to relay",
+                self.watched_account
+            );
+            return Ok(None);
+        }
+        // 账户大小变化通常意味着账户迁移或配置了错误的账户
+        if let Some(previous_size) = self.watched_account_size {
+            if previous_size != account_data.len() {
+                self.alerter
+                    .send(&format!(
+                        "Watched account {} {} from {} to {} bytes, check for an account migration",
+                        self.watched_account,
+                        if account_data.len() < previous_size {
+                            "shrank"
+                        } else {
+                            "grew"
+                        },
+                        previous_size,
+                        account_data.len()
+                    ))
+                    .await;
+            }
+        }
+        self.watched_account_size = Some(account_data.len());
+        if let Err(err) = check_discriminator(
+            &self.watched_account,
+            account_data,
+            self.watched_account_discriminator.as_ref(),
+        ) {
+            self.handle_discriminator_mismatch(err).await?;
+            return Ok(None);
+        }
+        let nonce_status = NonceStatus::from_bytes(
+            account_data,
+            self.watched_nonce_offset,
+            self.nonce_endianness,
         )?;
+        if self.skip_unchanged_watched_account {
+            self.watched_account_cache = Some((account, nonce_status.nonce));
+        }
+        Ok(Some(nonce_status.nonce))
+    }
 
-        self.send_transaction_to_l2(transaction).await
+    /// With `relay_on_finalized_l1`, the watched nonce at `finalized`
+    /// commitment capped at the `confirmed` one, `None` while the account
+    /// isn't finalized yet. Otherwise the `confirmed` nonce unchanged
+    fn finalized_watched_nonce(&self, confirmed_nonce: u64) -> Result<Option<u64>> {
+        if !self.relay_on_finalized_l1 {
+            return Ok(Some(confirmed_nonce));
+        }
+        let account = self
+            .l1_client
+            .get_account_with_commitment(&self.watched_account, CommitmentConfig::finalized())
+            .map_err(RelayerError::from)?
+            .value;
+        let account_data = match account {
+            Some(account) if !account.data.is_empty() => account.data,
+            _ => {
+                println!(
+                    "Watched account {} isn't finalized yet, nothing to relay",
+                    self.watched_account
+                );
+                return Ok(None);
+            }
+        };
+        let finalized_nonce = NonceStatus::from_bytes(
+            &account_data,
+            self.watched_nonce_offset,
+            self.nonce_endianness,
+        )?
+        .nonce
+        .min(confirmed_nonce);
+        if finalized_nonce < confirmed_nonce {
+            println!(
+                "Watched nonce {} not finalized yet, relaying up to finalized nonce {}",
+                confirmed_nonce, finalized_nonce
+            );
+        }
+        Ok(Some(finalized_nonce))
     }
 
-    async fn send_transaction_to_l2(&self, transaction: Transaction) -> Result<()> {
-        println!("\nSending transaction to L2...");
-        match self.l2_client.send_and_confirm_transaction(&transaction) {
-            Ok(signature) => {
-                println!("Transaction successful! Signature: {}", signature);
+    /// Applies `on_transfer_error` to a nonce that failed to relay, returning
+    /// the error only if the cycle should stop
+    async fn handle_transfer_error(&mut self, nonce: u64, err: anyhow::Error) -> Result<()> {
+        match self.on_transfer_error {
+            TransferErrorPolicy::Halt => Err(err),
+            TransferErrorPolicy::SkipAndContinue => {
+                println!("Nonce {} failed, skipping: {}", nonce, err);
+                self.alerter
+                    .send(&format!(
+                        "Relay of nonce {} failed and was skipped: {}",
+                        nonce, err
+                    ))
+                    .await;
+                self.record_skip(SkipReason::TransferFailed);
+                self.advance_cursor(nonce)
+            }
+            TransferErrorPolicy::RetryLater => {
+                println!("Nonce {} failed, retrying next cycle: {}", nonce, err);
+                self.retry_queue.insert(nonce);
                 Ok(())
             }
-            Err(err) => {
-                println!("Transaction failed: {}", err);
-                if let Some(program_error) = err.get_transaction_error() {
-                    println!("Program error: {:?}", program_error);
+        }
+    }
+
+    /// Re-checks the nonces left in the startup rescan through the same
+    /// pacing, pending limit and error handling as any relay. A nonce stays
+    /// queued when the cycle stops before it or it failed under
+    /// `retry_later`. Returns false while the pending limit holds the rest
+    async fn continue_rescan(&mut self) -> Result<bool> {
+        let mut sent_any = false;
+        for nonce in self.rescan_queue.clone() {
+            if self.at_pending_limit().await? {
+                return Ok(false);
+            }
+            self.pace_submission(sent_any).await;
+            sent_any = true;
+            match self.send_l2_transfer(nonce).await {
+                Ok(()) => {
+                    self.rescan_queue.remove(&nonce);
+                }
+                Err(err) => {
+                    let retry = self.on_transfer_error == TransferErrorPolicy::RetryLater;
+                    self.handle_transfer_error(nonce, err).await?;
+                    if !retry {
+                        self.rescan_queue.remove(&nonce);
+                    }
                 }
-                Err(anyhow::anyhow!("L2 transaction failed: {}", err))
             }
         }
+        if sent_any && self.rescan_queue.is_empty() {
+            println!("Startup rescan complete");
+        }
+        Ok(true)
     }
-}
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    env_logger::init();
+    /// Cross-checks the watched nonce against the verifier endpoint, alerting
+    /// and returning `false` when they disagree beyond the tolerance
+    async fn verify_watched_nonce(&self, l1_watched_nonce: u64) -> Result<bool> {
+        let verifier_client = match &self.verifier_client {
+            Some(client) => client,
+            None => return Ok(true),
+        };
 
-    println!("Starting relayer...");
+        let account_data = verifier_client
+            .get_account_data(&self.watched_account)
+            .map_err(RelayerError::from)?;
+        let verified_nonce = NonceStatus::from_bytes(
+            &account_data,
+            self.watched_nonce_offset,
+            self.nonce_endianness,
+        )?
+        .nonce;
+        if l1_watched_nonce.abs_diff(verified_nonce) <= self.verifier_nonce_tolerance {
+            return Ok(true);
+        }
 
-    let config_path = std::env::current_dir()?.join("config.toml");
-    println!("Loading config from: {}", config_path.display());
+        self.alerter
+            .send_critical(&format!(
+                "Watched nonce {} disagrees with verifier endpoint nonce {} (tolerance {}), not relaying",
+                l1_watched_nonce, verified_nonce, self.verifier_nonce_tolerance
+            ))
+            .await;
+        Ok(false)
+    }
 
-    let config = RelayerConfig::load(config_path)?;
-    println!("Config loaded successfully");
-    println!("L1 URL: {}", config.l1_url);
-    println!("L2 URL: {}", config.l2_url);
+    /// Reads the cursor of every nonce shard and selector route
+    fn read_cursors(&self) -> Result<L2Cursors> {
+        // 每个 nonce 账户与其所属 L2 程序和路由成对读取
+        let (nonce_accounts, owners): (Vec<Pubkey>, Vec<(Pubkey, Option<u8>)>) =
+            self.transaction_builder
+                .nonce_accounts
+                .iter()
+                .map(|nonce_account| (*nonce_account, (self.transaction_builder.program_id, None)))
+                .chain(self.routes.iter().flat_map(|(selector, builder)| {
+                    builder.nonce_accounts.iter().map(|nonce_account| {
+                        (*nonce_account, (builder.program_id, Some(*selector)))
+                    })
+                }))
+                .unzip();
+        // 所有 nonce 账户通过一次 getMultipleAccounts 批量读取
+        let accounts = rpc::get_multiple_accounts(&self.l2_client, &nonce_accounts)?;
 
-    println!("Initializing relayer...");
-    let mut relayer = Relayer::new(&config)?;
-    println!("Relayer initialized successfully");
+        let mut cursors = L2Cursors::default();
+        for ((nonce_account, (program_id, selector)), account) in
+            nonce_accounts.iter().zip(owners).zip(accounts)
+        {
+            if self.verify_nonce_account_owner {
+                if let Some(account) = &account {
+                    if account.owner != program_id {
+                        return Err(NonceAccountOwnerMismatch {
+                            account: *nonce_account,
+                            expected: program_id,
+                            found: account.owner,
+                        }
+                        .into());
+                    }
+                }
+            }
+            let nonce = self.parse_l2_nonce(nonce_account, account)?;
+            match selector {
+                Some(selector) => {
+                    cursors.routes.insert(selector, nonce);
+                }
+                None => cursors.shards.push(nonce),
+            }
+        }
+        // 有路由时消息都发往各路由，较快的路由不能让较慢路由的 nonce 被跳过
+        if let Some(slowest) = cursors.routes.values().copied().min() {
+            cursors.shards = vec![slowest];
+        }
+        Ok(cursors)
+    }
+
+    /// Tracks whether a `maintenance_windows` entry is open, logging when
+    /// one opens or closes
+    fn update_maintenance(&mut self) {
+        let now = chrono::Utc::now();
+        let in_maintenance = self
+            .maintenance_windows
+            .iter()
+            .any(|window| window.contains(now));
+        if in_maintenance != self.in_maintenance {
+            if in_maintenance {
+                println!("Entering maintenance window, submissions paused");
+            } else {
+                println!("Maintenance window over, resuming relaying");
+            }
+            self.in_maintenance = in_maintenance;
+        }
+    }
 
-    println!("Starting monitoring...");
-    relayer.monitor_and_relay().await?;
+    /// With `trust_local_cursor`, the persisted cursor to use instead of
+    /// reading the L2 nonce account, `None` on a reconcile cycle
+    fn local_cursor(&mut self) -> Option<u64> {
+        if !self.trust_local_cursor || !self.cursor_reconciled {
+            return None;
+        }
+        self.cycles_since_reconcile += 1;
+        if self.cycles_since_reconcile >= self.l2_reconcile_every {
+            self.cycles_since_reconcile = 0;
+            return None;
+        }
+        self.last_nonce
+    }
 
-    Ok(())
+    /// End of the nonce range handled this cycle, honouring `max_nonces_per_run`
+    fn run_end(&self, cursor: u64, l1_watched_nonce: u64) -> u64 {
+        match self.max_nonces_per_run {
+            Some(max) => l1_watched_nonce.min(cursor.saturating_add(max)),
+            None => l1_watched_nonce,
+        }
+    }
+
+    /// Whether L2 would accept the transaction right now
+    fn would_succeed(&self, transaction: &Transaction) -> Result<bool> {
+        let result = self
+            .l2_client
+            .simulate_transaction(transaction)
+            .map_err(RelayerError::from)?;
+        tracing::info!(error = ?result.value.err, "simulated relay transaction");
+        Ok(result.value.err.is_none())
+    }
+
+    /// Simulates every pending nonce against L2 without submitting anything
+    /// and prints whether each would be accepted
+    fn simulate_backlog(&self) -> Result<()> {
+        let account_data = self
+            .l1_client
+            .get_account_data(&self.watched_account)
+            .map_err(RelayerError::from)?;
+        check_discriminator(
+            &self.watched_account,
+            &account_data,
+            self.watched_account_discriminator.as_ref(),
+        )?;
+        let l1_watched_nonce = NonceStatus::from_bytes(
+            &account_data,
+            self.watched_nonce_offset,
+            self.nonce_endianness,
+        )?
+        .nonce;
+
+        let cursors = self.read_cursors()?;
+        let cursor = cursors.min();
+        let end = self.run_end(cursor, l1_watched_nonce);
+        if end < l1_watched_nonce {
+            println!(
+                "Backlog has {} nonces, simulating the first {} (max_nonces_per_run)",
+                l1_watched_nonce - cursor,
+                end - cursor
+            );
+        }
+
+        println!("{:>10}  {:<12}  reason", "nonce", "outcome");
+        let (mut succeeded, mut failed) = (0, 0);
+        for nonce in cursor..end {
+            if nonce < cursors.shards[self.transaction_builder.shard_for(nonce)] {
+                continue;
+            }
+            let (outcome, reason) = if self.skipped_nonces.contains(&nonce) {
+                ("skipped", String::new())
+            } else {
+                match self.simulate_nonce(nonce, &cursors) {
+                    Ok(false) => continue,
+                    Ok(true) => {
+                        succeeded += 1;
+                        ("would-succeed", String::new())
+                    }
+                    Err(err) => {
+                        failed += 1;
+                        ("would-fail", format!("{:#}", err))
+                    }
+                }
+            };
+            println!("{:>10}  {:<12}  {}", nonce, outcome, reason);
+        }
+        println!(
+            "Simulated {} nonces: {} would succeed, {} would fail",
+            succeeded + failed,
+            succeeded,
+            failed
+        );
+
+        Ok(())
+    }
+
+    /// Why `relay_filter` rejects a parsed transfer, `None` if it passes
+    fn filter_rejection(&self, nonce: u64, transfer_info: &TransferInfo) -> Option<String> {
+        self.relay_filter
+            .as_ref()
+            .and_then(|filter| filter.rejects(nonce, transfer_info))
+    }
+
+    /// Builds and signs the relays of nonces `from..to` without submitting
+    /// them, skipping nonces the online path wouldn't relay: skipped by an
+    /// operator, without a message PDA, or skipped by `zero_amount_policy`
+    /// or `relay_filter`
+    fn build_and_sign(&self, from: u64, to: u64) -> Result<Vec<SignedRelay>> {
+        if self.transaction_builder.options.durable_nonce.is_none() {
+            println!(
+                "Warning: without durable_nonce_account the signed relays expire with their blockhash in about a minute"
+            );
+        }
+
+        let mut relays = Vec::new();
+        for nonce in from..to {
+            if self.skipped_nonces.contains(&nonce) {
+                println!("Nonce {} skipped: {}", nonce, SkipReason::OperatorSkipped);
+                continue;
+            }
+            let (pda, bump) = self.pda_manager.find_address(nonce);
+            let account = match self.pda_manager.fetch_account(&self.l1_client, &pda)? {
+                Some(account) => account,
+                None => {
+                    println!("Nonce {} skipped: {}", nonce, SkipReason::MissingPda);
+                    continue;
+                }
+            };
+            let transfer_info = self.pda_manager.get_transfer_info(&pda, &account, nonce)?;
+            if transfer_info.amount == 0 && self.zero_amount_policy != ZeroAmountPolicy::RelayAnyway
+            {
+                println!("Nonce {} skipped: {}", nonce, SkipReason::ZeroAmount);
+                continue;
+            }
+            if let Some(reason) = self.filter_rejection(nonce, &transfer_info) {
+                println!(
+                    "Nonce {} skipped: {} ({})",
+                    nonce,
+                    SkipReason::Filtered,
+                    reason
+                );
+                continue;
+            }
+            let transaction = self
+                .builder_for(transfer_info.selector)?
+                .build_transfer_transaction(
+                    transfer_info.amount,
+                    nonce,
+                    bump,
+                    &transfer_info.to,
+                    &self.signers(),
+                    &self.l2_client,
+                )?;
+            relays.push(SignedRelay::new(nonce, &transaction)?);
+        }
+        Ok(relays)
+    }
+
+    /// Builds the relay transaction for a nonce and simulates it on L2,
+    /// returning `false` if its route already relayed it
+    fn simulate_nonce(&self, nonce: u64, cursors: &L2Cursors) -> Result<bool> {
+        let (pda, bump) = self.pda_manager.find_address(nonce);
+        let account = self
+            .pda_manager
+            .fetch_account(&self.l1_client, &pda)?
+            .ok_or_else(|| anyhow::anyhow!("message PDA {} not found", pda))?;
+        let transfer_info = self.pda_manager.get_transfer_info(&pda, &account, nonce)?;
+        let shard = self.transaction_builder.shard_for(nonce);
+        if nonce < cursors.destination(shard, transfer_info.selector) {
+            return Ok(false);
+        }
+
+        let transaction = self
+            .builder_for(transfer_info.selector)?
+            .build_transfer_transaction(
+                transfer_info.amount,
+                nonce,
+                bump,
+                &transfer_info.to,
+                &self.signers(),
+                &self.l2_client,
+            )?;
+
+        let result = self
+            .l2_client
+            .simulate_transaction(&transaction)
+            .map_err(RelayerError::from)?
+            .value;
+        match result.err {
+            None => Ok(true),
+            Some(err) => {
+                // 最后一条程序日志通常包含拒绝原因
+                let reason = match result.logs.and_then(|logs| logs.into_iter().last()) {
+                    Some(log) => format!("{} ({})", err, log),
+                    None => err.to_string(),
+                };
+                Err(anyhow::anyhow!(reason))
+            }
+        }
+    }
+
+    /// Reconciles the persisted cursor with the on-chain L2 nonce on the first cycle
+    fn reconcile_cursor(&mut self, chain_nonce: u64) -> Result<()> {
+        let local_nonce = match self.last_nonce {
+            Some(local_nonce) => local_nonce,
+            None => {
+                // 没有持久化状态时，过大的初始 nonce 往往说明 nonce_account 配置错误
+                if let Some(max_jump) = self.max_initial_nonce_jump {
+                    if chain_nonce > max_jump && !self.allow_large_initial_nonce {
+                        return Err(anyhow::anyhow!(
+                            "First observed on-chain nonce {} exceeds max_initial_nonce_jump {} with no persisted state, \
+                             verify nonce_account and set allow_large_initial_nonce = true to proceed",
+                            chain_nonce,
+                            max_jump
+                        ));
+                    }
+                }
+                println!(
+                    "No persisted cursor, starting from on-chain nonce {}",
+                    chain_nonce
+                );
+                self.last_nonce = Some(chain_nonce);
+                return Ok(());
+            }
+        };
+
+        let divergence = local_nonce.abs_diff(chain_nonce);
+        if divergence <= self.cursor_divergence_threshold {
+            if local_nonce != chain_nonce {
+                println!(
+                    "Updating last_nonce from {} to {}",
+                    local_nonce, chain_nonce
+                );
+            }
+            self.last_nonce = Some(chain_nonce);
+            return Ok(());
+        }
+
+        println!(
+            "\n!!! CURSOR DIVERGENCE: persisted last_nonce {} vs on-chain nonce {} (difference {}, threshold {})",
+            local_nonce, chain_nonce, divergence, self.cursor_divergence_threshold
+        );
+        match self.cursor_divergence_policy {
+            CursorDivergencePolicy::TrustChain => {
+                println!("Policy trust_chain: adopting on-chain nonce {}", chain_nonce);
+                self.last_nonce = Some(chain_nonce);
+                Ok(())
+            }
+            CursorDivergencePolicy::TrustLocal => {
+                println!("Policy trust_local: resuming from persisted nonce {}", local_nonce);
+                Ok(())
+            }
+            CursorDivergencePolicy::Halt => Err(anyhow::anyhow!(
+                "Persisted cursor {} diverges from on-chain nonce {} by {}, halting per cursor_divergence_policy",
+                local_nonce,
+                chain_nonce,
+                divergence
+            )),
+        }
+    }
+
+    /// Applies `discriminator_mismatch_policy` to a discriminator mismatch,
+    /// propagating any other error unchanged
+    async fn handle_discriminator_mismatch(&self, err: anyhow::Error) -> Result<()> {
+        let mismatch = match err.downcast_ref::<DiscriminatorMismatch>() {
+            Some(mismatch) => mismatch,
+            None => return Err(err),
+        };
+
+        match self.discriminator_mismatch_policy {
+            DiscriminatorMismatchPolicy::Skip => {
+                println!("{}, skipping", mismatch);
+                Ok(())
+            }
+            DiscriminatorMismatchPolicy::AlertAndSkip => {
+                self.alerter.send(&format!("{}, skipping", mismatch)).await;
+                Ok(())
+            }
+            DiscriminatorMismatchPolicy::Halt => {
+                Err(err.context("Halting per discriminator_mismatch_policy"))
+            }
+        }
+    }
+
+    /// Persists the relay cursor when a state file is configured, keeping
+    /// skips written by `skip-nonce` since the state was last read
+    fn save_state(&self) -> Result<()> {
+        if let Some(state_path) = &self.state_path {
+            let mut skipped_nonces = self.skipped_nonces.clone();
+            if let Some(state) = RelayerState::load(state_path)? {
+                skipped_nonces.extend(state.skipped_nonces);
+            }
+            RelayerState {
+                last_nonce: self.last_nonce,
+                missing_pdas: self.missing_pdas.clone(),
+                skipped_nonces,
+                pending_confirmations: self.pending_confirmations.clone(),
+                emergency_paused: self.emergency_paused,
+                emergency_resumed: self.emergency_resumed,
+            }
+            .save(state_path)?;
+        }
+        Ok(())
+    }
+
+    async fn send_l2_transfer(&mut self, nonce: u64) -> Result<()> {
+        self.begin_decision(nonce);
+        let result = async {
+            let (transfer_info, bump) = match self.load_transfer(nonce).await? {
+                Some(transfer) => transfer,
+                None => return Ok(()),
+            };
+            self.relay_transfer(nonce, transfer_info, bump).await
+        }
+        .instrument(relay_span(nonce))
+        .await;
+        self.finish_decision(&result);
+        // 追赶大量 nonce 的一轮可能超过 deadman 超时
+        self.heartbeat.beat();
+        result
+    }
+
+    /// Starts tracing the decision path of a nonce, with `decision_trace`
+    fn begin_decision(&mut self, nonce: u64) {
+        if self.decision_trace {
+            self.decision = Some(Decision::new(nonce));
+        }
+    }
+
+    /// Updates the decision being traced, if any
+    fn trace(&mut self, update: impl FnOnce(&mut Decision)) {
+        if let Some(decision) = &mut self.decision {
+            update(decision);
+        }
+    }
+
+    /// Emits the traced decision once the nonce has been handled
+    fn finish_decision(&mut self, result: &Result<()>) {
+        if let Some(mut decision) = self.decision.take() {
+            if let Err(err) = result {
+                decision.decide(Action::Failed, err.to_string());
+            }
+            decision.emit();
+        }
+    }
+
+    /// Counts a skipped nonce and records the reason in the decision trace
+    fn record_skip(&mut self, reason: SkipReason) {
+        self.metrics.record_skip(reason);
+        self.trace(|decision| decision.decide(Action::Skipped, reason.label()));
+    }
+
+    /// Relays a message parsed from an L1 program log event
+    async fn relay_event(&mut self, event: &MessageEvent) -> Result<()> {
+        let nonce = event.nonce;
+        if self.skipped_nonces.contains(&nonce) {
+            println!("Nonce {} skipped: {}", nonce, SkipReason::OperatorSkipped);
+            self.record_skip(SkipReason::OperatorSkipped);
+            return self.advance_cursor(nonce);
+        }
+        if self.pending_confirmations.contains_key(&nonce) {
+            self.trace(|decision| {
+                decision.decide(Action::Deferred, "earlier relay awaiting confirmation")
+            });
+            return Ok(());
+        }
+
+        // 刷新分片 cursor，已中继过的事件在提交前会被模拟检查拦下
+        self.cursors = self.read_cursors()?;
+        let (to, owner) = self.pda_manager.destination(event.to);
+        let (_, bump) = self.pda_manager.find_address(nonce);
+        let transfer_info = TransferInfo {
+            amount: event.amount,
+            to,
+            selector: None,
+            owner,
+        };
+        if self.filtered(nonce, &transfer_info)? {
+            return Ok(());
+        }
+        self.relay_transfer(nonce, transfer_info, bump)
+            .instrument(relay_span(nonce))
+            .await
+    }
+
+    /// Builds, submits and confirms the relay of one loaded transfer
+    async fn relay_transfer(
+        &mut self,
+        nonce: u64,
+        transfer_info: TransferInfo,
+        bump: u8,
+    ) -> Result<()> {
+        self.trace(|decision| {
+            decision.amount = Some(transfer_info.amount);
+            decision.destination = Some(transfer_info.to.to_string());
+        });
+        self.ensure_destination_ata(&transfer_info)?;
+        let mut instructions = match self.destination_instructions(&transfer_info)? {
+            Some(instructions) => instructions,
+            None => {
+                println!(
+                    "Nonce {} skipped: {}",
+                    nonce,
+                    SkipReason::MissingDestination
+                );
+                self.record_skip(SkipReason::MissingDestination);
+                return Ok(());
+            }
+        };
+
+        // 等待时间越长优先费越高
+        let pending_for = self
+            .nonce_first_seen
+            .entry(nonce)
+            .or_insert_with(Instant::now)
+            .elapsed();
+        // 按 selector 选择目标并构建交易
+        let builder = self.builder_for(transfer_info.selector)?;
+        let mut signers = self.signers();
+        let creates_destination = !instructions.is_empty();
+        instructions.push(builder.transfer_instruction(
+            transfer_info.amount,
+            nonce,
+            bump,
+            &transfer_info.to,
+            &signers,
+        )?);
+        instructions.extend(builder.memo_instruction(
+            transfer_info.amount,
+            nonce,
+            &transfer_info.to,
+        ));
+        // 创建目标账户时由 rent payer 出资，需要它一同签名
+        if creates_destination {
+            if let Some(rent_payer) = &self.rent_payer {
+                signers.push(rent_payer);
+            }
+        }
+        let transaction =
+            builder.build_transaction(&instructions, &signers, &self.l2_client, pending_for)?;
+        let signer_keys: Vec<Pubkey> = signers.iter().map(|signer| signer.pubkey()).collect();
+        tracing::info!(
+            amount = transfer_info.amount,
+            to = %transfer_info.to,
+            "built relay transaction"
+        );
+
+        // cursor 之前的 nonce 只在重新扫描时出现，先模拟确认尚未上链，避免重复提交
+        let rescanned = nonce
+            < self.cursors.destination(
+                self.transaction_builder.shard_for(nonce),
+                transfer_info.selector,
+            );
+        if rescanned {
+            self.trace(|decision| decision.policies.push("rescan_simulation"));
+        }
+        if rescanned && !self.would_succeed(&transaction)? {
+            println!("Nonce {} skipped: {}", nonce, SkipReason::AlreadyRelayed);
+            self.record_skip(SkipReason::AlreadyRelayed);
+            return Ok(());
+        }
+
+        let transfer = PendingTransfer {
+            selector: transfer_info.selector,
+            amount: transfer_info.amount,
+            destination: transfer_info.to.to_string(),
+        };
+        let signature = match self
+            .send_transaction_to_l2(nonce, transaction, &signer_keys)
+            .await?
+        {
+            Some(signature) => signature,
+            None => {
+                self.track_pending_transfer(&[nonce], transfer)?;
+                self.trace(|decision| decision.decide(Action::Deferred, "relay unconfirmed"));
+                return Ok(());
+            }
+        };
+        if !self.complete_relay(&[nonce], signature, &transfer).await? {
+            self.trace(|decision| {
+                decision.decide(Action::Failed, "nonce account unchanged after relay")
+            });
+            return Ok(());
+        }
+        self.trace(|decision| {
+            decision.decide(Action::Relayed, "confirmed");
+            decision.signature = Some(signature.to_string());
+        });
+
+        Ok(())
+    }
+
+    /// Instructions to run ahead of the relay under `on_missing_destination`,
+    /// `None` if the transfer should be skipped because its destination
+    /// account doesn't exist
+    fn destination_instructions(
+        &mut self,
+        transfer_info: &TransferInfo,
+    ) -> Result<Option<Vec<Instruction>>> {
+        // 代币模式下由 ensure_destination_ata 负责目标账户
+        if self.on_missing_destination == MissingDestinationPolicy::Relay
+            || transfer_info.owner.is_some()
+        {
+            return Ok(Some(Vec::new()));
+        }
+
+        self.trace(|decision| decision.policies.push("on_missing_destination"));
+        let exists = self
+            .l2_client
+            .get_account_with_commitment(&transfer_info.to, self.l2_client.commitment())
+            .map_err(RelayerError::from)?
+            .value
+            .is_some();
+        if exists {
+            return Ok(Some(Vec::new()));
+        }
+
+        match self.on_missing_destination {
+            MissingDestinationPolicy::Create => {
+                // 转入免租金额即可创建系统账户
+                let rent_exempt = match self.destination_rent_exempt {
+                    Some(lamports) => lamports,
+                    None => {
+                        let lamports = self
+                            .l2_client
+                            .get_minimum_balance_for_rent_exemption(0)
+                            .map_err(RelayerError::from)?;
+                        self.destination_rent_exempt = Some(lamports);
+                        lamports
+                    }
+                };
+                println!(
+                    "Destination {} doesn't exist, funding it with {} lamports",
+                    transfer_info.to, rent_exempt
+                );
+                Ok(Some(vec![system_instruction::transfer(
+                    &self.rent_payer().pubkey(),
+                    &transfer_info.to,
+                    rent_exempt,
+                )]))
+            }
+            _ => {
+                println!(
+                    "Warning: destination {} doesn't exist, skipping",
+                    transfer_info.to
+                );
+                Ok(None)
+            }
+        }
+    }
+
+    /// Checks that the destination associated token account exists, creating
+    /// it when `create_destination_ata` is set
+    fn ensure_destination_ata(&mut self, transfer_info: &TransferInfo) -> Result<()> {
+        let (owner, mint) = match (transfer_info.owner, self.destination_mint) {
+            (Some(owner), Some(mint)) => (owner, mint),
+            _ => return Ok(()),
+        };
+        if self.known_atas.contains(&transfer_info.to) {
+            return Ok(());
+        }
+
+        let exists = self
+            .l2_client
+            .get_account_with_commitment(&transfer_info.to, self.l2_client.commitment())
+            .map_err(RelayerError::from)?
+            .value
+            .is_some();
+        if !exists {
+            if !self.create_destination_ata {
+                return Err(anyhow::anyhow!(
+                    "Destination token account {} of {} for mint {} doesn't exist",
+                    transfer_info.to,
+                    owner,
+                    mint
+                ));
+            }
+
+            println!(
+                "Creating destination token account {} of {} for mint {}",
+                transfer_info.to, owner, mint
+            );
+            let payer = self.signer.pubkey();
+            let instruction = create_associated_token_account_idempotent(
+                &self.rent_payer().pubkey(),
+                &owner,
+                &mint,
+                &spl_token::id(),
+            );
+            let recent_blockhash = self
+                .l2_client
+                .get_latest_blockhash()
+                .map_err(RelayerError::from)?;
+            let mut signers = vec![self.signer.as_ref() as &dyn Signer];
+            signers.extend(
+                self.rent_payer
+                    .as_ref()
+                    .map(|rent_payer| rent_payer as &dyn Signer),
+            );
+            let transaction = Transaction::new_signed_with_payer(
+                &[instruction],
+                Some(&payer),
+                &signers,
+                recent_blockhash,
+            );
+            let signature = self
+                .l2_client
+                .send_and_confirm_transaction(&transaction)
+                .map_err(RelayerError::from)?;
+            println!("Destination token account created: {}", signature);
+        }
+
+        self.known_atas.insert(transfer_info.to);
+        Ok(())
+    }
+
+    /// Relays the pending nonces as one summed transfer per destination, each
+    /// carrying the highest nonce of its group
+    async fn relay_aggregated(&mut self, pending: &[u64]) -> Result<()> {
+        let mut transfers = Vec::new();
+        for &nonce in pending {
+            let (transfer_info, bump) = match self.load_transfer(nonce).await? {
+                Some(transfer) => transfer,
+                None => continue,
+            };
+            self.ensure_destination_ata(&transfer_info)?;
+            transfers.push((nonce, transfer_info, bump));
+        }
+
+        for ((selector, to), group) in AggregatedTransfer::group(transfers)? {
+            if self.at_pending_limit().await? {
+                break;
+            }
+            self.pace_submission(false).await;
+            println!(
+                "\nRelaying nonces {:?} to {} as one transfer of {}",
+                group.nonces, to, group.amount
+            );
+            // 整组按最早出现的 nonce 计算优先费
+            let pending_for = group
+                .nonces
+                .iter()
+                .filter_map(|nonce| self.nonce_first_seen.get(nonce))
+                .map(Instant::elapsed)
+                .max()
+                .unwrap_or_default();
+            let builder = self.builder_for(selector)?;
+            let signers = self.signers();
+            let instruction = builder.transfer_instruction(
+                group.amount,
+                group.nonce,
+                group.bump,
+                &to,
+                &signers,
+            )?;
+            let mut instructions = vec![instruction];
+            instructions.extend(builder.memo_instruction(group.amount, group.nonce, &to));
+            let transaction =
+                builder.build_transaction(&instructions, &signers, &self.l2_client, pending_for)?;
+            let signer_keys: Vec<Pubkey> = signers.iter().map(|signer| signer.pubkey()).collect();
+            let transfer = PendingTransfer {
+                selector,
+                amount: group.amount,
+                destination: to.to_string(),
+            };
+
+            let result = self
+                .send_transaction_to_l2(group.nonce, transaction, &signer_keys)
+                .instrument(relay_span(group.nonce))
+                .await;
+            self.heartbeat.beat();
+            let signature = match result {
+                Ok(Some(signature)) => signature,
+                // 失败的合并交易没有上链，逐个重发找出出错的转账
+                Err(err) if self.split_failed_batches && group.nonces.len() > 1 => {
+                    println!(
+                        "Aggregated relay of nonces {:?} failed, relaying them one by one: {}",
+                        group.nonces, err
+                    );
+                    for nonce in group.nonces {
+                        if let Err(err) = self.send_l2_transfer(nonce).await {
+                            self.handle_transfer_error(nonce, err).await?;
+                        }
+                    }
+                    continue;
+                }
+                Err(err) => return Err(err),
+                Ok(None) => {
+                    // 整组共用同一笔交易，全部等待确认，避免部分 nonce 被重复合并发送
+                    self.track_pending_transfer(&group.nonces, transfer)?;
+                    continue;
+                }
+            };
+            self.complete_relay(&group.nonces, signature, &transfer)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// With `verify_state_change`, checks that the nonce account moved past
+    /// a confirmed relay, alerting when it didn't
+    async fn state_changed(
+        &self,
+        selector: Option<u8>,
+        nonce: u64,
+        signature: &Signature,
+    ) -> Result<bool> {
+        if !self.verify_state_change {
+            return Ok(true);
+        }
+
+        let nonce_account = self.builder_for(selector)?.nonce_account_for(nonce);
+        let account = self
+            .l2_client
+            .get_account(&nonce_account)
+            .map_err(RelayerError::from)?;
+        let cursor = self.parse_l2_nonce(&nonce_account, Some(account))?;
+        if cursor > nonce {
+            return Ok(true);
+        }
+
+        self.alerter
+            .send(&format!(
+                "Relay of nonce {} confirmed in {} but nonce account {} is still at {}",
+                nonce, signature, nonce_account, cursor
+            ))
+            .await;
+        Ok(false)
+    }
+
+    /// Reads and parses the message PDA of a nonce, returning `None` when the
+    /// nonce shouldn't be relayed this cycle
+    async fn load_transfer(&mut self, nonce: u64) -> Result<Option<(TransferInfo, u8)>> {
+        // 运维人员标记为永久跳过的 nonce 视为已处理
+        self.trace(|decision| decision.policies.push("skip_set"));
+        if self.skipped_nonces.contains(&nonce) {
+            println!("Nonce {} skipped: {}", nonce, SkipReason::OperatorSkipped);
+            self.record_skip(SkipReason::OperatorSkipped);
+            self.advance_cursor(nonce)?;
+            return Ok(None);
+        }
+        if self.missing_pdas.contains(&nonce) {
+            self.trace(|decision| {
+                decision.policies.push("cache_missing_pdas");
+                decision.decide(Action::Deferred, "message PDA cached as missing");
+            });
+            return Ok(None);
+        }
+        // 超时未确认的交易可能仍会上链，等待确认结果再决定是否重发
+        if self.pending_confirmations.contains_key(&nonce) {
+            self.trace(|decision| {
+                decision.decide(Action::Deferred, "earlier relay awaiting confirmation")
+            });
+            return Ok(None);
+        }
+
+        println!("\nPreparing L2 transfer for nonce: {}", nonce);
+        let (pda, bump) = self.pda_manager.find_address(nonce);
+        self.trace(|decision| decision.pda = Some(pda.to_string()));
+
+        // 只获取一次 PDA 账户，同时用于存在性检查和解析
+        let account = match self.prefetched_pdas.remove(&pda) {
+            Some(account) => Some(account),
+            None => self.pda_manager.fetch_account(&self.l1_client, &pda)?,
+        };
+        self.trace(|decision| decision.pda_exists = Some(account.is_some()));
+        let account = match account {
+            Some(account) => account,
+            None => {
+                // 如果账户不存在，跳过这个nonce
+                self.record_skip(SkipReason::MissingPda);
+                if self.missing_pda_is_gap(nonce).await && self.cache_missing_pdas {
+                    self.missing_pdas.insert(nonce);
+                }
+                return Ok(None);
+            }
+        };
+        self.missing_first_seen.remove(&nonce);
+        self.confirmed_gaps.remove(&nonce);
+
+        // 获取转账信息，新建的 PDA 可能还没有完整同步到当前 RPC 节点
+        let mut account = account;
+        let mut retries = 0;
+        let transfer_info = loop {
+            match self.pda_manager.get_transfer_info(&pda, &account, nonce) {
+                Ok(transfer_info) => break transfer_info,
+                Err(err) if err.is::<InsufficientAccountData>() => {
+                    if retries >= self.pda_read_retries {
+                        println!(
+                            "Warning: skipping nonce {} after {} re-reads of PDA {}: {}",
+                            nonce, retries, pda, err
+                        );
+                        self.record_skip(SkipReason::IncompletePda);
+                        return Ok(None);
+                    }
+                    retries += 1;
+                    time::sleep(self.pda_read_retry_delay).await;
+                    account = match self.pda_manager.fetch_account(&self.l1_client, &pda)? {
+                        Some(account) => account,
+                        None => {
+                            self.record_skip(SkipReason::MissingPda);
+                            return Ok(None);
+                        }
+                    };
+                }
+                Err(err) => {
+                    self.handle_discriminator_mismatch(err).await?;
+                    self.record_skip(SkipReason::DiscriminatorMismatch);
+                    return Ok(None);
+                }
+            }
+        };
+        self.trace(|decision| {
+            decision.amount = Some(transfer_info.amount);
+            decision.destination = Some(transfer_info.to.to_string());
+        });
+
+        // 每个路由有自己的 cursor，较快路由已越过的 nonce 不再转发
+        let shard = self.transaction_builder.shard_for(nonce);
+        if nonce >= self.cursors.shards[shard]
+            && nonce < self.cursors.destination(shard, transfer_info.selector)
+        {
+            println!("Nonce {} skipped: {}", nonce, SkipReason::AlreadyRelayed);
+            self.record_skip(SkipReason::AlreadyRelayed);
+            self.advance_cursor(nonce)?;
+            return Ok(None);
+        }
+        if transfer_info.amount == 0 && self.skip_zero_amount(nonce)? {
+            return Ok(None);
+        }
+        if self.filtered(nonce, &transfer_info)? {
+            return Ok(None);
+        }
+
+        Ok(Some((transfer_info, bump)))
+    }
+
+    /// Applies `zero_amount_policy` to a zero amount transfer, returning
+    /// whether it was skipped. Skipped nonces advance the cursor
+    fn skip_zero_amount(&mut self, nonce: u64) -> Result<bool> {
+        self.trace(|decision| decision.policies.push("zero_amount_policy"));
+        match self.zero_amount_policy {
+            ZeroAmountPolicy::SkipSilently => {}
+            ZeroAmountPolicy::SkipWithLog => {
+                println!("Nonce {} skipped: {}", nonce, SkipReason::ZeroAmount);
+            }
+            ZeroAmountPolicy::RelayAnyway => return Ok(false),
+        }
+        self.record_skip(SkipReason::ZeroAmount);
+        self.advance_cursor(nonce)?;
+        Ok(true)
+    }
+
+    /// Applies `relay_filter` to a parsed transfer, returning whether it was
+    /// filtered out. Filtered nonces hold the cursor unless `advance_past` is set
+    fn filtered(&mut self, nonce: u64, transfer_info: &TransferInfo) -> Result<bool> {
+        let advance_past = match &self.relay_filter {
+            Some(filter) => filter.advance_past,
+            None => return Ok(false),
+        };
+        let reason = self.filter_rejection(nonce, transfer_info);
+        self.trace(|decision| decision.policies.push("relay_filter"));
+        let reason = match reason {
+            Some(reason) => reason,
+            None => return Ok(false),
+        };
+
+        println!(
+            "Nonce {} skipped: {} ({})",
+            nonce,
+            SkipReason::Filtered,
+            reason
+        );
+        self.record_skip(SkipReason::Filtered);
+        if advance_past {
+            self.advance_cursor(nonce)?;
+        }
+        Ok(true)
+    }
+
+    /// Whether a missing message PDA counts as a gap rather than one that
+    /// hasn't propagated yet, alerting once it outlasts `pda_missing_grace_secs`
+    async fn missing_pda_is_gap(&mut self, nonce: u64) -> bool {
+        let grace = match self.pda_missing_grace {
+            Some(grace) => grace,
+            None => return true,
+        };
+        if self.confirmed_gaps.contains(&nonce) {
+            return true;
+        }
+        let first_seen = *self
+            .missing_first_seen
+            .entry(nonce)
+            .or_insert_with(Instant::now);
+        let missing_for = first_seen.elapsed();
+        if missing_for < grace {
+            println!(
+                "Message PDA for nonce {} missing for {}s, within the grace period",
+                nonce,
+                missing_for.as_secs()
+            );
+            return false;
+        }
+
+        self.missing_first_seen.remove(&nonce);
+        self.confirmed_gaps.insert(nonce);
+        self.alerter
+            .send(&format!(
+                "Message PDA for nonce {} still missing after {}s, treating it as a gap",
+                nonce,
+                missing_for.as_secs()
+            ))
+            .await;
+        true
+    }
+
+    /// Closes a relayed nonce's message PDA on L1 with `close_pda_after_relay`,
+    /// signed by the L1 wallet. The relay already confirmed, so failures are
+    /// only alerted on
+    async fn close_pda(&self, nonce: u64) {
+        let l1_wallet = match &self.l1_wallet {
+            Some(l1_wallet) if self.transaction_builder.options.close_pda.is_some() => l1_wallet,
+            _ => return,
+        };
+        let (pda, _) = self.pda_manager.find_address(nonce);
+        let result = self
+            .l1_client
+            .get_latest_blockhash()
+            .map_err(anyhow::Error::from)
+            .and_then(|recent_blockhash| {
+                self.transaction_builder.close_pda_transaction(
+                    &pda,
+                    nonce,
+                    l1_wallet,
+                    recent_blockhash,
+                )
+            })
+            .and_then(|transaction| {
+                Ok(self.l1_client.send_and_confirm_transaction(&transaction)?)
+            });
+        match result {
+            Ok(signature) => println!(
+                "Closed message PDA {} of nonce {}: {}",
+                pda, nonce, signature
+            ),
+            Err(err) => {
+                self.alerter
+                    .send(&format!(
+                        "Failed to close message PDA {} of relayed nonce {}: {}",
+                        pda, nonce, err
+                    ))
+                    .await
+            }
+        }
+    }
+
+    /// Reports a confirmed relay to the event sink, relay history and
+    /// success webhook, if any is configured
+    async fn notify_relayed(&mut self, nonce: u64, signature: Signature, amount: u64, to: &Pubkey) {
+        if !self.alerter.notifies_success()
+            && self.event_sink.is_none()
+            && self.relay_history.is_none()
+        {
+            return;
+        }
+        let slot = self
+            .l2_client
+            .get_signature_statuses(&[signature])
+            .ok()
+            .and_then(|statuses| statuses.value.into_iter().next().flatten())
+            .map(|status| status.slot);
+
+        let event = RelayEvent::new(slot, signature.to_string(), nonce, amount, to.to_string());
+        if let Some(sink) = self.event_sink.as_mut() {
+            if let Err(err) = sink.emit(&event) {
+                println!("Failed to emit relay event for nonce {}: {}", nonce, err);
+            }
+        }
+        if let Some(history) = &self.relay_history {
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs() as i64)
+                .unwrap_or_default();
+            if let Err(err) = history.record(&event, STATUS_CONFIRMED, timestamp) {
+                println!(
+                    "Warning: failed to record nonce {} in relay history: {}",
+                    nonce, err
+                );
+            }
+        }
+        if self.alerter.notifies_success() {
+            let notification = RelayNotification {
+                nonce,
+                signature: signature.to_string(),
+                amount,
+                destination: to.to_string(),
+                slot,
+            };
+            if self.success_webhook_batch {
+                self.pending_notifications.push(notification);
+            } else {
+                self.alerter.notify_success(&[notification]).await;
+            }
+        }
+    }
+
+    /// Alerts on a relay that didn't confirm in time and tracks it so later
+    /// cycles re-check it instead of forgetting or resubmitting it
+    async fn escalate_unconfirmed(
+        &mut self,
+        nonce: u64,
+        signature: Signature,
+        recent_blockhash: Hash,
+        submitted: Instant,
+    ) -> Result<()> {
+        println!(
+            "Transaction {} for nonce {} still unconfirmed after {}s",
+            signature,
+            nonce,
+            self.confirm_timeout.unwrap_or_default().as_secs()
+        );
+        self.alerter
+            .send(&format!(
+                "Relay of nonce {} unconfirmed after {}s, check signature {} in the explorer",
+                nonce,
+                self.confirm_timeout.unwrap_or_default().as_secs(),
+                signature
+            ))
+            .await;
+
+        self.pending_confirmations.insert(
+            nonce,
+            PendingConfirmation {
+                signature: signature.to_string(),
+                recent_blockhash: recent_blockhash.to_string(),
+                transfer: None,
+                submitted_at: SystemTime::now()
+                    .checked_sub(submitted.elapsed())
+                    .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                    .map(|elapsed| elapsed.as_secs()),
+            },
+        );
+        self.save_state()
+    }
+
+    /// Records the transfer of a relay left pending under every nonce it
+    /// carries, the transaction having been tracked under its last nonce
+    fn track_pending_transfer(&mut self, nonces: &[u64], transfer: PendingTransfer) -> Result<()> {
+        let last = nonces.iter().max().copied().unwrap_or_default();
+        let mut pending = match self.pending_confirmations.get(&last).cloned() {
+            Some(pending) => pending,
+            None => return Ok(()),
+        };
+        pending.transfer = Some(transfer);
+        for nonce in nonces {
+            self.pending_confirmations.insert(*nonce, pending.clone());
+        }
+        self.save_state()
+    }
+
+    /// Completes the relay of `nonces` once its transaction confirmed,
+    /// whether in time or on a later recheck: checks the nonce account
+    /// moved, advances the cursor, closes the PDAs and reports the relay.
+    /// Returns false when the nonce account didn't move
+    async fn complete_relay(
+        &mut self,
+        nonces: &[u64],
+        signature: Signature,
+        transfer: &PendingTransfer,
+    ) -> Result<bool> {
+        let last = nonces.iter().max().copied().unwrap_or_default();
+        if !self
+            .state_changed(transfer.selector, last, &signature)
+            .await?
+        {
+            return Ok(false);
+        }
+        for nonce in nonces {
+            self.advance_cursor(*nonce)?;
+            self.nonce_first_seen.remove(nonce);
+            self.fee_escalations.remove(nonce);
+            self.close_pda(*nonce).await;
+        }
+        let to = Pubkey::from_str(&transfer.destination)?;
+        self.metrics.record_relay(&signature);
+        self.metrics.record_relayed_amount(&to, transfer.amount);
+        self.notify_relayed(last, signature, transfer.amount, &to)
+            .await;
+        Ok(true)
+    }
+
+    /// Checks the L1 program and every L2 relay program are deployed and
+    /// executable, so a mistyped program ID fails at startup rather than on
+    /// the first relay
+    fn verify_program_ids(&self) -> Result<()> {
+        let mut programs = vec![
+            ("L1", &self.l1_client, self.l1_program_id),
+            ("L2", &self.l2_client, self.transaction_builder.program_id),
+        ];
+        programs.extend(
+            self.routes
+                .values()
+                .map(|builder| ("L2 route", &self.l2_client, builder.program_id)),
+        );
+
+        for (chain, client, program_id) in programs {
+            let account = client
+                .get_account_with_commitment(&program_id, client.commitment())
+                .map_err(RelayerError::from)?
+                .value
+                .ok_or_else(|| anyhow::anyhow!("{} program {} doesn't exist", chain, program_id))?;
+            if !account.executable {
+                return Err(anyhow::anyhow!(
+                    "{} program {} isn't executable, check the configured program ID",
+                    chain,
+                    program_id
+                ));
+            }
+            println!("{} program {} is deployed", chain, program_id);
+        }
+        Ok(())
+    }
+
+    /// Whether an RPC node reports a slot more than `slot_regression_tolerance`
+    /// below the highest one seen on its chain, so its reads would be stale
+    fn slot_regressed(&mut self) -> Result<bool> {
+        let tolerance = match self.slot_regression_tolerance {
+            Some(tolerance) => tolerance,
+            None => return Ok(false),
+        };
+        let mut regressed = false;
+        for (chain, client, highest) in [
+            ("L1", &self.l1_client, &mut self.highest_slots.0),
+            ("L2", &self.l2_client, &mut self.highest_slots.1),
+        ] {
+            let slot = client.get_slot().map_err(RelayerError::from)?;
+            if slot.saturating_add(tolerance) < *highest {
+                println!(
+                    "Warning: {} node {} is at slot {}, behind the highest seen slot {}, skipping this cycle",
+                    chain,
+                    client.url(),
+                    slot,
+                    highest
+                );
+                regressed = true;
+            }
+            *highest = (*highest).max(slot);
+        }
+        Ok(regressed)
+    }
+
+    /// Compares the local clock against the L2 cluster's latest block time,
+    /// refusing to start if `halt_on_clock_skew` is set and the skew exceeds
+    /// `max_clock_skew_secs`
+    fn check_clock_skew(&mut self) -> Result<()> {
+        let slot = self
+            .l2_client
+            .get_slot_with_commitment(CommitmentConfig::confirmed())
+            .map_err(RelayerError::from)?;
+        let block_time = match self.l2_client.get_block_time(slot) {
+            Ok(block_time) => block_time,
+            Err(err) => {
+                println!("Unable to measure clock skew at slot {}: {}", slot, err);
+                return Ok(());
+            }
+        };
+        let local_time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        let skew_secs = local_time - block_time;
+        println!(
+            "Local clock is {}s {} the cluster (slot {})",
+            skew_secs.abs(),
+            if skew_secs >= 0 { "ahead of" } else { "behind" },
+            slot
+        );
+        self.clock_skew = Duration::from_secs(skew_secs.unsigned_abs());
+
+        if let Some(max_skew) = self.max_clock_skew_secs {
+            if skew_secs.unsigned_abs() > max_skew {
+                let message = format!(
+                    "Clock skew of {}s exceeds max_clock_skew_secs ({}s)",
+                    skew_secs, max_skew
+                );
+                if self.halt_on_clock_skew {
+                    return Err(anyhow::anyhow!(message));
+                }
+                println!("Warning: {}", message);
+            }
+        }
+        Ok(())
+    }
+
+    /// Lowest fee payer balance that still keeps it rent exempt while
+    /// covering `fee_payer_fee_reserve_lamports` of fees
+    fn fee_payer_threshold(&self) -> Result<u64> {
+        let fee_payer = self.signer.pubkey();
+        let data_len = self
+            .l2_client
+            .get_account_with_commitment(&fee_payer, self.l2_client.commitment())
+            .map_err(RelayerError::from)?
+            .value
+            .map(|account| account.data.len())
+            .unwrap_or_default();
+        let rent_exempt = self
+            .l2_client
+            .get_minimum_balance_for_rent_exemption(data_len)
+            .map_err(RelayerError::from)?;
+        let threshold = rent_exempt.saturating_add(self.fee_payer_fee_reserve);
+        println!(
+            "Fee payer {} balance threshold: {} lamports ({} rent exempt + {} fee reserve)",
+            fee_payer, threshold, rent_exempt, self.fee_payer_fee_reserve
+        );
+        Ok(threshold)
+    }
+
+    /// Alerts once when the fee payer balance drops below the rent-exempt
+    /// threshold, and again only after it has recovered
+    async fn check_fee_payer_balance(&mut self) -> Result<()> {
+        let threshold = match self.min_fee_payer_balance {
+            Some(threshold) => threshold,
+            None => return Ok(()),
+        };
+        let fee_payer = self.signer.pubkey();
+        let balance = self
+            .l2_client
+            .get_balance(&fee_payer)
+            .map_err(RelayerError::from)?;
+
+        let low = balance < threshold;
+        if low && !self.low_balance_alerted {
+            self.alerter
+                .send(&format!(
+                    "Fee payer {} balance {} lamports is below {} lamports, it can't keep paying fees and stay rent exempt",
+                    fee_payer, balance, threshold
+                ))
+                .await;
+        }
+        self.low_balance_alerted = low;
+        Ok(())
+    }
+
+    /// Whether `max_pending_confirmations` relays are awaiting confirmation,
+    /// in which case new submissions wait. Rechecks them first so landed
+    /// relays free their slots
+    async fn at_pending_limit(&mut self) -> Result<bool> {
+        let limit = match (self.max_pending_confirmations, &self.latency_throttle) {
+            (Some(limit), Some(throttle)) => throttle.pending_limit(limit),
+            (Some(limit), None) => limit,
+            (None, _) => return Ok(false),
+        };
+        if self.pending_confirmations.len() < limit {
+            return Ok(false);
+        }
+        self.recheck_pending_confirmations().await?;
+        if self.pending_confirmations.len() < limit {
+            return Ok(false);
+        }
+        println!(
+            "{} relays awaiting confirmation, pausing submissions until they clear",
+            self.pending_confirmations.len()
+        );
+        Ok(true)
+    }
+
+    /// Waits before a submission, see `submission_delay`
+    async fn pace_submission(&self, sent_any: bool) {
+        let delay = submission_delay(
+            self.inter_transfer_delay,
+            self.latency_throttle.as_ref(),
+            sent_any,
+        );
+        if !delay.is_zero() {
+            time::sleep(delay).await;
+        }
+    }
+
+    /// Feeds a confirmed relay's latency to the throttle, reporting when it
+    /// engages or lifts
+    fn record_confirmation_latency(&mut self, latency: Duration) {
+        let throttle = match &mut self.latency_throttle {
+            Some(throttle) => throttle,
+            None => return,
+        };
+        match throttle.record(latency) {
+            Some(true) => println!(
+                "Confirmation latency averaging {:?}, throttling submissions",
+                throttle.average()
+            ),
+            Some(false) => println!(
+                "Confirmation latency back to {:?}, lifting the throttle",
+                throttle.average()
+            ),
+            None => {}
+        }
+    }
+
+    /// Resolves pending confirmations that landed, failed or can no longer land
+    async fn recheck_pending_confirmations(&mut self) -> Result<()> {
+        let commitment = self
+            .confirmation_strategy
+            .commitment()
+            .unwrap_or_else(CommitmentConfig::confirmed);
+
+        // 合并交易的所有 nonce 共用一个签名，按签名整体处理
+        let mut by_signature: BTreeMap<String, (Vec<u64>, PendingConfirmation)> = BTreeMap::new();
+        for (nonce, pending) in &self.pending_confirmations {
+            by_signature
+                .entry(pending.signature.clone())
+                .or_insert_with(|| (Vec::new(), pending.clone()))
+                .0
+                .push(*nonce);
+        }
+
+        for (nonces, pending) in by_signature.into_values() {
+            let signature = Signature::from_str(&pending.signature)?;
+            let recent_blockhash = Hash::from_str(&pending.recent_blockhash)?;
+            match self
+                .l2_client
+                .get_signature_status_with_commitment(&signature, commitment)
+                .map_err(RelayerError::from)?
+            {
+                Some(Ok(())) => {
+                    println!(
+                        "Pending relay of nonces {:?} confirmed: {}",
+                        nonces, signature
+                    );
+                    for nonce in &nonces {
+                        self.pending_confirmations.remove(nonce);
+                    }
+                    if let Some(latency) = pending.submitted_at.and_then(|submitted_at| {
+                        let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?;
+                        now.checked_sub(Duration::from_secs(submitted_at))
+                    }) {
+                        self.record_confirmation_latency(latency);
+                    }
+                    match &pending.transfer {
+                        Some(transfer) => {
+                            self.complete_relay(&nonces, signature, transfer).await?;
+                        }
+                        // 旧版本写入的状态没有转账内容，只能推进 cursor
+                        None => {
+                            for nonce in &nonces {
+                                self.advance_cursor(*nonce)?;
+                            }
+                        }
+                    }
+                }
+                Some(Err(err)) => {
+                    println!(
+                        "Pending relay of nonces {:?} failed, it will be retried: {}",
+                        nonces, err
+                    );
+                    for nonce in &nonces {
+                        self.pending_confirmations.remove(nonce);
+                    }
+                }
+                None => {
+                    if self
+                        .blockhash_valid(&recent_blockhash)
+                        .await
+                        .map_err(RelayerError::from)?
+                    {
+                        println!("Relay of nonces {:?} still pending: {}", nonces, signature);
+                        continue;
+                    }
+                    println!(
+                        "Pending relay of nonces {:?} expired without landing, it will be retried",
+                        nonces
+                    );
+                    for nonce in &nonces {
+                        self.pending_confirmations.remove(nonce);
+                    }
+                }
+            }
+        }
+
+        self.save_state()
+    }
+
+    /// Marks a nonce as processed and moves the local cursor over every
+    /// contiguously processed nonce, whatever order they completed in
+    fn advance_cursor(&mut self, nonce: u64) -> Result<()> {
+        let mut cursor = self.last_nonce.unwrap_or(nonce);
+        if nonce < cursor {
+            return Ok(());
+        }
+
+        self.completed_nonces.insert(nonce);
+        while self.completed_nonces.remove(&cursor) {
+            cursor += 1;
+        }
+
+        if self.last_nonce != Some(cursor) {
+            self.last_nonce = Some(cursor);
+            self.save_state()?;
+        }
+        Ok(())
+    }
+
+    /// Submits and confirms a relay transaction built with the signers in
+    /// `signer_keys`, returning `None` if it was escalated as a pending
+    /// confirmation after `confirm_timeout_secs`
+    async fn send_transaction_to_l2(
+        &mut self,
+        nonce: u64,
+        mut transaction: Transaction,
+        signer_keys: &[Pubkey],
+    ) -> Result<Option<Signature>> {
+        println!("\nSending transaction to L2...");
+        // 构建后 blockhash 可能已接近过期，提交前按需刷新并重新签名
+        let options = &self.transaction_builder.options;
+        if options.durable_nonce.is_none()
+            && options
+                .blockhash_cache
+                .is_stale(&transaction.message.recent_blockhash)
+        {
+            let recent_blockhash = self.transaction_builder.recent_blockhash(&self.l2_client)?;
+            if recent_blockhash != transaction.message.recent_blockhash {
+                self.resign(&mut transaction, signer_keys, recent_blockhash)?;
+            }
+        }
+        let attempts = self
+            .confirmation_strategy
+            .attempts(self.confirmation_retries);
+
+        let submitted = Instant::now();
+        let mut result = self.submit_and_confirm(nonce, &transaction).await;
+        for attempt in 2..=attempts {
+            if !matches!(result, Ok(Confirmation::Expired)) {
+                break;
+            }
+            // blockhash 已过期，旧交易不会再上链，可以安全地重新签名提交
+            println!(
+                "Blockhash expired before confirmation, resubmitting (attempt {}/{})",
+                attempt, attempts
+            );
+            let recent_blockhash = self.transaction_builder.recent_blockhash(&self.l2_client)?;
+            self.resign(&mut transaction, signer_keys, recent_blockhash)?;
+            result = self.submit_and_confirm(nonce, &transaction).await;
+        }
+        // 旧交易的 blockhash 已过期不会再上链，提价重发后最多只有一笔上链
+        if let (Some(escalation), Some(base)) = (
+            self.fee_escalation,
+            transaction::compute_unit_price(&transaction),
+        ) {
+            while matches!(result, Ok(Confirmation::Expired)) {
+                let attempt = self.fee_escalations.get(&nonce).map_or(1, |n| n + 1);
+                if attempt > escalation.max_attempts {
+                    break;
+                }
+                self.fee_escalations.insert(nonce, attempt);
+                let price = escalation.compute_unit_price(base, attempt);
+                println!(
+                    "Blockhash expired before confirmation, resending nonce {} at {} micro-lamports per compute unit (escalation {}/{})",
+                    nonce, price, attempt, escalation.max_attempts
+                );
+                transaction::set_compute_unit_price(&mut transaction, price);
+                let recent_blockhash =
+                    self.transaction_builder.recent_blockhash(&self.l2_client)?;
+                self.resign(&mut transaction, signer_keys, recent_blockhash)?;
+                result = self.submit_and_confirm(nonce, &transaction).await;
+            }
+        }
+
+        match result {
+            Ok(Confirmation::Confirmed(signature)) => {
+                self.record_confirmation_latency(submitted.elapsed());
+                if !self.quorum_confirmed(&signature).await {
+                    self.escalate_unconfirmed(
+                        nonce,
+                        signature,
+                        transaction.message.recent_blockhash,
+                        submitted,
+                    )
+                    .await?;
+                    return Ok(None);
+                }
+                if let Some(err) = self.transaction_error(&signature).await {
+                    println!("Transaction failed on-chain: {:?}", err);
+                    tracing::error!(%signature, error = ?err, "relay failed on-chain");
+                    return Err(anyhow::anyhow!(
+                        "L2 transaction {} was confirmed but failed: {:?}",
+                        signature,
+                        err
+                    ));
+                }
+                println!("Transaction successful! Signature: {}", signature);
+                tracing::info!(%signature, "relay confirmed");
+                self.check_expected_log(nonce, &signature).await;
+                Ok(Some(signature))
+            }
+            Ok(Confirmation::TimedOut(signature)) => {
+                tracing::warn!(%signature, "relay unconfirmed after timeout");
+                self.escalate_unconfirmed(
+                    nonce,
+                    signature,
+                    transaction.message.recent_blockhash,
+                    submitted,
+                )
+                .await?;
+                Ok(None)
+            }
+            Ok(Confirmation::Expired) => {
+                println!("Transaction failed: blockhash expired before confirmation");
+                tracing::warn!("relay blockhash expired before confirmation");
+                Err(anyhow::anyhow!(
+                    "L2 transaction failed: unable to confirm transaction before its blockhash expired"
+                ))
+            }
+            Err(err) => {
+                println!("Transaction failed: {}", err);
+                tracing::error!(error = %err, "relay failed");
+                if let Some(program_error) = err.get_transaction_error() {
+                    println!("Program error: {:?}", program_error);
+                }
+                let relayer_error = RelayerError::from(err);
+                println!("Hint: {}", relayer_error.hint());
+                Err(anyhow::Error::new(relayer_error).context("L2 transaction failed"))
+            }
+        }
+    }
+
+    /// Whether `confirmation_quorum` endpoints, counting `l2_url` which
+    /// already confirmed it, report the relay at the target commitment.
+    /// Lagging endpoints are polled until `confirm_timeout_secs`
+    async fn quorum_confirmed(&self, signature: &Signature) -> bool {
+        if self.quorum_clients.is_empty() {
+            return true;
+        }
+        let commitment = self
+            .confirmation_strategy
+            .commitment()
+            .unwrap_or_else(CommitmentConfig::confirmed);
+        let timeout = self.confirm_timeout.unwrap_or(DEFAULT_QUORUM_TIMEOUT) + self.clock_skew;
+
+        let started = Instant::now();
+        loop {
+            let mut confirmations = 1;
+            for client in &self.quorum_clients {
+                match client.get_signature_status_with_commitment(signature, commitment) {
+                    Ok(Some(Ok(()))) => confirmations += 1,
+                    Ok(Some(Err(err))) => println!(
+                        "Warning: {} reports relay {} failed: {}",
+                        client.url(),
+                        signature,
+                        err
+                    ),
+                    Ok(None) => {}
+                    Err(err) => println!(
+                        "Warning: quorum check against {} failed: {}",
+                        client.url(),
+                        err
+                    ),
+                }
+            }
+            if confirmations >= self.confirmation_quorum {
+                return true;
+            }
+            self.heartbeat.beat();
+            if started.elapsed() >= timeout {
+                println!(
+                    "Relay {} confirmed by {} of {} endpoints, short of the quorum of {}",
+                    signature,
+                    confirmations,
+                    self.quorum_clients.len() + 1,
+                    self.confirmation_quorum
+                );
+                return false;
+            }
+            time::sleep(Duration::from_millis(500)).await;
+        }
+    }
+
+    /// Submits the relay of `nonce` and confirms it according to
+    /// `confirmation_strategy`, returning `None` if its blockhash expired
+    /// before it landed
+    async fn submit_and_confirm(
+        &self,
+        nonce: u64,
+        transaction: &Transaction,
+    ) -> ClientResult<Confirmation> {
+        if self.confirmation_strategy == ConfirmationStrategy::ConfirmStateChange {
+            return self.submit_and_confirm_state(nonce, transaction).await;
+        }
+        let commitment = self.confirmation_strategy.commitment();
+        // 先订阅再提交，避免错过确认通知
+        let subscription = commitment.and_then(|commitment| {
+            self.subscribe_signature(&transaction.signatures[0], commitment)
+        });
+
+        let signature = self.l2_client.send_transaction(transaction)?;
+        tracing::info!(%signature, "submitted relay transaction");
+        let commitment = match commitment {
+            Some(commitment) => commitment,
+            None => return Ok(Confirmation::Confirmed(signature)),
+        };
+
+        if let Some(subscription) = subscription {
+            if let Some(confirmation) = self
+                .confirm_via_subscription(
+                    &signature,
+                    &transaction.message.recent_blockhash,
+                    &subscription,
+                )
+                .await?
+            {
+                return Ok(confirmation);
+            }
+            println!("Falling back to polling for {}", signature);
+        }
+
+        // 部分 RPC 提交后立即查询状态只会返回 not found，先等待一段时间
+        wait_post_submit(self.post_submit_delay).await;
+
+        self.confirm_transaction(
+            &signature,
+            &transaction.message.recent_blockhash,
+            commitment,
+        )
+        .await
+    }
+
+    /// Submits the relay of `nonce` and waits for its nonce account to move
+    /// past it through `accountSubscribe`, confirming the relay's effect
+    /// rather than its inclusion. Falls back to polling the signature if the
+    /// subscription drops
+    async fn submit_and_confirm_state(
+        &self,
+        nonce: u64,
+        transaction: &Transaction,
+    ) -> ClientResult<Confirmation> {
+        let url = self.l2_ws_url.as_ref().ok_or_else(|| {
+            ClientErrorKind::Custom("confirm_state_change requires l2_ws_url".to_string())
+        })?;
+        let nonce_account = self.transaction_builder.nonce_account_for(nonce);
+        let config = RpcAccountInfoConfig {
+            encoding: Some(UiAccountEncoding::Base64),
+            commitment: Some(CommitmentConfig::confirmed()),
+            ..RpcAccountInfoConfig::default()
+        };
+        // 先订阅再提交，避免错过账户变化通知
+        let (_subscription, receiver) =
+            PubsubClient::account_subscribe(url, &nonce_account, Some(config))
+                .map_err(|e| ClientErrorKind::Custom(e.to_string()))?;
+
+        let signature = self.l2_client.send_transaction(transaction)?;
+        tracing::info!(%signature, "submitted relay transaction");
+        let recent_blockhash = &transaction.message.recent_blockhash;
+        let started = Instant::now();
+        loop {
+            match tokio::task::block_in_place(|| receiver.recv_timeout(Duration::from_secs(1))) {
+                Ok(response) => {
+                    let account = response.value.decode::<Account>();
+                    match self.parse_l2_nonce(&nonce_account, account) {
+                        Ok(cursor) if cursor > nonce => {
+                            return Ok(Confirmation::Confirmed(signature));
+                        }
+                        Ok(_) => {}
+                        Err(err) => println!(
+                            "Warning: unreadable update of nonce account {}: {}",
+                            nonce_account, err
+                        ),
+                    }
+                }
+                Err(err) if err.is_timeout() => {
+                    if !self.blockhash_valid(recent_blockhash).await? {
+                        return Ok(Confirmation::Expired);
+                    }
+                    self.heartbeat.beat();
+                    if self
+                        .confirm_timeout
+                        .is_some_and(|timeout| started.elapsed() >= timeout + self.clock_skew)
+                    {
+                        return Ok(Confirmation::TimedOut(signature));
+                    }
+                }
+                Err(_) => {
+                    println!("Falling back to polling for {}", signature);
+                    return self
+                        .confirm_transaction(
+                            &signature,
+                            recent_blockhash,
+                            CommitmentConfig::confirmed(),
+                        )
+                        .await;
+                }
+            }
+        }
+    }
+
+    /// Subscribes to the signature's status on `l2_ws_url` when
+    /// `use_ws_confirmation` is set, `None` if disabled or unavailable
+    fn subscribe_signature(
+        &self,
+        signature: &Signature,
+        commitment: CommitmentConfig,
+    ) -> Option<SignatureSubscription> {
+        let url = self.ws_confirmation_url.as_ref()?;
+        let config = RpcSignatureSubscribeConfig {
+            commitment: Some(commitment),
+            enable_received_notification: Some(false),
+        };
+        match PubsubClient::signature_subscribe(url, signature, Some(config)) {
+            Ok(subscription) => Some(subscription),
+            Err(err) => {
+                println!("Signature subscription at {} failed: {}", url, err);
+                None
+            }
+        }
+    }
+
+    /// Waits for the subscription's notification, returning `None` once it
+    /// disconnects or the blockhash expires so polling can settle the outcome
+    async fn confirm_via_subscription(
+        &self,
+        signature: &Signature,
+        recent_blockhash: &Hash,
+        (_, receiver): &SignatureSubscription,
+    ) -> ClientResult<Option<Confirmation>> {
+        let started = Instant::now();
+        loop {
+            match tokio::task::block_in_place(|| receiver.recv_timeout(Duration::from_secs(1))) {
+                Ok(response) => match response.value {
+                    RpcSignatureResult::ProcessedSignature(result) => {
+                        return match result.err {
+                            None => Ok(Some(Confirmation::Confirmed(*signature))),
+                            Some(err) => Err(err.into()),
+                        }
+                    }
+                    RpcSignatureResult::ReceivedSignature(_) => {}
+                },
+                Err(err) if err.is_timeout() => {
+                    if !self.blockhash_valid(recent_blockhash).await? {
+                        return Ok(None);
+                    }
+                    self.heartbeat.beat();
+                    if self
+                        .confirm_timeout
+                        .is_some_and(|timeout| started.elapsed() >= timeout + self.clock_skew)
+                    {
+                        return Ok(Some(Confirmation::TimedOut(*signature)));
+                    }
+                }
+                Err(_) => return Ok(None),
+            }
+        }
+    }
+
+    /// With `verify_tx_success`, the error a confirmed relay was included
+    /// with, retrying while its detail isn't available yet
+    async fn transaction_error(&self, signature: &Signature) -> Option<TransactionError> {
+        if !self.verify_tx_success {
+            return None;
+        }
+
+        let config = RpcTransactionConfig {
+            encoding: Some(UiTransactionEncoding::Json),
+            commitment: Some(CommitmentConfig::confirmed()),
+            max_supported_transaction_version: Some(0),
+        };
+        for attempt in 1..=VERIFY_TX_ATTEMPTS {
+            match self
+                .l2_client
+                .get_transaction_with_config(signature, config)
+            {
+                Ok(transaction) => {
+                    return transaction.transaction.meta.and_then(|meta| meta.err);
+                }
+                Err(err) if attempt < VERIFY_TX_ATTEMPTS => {
+                    println!(
+                        "Transaction {} not available yet (attempt {}/{}): {}",
+                        signature, attempt, VERIFY_TX_ATTEMPTS, err
+                    );
+                    time::sleep(VERIFY_TX_RETRY_DELAY).await;
+                }
+                Err(err) => println!(
+                    "Warning: could not fetch {} to verify it succeeded, trusting its confirmation: {}",
+                    signature, err
+                ),
+            }
+        }
+        None
+    }
+
+    /// Warns and alerts when a confirmed relay's logs lack the configured
+    /// `expected_log_substring`, a sign the program took an unexpected branch
+    async fn check_expected_log(&self, nonce: u64, signature: &Signature) {
+        let expected = match &self.expected_log_substring {
+            Some(expected) => expected,
+            None => return,
+        };
+
+        let config = RpcTransactionConfig {
+            encoding: Some(UiTransactionEncoding::Json),
+            commitment: Some(CommitmentConfig::confirmed()),
+            max_supported_transaction_version: Some(0),
+        };
+        let logs: Option<Vec<String>> = match self
+            .l2_client
+            .get_transaction_with_config(signature, config)
+        {
+            Ok(transaction) => transaction
+                .transaction
+                .meta
+                .and_then(|meta| meta.log_messages.into()),
+            Err(err) => {
+                println!(
+                    "Warning: could not fetch logs of {} to check for the expected log: {}",
+                    signature, err
+                );
+                return;
+            }
+        };
+
+        if !logs
+            .unwrap_or_default()
+            .iter()
+            .any(|log| log.contains(expected.as_str()))
+        {
+            self.alerter
+                .send(&format!(
+                    "Relay of nonce {} confirmed in {} but its logs lack '{}'",
+                    nonce, signature, expected
+                ))
+                .await;
+        }
+    }
+
+    /// Whether a transaction signed with this blockhash can still land. A
+    /// durable nonce stays valid until the nonce account is advanced
+    async fn blockhash_valid(&self, recent_blockhash: &Hash) -> ClientResult<bool> {
+        if self.transaction_builder.options.durable_nonce.is_some() {
+            let current = self
+                .transaction_builder
+                .recent_blockhash(&self.l2_client)
+                .map_err(|e| ClientErrorKind::Custom(e.to_string()))?;
+            return Ok(current == *recent_blockhash);
+        }
+        self.l2_client
+            .is_blockhash_valid(recent_blockhash, CommitmentConfig::processed())
+    }
+
+    /// Polls the signature status until it reaches the commitment, its
+    /// blockhash expires or `confirm_timeout_secs` passes
+    async fn confirm_transaction(
+        &self,
+        signature: &Signature,
+        recent_blockhash: &Hash,
+        commitment: CommitmentConfig,
+    ) -> ClientResult<Confirmation> {
+        let started = Instant::now();
+        loop {
+            match self
+                .l2_client
+                .get_signature_status_with_commitment(signature, commitment)?
+            {
+                Some(Ok(())) => return Ok(Confirmation::Confirmed(*signature)),
+                Some(Err(err)) => return Err(err.into()),
+                None => {
+                    if !self.blockhash_valid(recent_blockhash).await? {
+                        // 失效前的最后一刻交易仍可能上链，再确认一次
+                        return match self
+                            .l2_client
+                            .get_signature_status_with_commitment(signature, commitment)?
+                        {
+                            Some(Ok(())) => Ok(Confirmation::Confirmed(*signature)),
+                            Some(Err(err)) => Err(err.into()),
+                            None => Ok(Confirmation::Expired),
+                        };
+                    }
+                    // 等待确认受 blockhash 有效期和超时限制，不算作卡住
+                    self.heartbeat.beat();
+                    // 本机时钟与集群不一致时放宽超时，避免过早升级为待确认
+                    if self
+                        .confirm_timeout
+                        .is_some_and(|timeout| started.elapsed() >= timeout + self.clock_skew)
+                    {
+                        return Ok(Confirmation::TimedOut(*signature));
+                    }
+                    time::sleep(Duration::from_millis(500)).await;
+                }
+            }
+        }
+    }
+}
+
+/// Monitor loop over one relayer per nonce stream, running a cycle of each
+/// in turn. A single-counter watched account is one stream. Returns between
+/// cycles once `terminate` is set or `max_uptime_secs` is reached
+async fn monitor_streams(
+    relayers: &mut [Relayer],
+    mut terminate: watch::Receiver<bool>,
+) -> Result<Exit> {
+    let started = Instant::now();
+    for relayer in relayers.iter_mut() {
+        relayer.start_with_retries().await?;
+    }
+    loop {
+        // 只在两轮之间退出，当前一轮总是完整执行
+        if *terminate.borrow() {
+            return Ok(Exit::Terminated);
+        }
+        if let Some(max_uptime) = relayers.first().and_then(|relayer| relayer.max_uptime) {
+            if started.elapsed() >= max_uptime {
+                println!(
+                    "Uptime of {:?} reached max_uptime_secs, exiting for a restart",
+                    started.elapsed()
+                );
+                return Ok(Exit::Restart);
+            }
+        }
+        // 所有流共享同一个 config cell，重新加载的配置在每轮开始前分发给每个流
+        let reloaded = relayers
+            .first()
+            .and_then(|relayer| relayer.config_cell.lock().unwrap().take());
+        for relayer in relayers.iter_mut() {
+            if let Some(reloaded) = &reloaded {
+                relayer.reload(reloaded)?;
+            }
+            relayer.monitor_cycle().await?;
+        }
+        // 等待期间收到终止信号立即退出，发送端关闭时只等待超时
+        tokio::select! {
+            _ = time::sleep(Duration::from_secs(60)) => {}
+            Ok(()) = terminate.changed() => {}
+        }
+        if let Some(cooldown) = post_catchup_cooldown(relayers) {
+            println!("Catch-up complete, cooling down for {:?}", cooldown);
+            time::sleep(cooldown).await;
+        }
+    }
+}
+
+/// `first` followed by every item `next` receives within `window` of it, so a
+/// burst of notifications is handled in one pass. `next` waits up to the
+/// given time for an item
+fn coalesce<T>(first: T, window: Duration, mut next: impl FnMut(Duration) -> Option<T>) -> Vec<T> {
+    // 窗口内到达的后续通知合并为一次处理
+    let mut items = vec![first];
+    let deadline = Instant::now() + window;
+    while let Some(remaining) = deadline
+        .checked_duration_since(Instant::now())
+        .filter(|remaining| !remaining.is_zero())
+    {
+        match next(remaining) {
+            Some(item) => items.push(item),
+            None => break,
+        }
+    }
+    items
+}
+
+/// Longest `post_catchup_cooldown_secs` of the relayers whose catch-up
+/// completed this round, clearing their catch-up flag
+fn post_catchup_cooldown(relayers: &mut [Relayer]) -> Option<Duration> {
+    // 追赶刚完成时 RPC 可能仍在恢复，空闲轮次不等待
+    relayers
+        .iter_mut()
+        .filter_map(|relayer| {
+            std::mem::take(&mut relayer.caught_up)
+                .then_some(relayer.post_catchup_cooldown)
+                .flatten()
+        })
+        .max()
+}
+
+/// Sets `terminate` on SIGTERM or Ctrl-C, letting the monitor loop finish
+/// its cycle and shut down
+async fn watch_termination(terminate: watch::Sender<bool>) {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                tokio::select! {
+                    _ = sigterm.recv() => {}
+                    _ = tokio::signal::ctrl_c() => {}
+                }
+            }
+            Err(err) => {
+                println!("SIGTERM handling unavailable: {}", err);
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+    println!("Termination requested, shutting down after the current cycle");
+    let _ = terminate.send(true);
+}
+
+/// Shutdown shared by termination and `max_uptime_secs`: persists the state
+/// of every relayer, posts batched alerts and exports the remaining spans
+async fn shutdown(relayers: &[Relayer]) -> Result<()> {
+    for relayer in relayers {
+        relayer.save_state()?;
+        relayer.alerter.flush().await;
+    }
+    telemetry::shutdown().await;
+    Ok(())
+}
+
+/// Builds one relayer per configured nonce stream, sharing the metrics and
+/// deadman heartbeat of `relayer`
+fn stream_relayers(config: &RelayerConfig, relayer: &Relayer) -> Result<Vec<Relayer>> {
+    let account_data = relayer
+        .l1_client
+        .get_account_data(&relayer.watched_account)
+        .map_err(RelayerError::from)?;
+    let counters = NonceStatus::read_counters(
+        &account_data,
+        config
+            .nonce_streams
+            .iter()
+            .map(|stream| (stream.name.as_str(), stream.offset)),
+        config.nonce_endianness,
+    )?;
+    println!("Nonce streams: {:?}", counters);
+
+    let mut relayers = Vec::with_capacity(config.nonce_streams.len());
+    for stream in &config.nonce_streams {
+        let mut stream_relayer = Relayer::new(
+            &stream_config(config, stream),
+            relayer.rpc_connections.clone(),
+        )?;
+        stream_relayer.watched_nonce_offset = stream.offset;
+        stream_relayer.stream = Some(stream.name.clone());
+        if let Some(seed) = &stream.pda_seed {
+            stream_relayer
+                .pda_manager
+                .set_stream_seed(seed.as_bytes().to_vec());
+        }
+        stream_relayer.metrics = relayer.metrics.clone();
+        stream_relayer.heartbeat = relayer.heartbeat.clone();
+        stream_relayer.config_cell = relayer.config_cell.clone();
+        relayers.push(stream_relayer);
+    }
+    Ok(relayers)
+}
+
+/// Wait before submitting a relay: `inter_transfer_delay_ms` after a
+/// previous transfer this cycle, to stay under RPC rate limits, plus the
+/// latency above `latency_throttle_threshold_ms` while confirmations are slow
+fn submission_delay(
+    inter_transfer_delay: Duration,
+    throttle: Option<&LatencyThrottle>,
+    sent_any: bool,
+) -> Duration {
+    let spacing = if sent_any {
+        inter_transfer_delay
+    } else {
+        Duration::ZERO
+    };
+    spacing + throttle.map_or(Duration::ZERO, LatencyThrottle::delay)
+}
+
+/// Waits `post_submit_delay_ms` between submitting a relay and polling its
+/// status
+async fn wait_post_submit(delay: Duration) {
+    if !delay.is_zero() {
+        time::sleep(delay).await;
+    }
+}
+
+/// `startup_rescan_window` plus up to `jitter` more nonces
+fn rescan_window(window: u64, jitter: u64, rng: &mut impl rand::Rng) -> u64 {
+    if window == 0 {
+        return 0;
+    }
+    window.saturating_add(rng.gen_range(0, jitter.saturating_add(1)))
+}
+
+/// Builder of the route for `selector`, the default one for messages
+/// without a selector
+fn select_route<'a>(
+    default: &'a TransactionBuilder,
+    routes: &'a BTreeMap<u8, TransactionBuilder>,
+    selector: Option<u8>,
+) -> Result<&'a TransactionBuilder> {
+    match selector {
+        Some(selector) => routes
+            .get(&selector)
+            .ok_or_else(|| anyhow::anyhow!("No route configured for selector {}", selector)),
+        None => Ok(default),
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<ExitCode> {
+    env_logger::init();
+    let Args {
+        config_path,
+        command,
+    } = Args::parse(std::env::args().skip(1))?;
+
+    let config_path = config::resolve_config_path(config_path)?;
+    let config = RelayerConfig::load(&config_path)?;
+    if let Some(endpoint) = &config.otlp_endpoint {
+        telemetry::init(endpoint)?;
+    }
+    // 先加载配置，启动日志才能按 ndjson_stdout 输出到 stderr
+    if config.ndjson_stdout {
+        ndjson::redirect_stdout()?;
+    }
+
+    println!(
+        "Starting relayer v{} ({})...",
+        metrics::VERSION,
+        metrics::GIT_HASH
+    );
+    println!("Config loaded from: {}", config_path.display());
+    if let Command::SkipNonce { nonce, stream } = &command {
+        skip_nonce(&config, *nonce, stream.as_deref())?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    if let Command::Resume { stream } = &command {
+        resume(&config, stream.as_deref())?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    // 提交阶段只需要网络，不加载签名者
+    if let Command::SubmitSigned { path } = command {
+        submit_signed(&config, &path)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    println!("L1 URL: {}", config.l1_url);
+    println!("L2 URL: {}", config.l2_url);
+
+    println!("Initializing relayer...");
+    let mut relayer = Relayer::new(&config, rpc::connection_limit(config.max_rpc_connections)?)?;
+    println!("Relayer initialized successfully");
+
+    if let Command::VerifyConfig = command {
+        relayer.verify_program_ids()?;
+        println!("Config verified");
+        return Ok(ExitCode::SUCCESS);
+    }
+    if config.verify_program_ids {
+        relayer.verify_program_ids()?;
+    }
+    if let Command::SimulateBacklog = command {
+        relayer.simulate_backlog()?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    if let Command::BuildAndSign { from, to, out } = command {
+        let relays = relayer.build_and_sign(from, to)?;
+        offline::write_signed(&out, &relays)?;
+        println!("Wrote {} signed relays to {}", relays.len(), out.display());
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    #[cfg(unix)]
+    {
+        let config_cell = relayer.config_cell.clone();
+        tokio::spawn(async move {
+            if let Err(err) = reload::watch_sighup(config_path, config_cell).await {
+                println!("Config reload on SIGHUP unavailable: {}", err);
+            }
+        });
+    }
+
+    if let Some(addr) = &config.metrics_listen_addr {
+        let listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to bind metrics endpoint {}: {}", addr, e))?;
+        println!("Serving metrics on http://{}/metrics", addr);
+        let exposed_config = if config.expose_config {
+            println!("Serving the redacted config on http://{}/config", addr);
+            Some(Arc::new(serde_json::to_string_pretty(&config.redacted()?)?))
+        } else {
+            None
+        };
+        tokio::spawn(metrics::serve(
+            listener,
+            relayer.metrics.clone(),
+            exposed_config,
+        ));
+    }
+
+    if let Some(port) = config.status_tcp_port {
+        let addr = (config.status_bind_addr.as_str(), port);
+        let listener = tokio::net::TcpListener::bind(addr).await.map_err(|e| {
+            anyhow::anyhow!(
+                "Failed to bind status port {}:{}: {}",
+                config.status_bind_addr,
+                port,
+                e
+            )
+        })?;
+        println!(
+            "Serving status on tcp://{}:{}",
+            config.status_bind_addr, port
+        );
+        tokio::spawn(metrics::serve_status(listener, relayer.metrics.clone()));
+    }
+
+    match config.source_mode {
+        SourceMode::AccountNonce => {
+            if let Some(timeout) = config.deadman_timeout_secs {
+                // 独立的 alerter，监控循环卡住时仍能发出告警
+                let alerter = Alerter::new(config.alert_webhook_url.clone(), None, None);
+                tokio::spawn(deadman::watch(
+                    relayer.heartbeat.clone(),
+                    Duration::from_secs(timeout),
+                    alerter,
+                ));
+            }
+            let (terminate_sender, terminate) = watch::channel(false);
+            tokio::spawn(watch_termination(terminate_sender));
+            let exit = if config.nonce_streams.is_empty() {
+                println!("Starting monitoring...");
+                let exit = relayer.monitor_and_relay(terminate).await?;
+                shutdown(std::slice::from_ref(&relayer)).await?;
+                exit
+            } else {
+                let mut relayers = stream_relayers(&config, &relayer)?;
+                println!("Starting monitoring of {} nonce streams...", relayers.len());
+                let exit = monitor_streams(&mut relayers, terminate).await?;
+                shutdown(&relayers).await?;
+                exit
+            };
+            Ok(exit.code())
+        }
+        SourceMode::ProgramLogs => {
+            println!("Starting program log subscription...");
+            relayer.relay_program_logs().await?;
+            Ok(ExitCode::SUCCESS)
+        }
+    }
+}
+
+/// Submits the relays written by `build-and-sign`, reporting each outcome
+fn submit_signed(config: &RelayerConfig, path: &Path) -> Result<()> {
+    let l2_client = rpc::new_rpc_client(
+        &config.l2_url,
+        &config.rpc_headers,
+        rpc::connection_limit(config.max_rpc_connections)?,
+        CommitmentConfig::confirmed(),
+        None,
+        false,
+    )?;
+
+    let mut failed = 0;
+    for relay in offline::read_signed(path)? {
+        let result = relay.decode().and_then(|transaction| {
+            Ok(l2_client
+                .send_and_confirm_transaction(&transaction)
+                .map_err(RelayerError::from)?)
+        });
+        match result {
+            Ok(signature) => println!("Nonce {} relayed: {}", relay.nonce, signature),
+            Err(err) => {
+                println!("Nonce {} failed: {}", relay.nonce, err);
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(anyhow::anyhow!("{} signed relays failed to submit", failed));
+    }
+    Ok(())
+}
+
+/// State file of the relayer an operator command targets: `state_path`
+/// itself, or with `nonce_streams` the file of the given stream
+fn command_state_path(
+    config: &RelayerConfig,
+    command: &str,
+    stream: Option<&str>,
+) -> Result<String> {
+    let state_path = config
+        .state_path
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("{} requires state_path to be configured", command))?;
+    if config.nonce_streams.is_empty() {
+        return match stream {
+            Some(stream) => Err(anyhow::anyhow!(
+                "--stream {} given but no nonce_streams are configured",
+                stream
+            )),
+            None => Ok(state_path.clone()),
+        };
+    }
+
+    let names = || {
+        config
+            .nonce_streams
+            .iter()
+            .map(|stream| stream.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    match stream {
+        Some(stream) if config.nonce_streams.iter().any(|s| s.name == stream) => {
+            Ok(stream_state_path(state_path, stream))
+        }
+        Some(stream) => Err(anyhow::anyhow!(
+            "Unknown nonce stream {}, expected one of {}",
+            stream,
+            names()
+        )),
+        None => Err(anyhow::anyhow!(
+            "{} requires --stream <name> with nonce_streams configured, one of {}",
+            command,
+            names()
+        )),
+    }
+}
+
+/// State file of one nonce stream
+/// Config of one nonce stream's relayer, with the stream's own L2 nonce
+/// account and state file
+fn stream_config(config: &RelayerConfig, stream: &NonceStreamConfig) -> RelayerConfig {
+    let mut stream_config = config.clone();
+    stream_config.nonce_account = stream.nonce_account.clone();
+    stream_config.state_path = config
+        .state_path
+        .as_ref()
+        .map(|path| stream_state_path(path, &stream.name));
+    stream_config
+}
+
+fn stream_state_path(state_path: &str, stream: &str) -> String {
+    format!("{}.{}", state_path, stream)
+}
+
+/// Records a nonce as permanently skipped so the relayer moves past it
+fn skip_nonce(config: &RelayerConfig, nonce: u64, stream: Option<&str>) -> Result<()> {
+    let state_path = &command_state_path(config, "skip-nonce", stream)?;
+
+    let mut state = RelayerState::load(state_path)?.unwrap_or_default();
+    if state.skipped_nonces.insert(nonce) {
+        state.save(state_path)?;
+        println!("Nonce {} marked as skipped in {}", nonce, state_path);
+    } else {
+        println!("Nonce {} is already marked as skipped", nonce);
+    }
+
+    Ok(())
+}
+
+/// Lifts an emergency pause, letting the relayer catch up past
+/// `emergency_lag_threshold` until lag drops back under it
+fn resume(config: &RelayerConfig, stream: Option<&str>) -> Result<()> {
+    let state_path = &command_state_path(config, "resume", stream)?;
+
+    let mut state = RelayerState::load(state_path)?.unwrap_or_default();
+    if !state.emergency_paused {
+        println!("Relayer is not paused");
+        return Ok(());
+    }
+    state.emergency_paused = false;
+    state.emergency_resumed = true;
+    state.save(state_path)?;
+    println!("Emergency pause lifted in {}", state_path);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_client::rpc_request::RpcRequest;
+    use tempfile::TempDir;
+
+    fn build_options() -> BuildOptions {
+        BuildOptions {
+            max_transaction_size: solana_sdk::packet::PACKET_DATA_SIZE,
+            data_template: transaction::compile_template(&[], false, None).unwrap(),
+            durable_nonce: None,
+            blockhash_cache: Arc::new(BlockhashCache::new(60, None, CommitmentConfig::confirmed())),
+            priority_fee: None,
+            close_pda: None,
+            init_nonce_account: None,
+            memo_key: None,
+        }
+    }
+
+    fn builder() -> TransactionBuilder {
+        TransactionBuilder::new(
+            Pubkey::new_unique(),
+            vec![Pubkey::new_unique()],
+            build_options(),
+        )
+    }
+
+    /// Config over a fresh wallet and state file, the latter holding
+    /// `last_nonce` when given, with `overrides` merged in. Both live in the
+    /// returned directory, removed once it's dropped
+    fn config(last_nonce: Option<u64>, overrides: serde_json::Value) -> (TempDir, RelayerConfig) {
+        let dir = tempfile::tempdir().unwrap();
+        let wallet_path = dir.path().join("wallet.json");
+        solana_sdk::signature::write_keypair_file(&Keypair::new(), &wallet_path).unwrap();
+        let state_path = dir.path().join("state.json");
+        if last_nonce.is_some() {
+            RelayerState {
+                last_nonce,
+                ..RelayerState::default()
+            }
+            .save(&state_path)
+            .unwrap();
+        }
+
+        let mut config = serde_json::json!({
+            "l1_url": "http://127.0.0.1:8899",
+            "l2_url": "http://127.0.0.1:8999",
+            "watched_account": Pubkey::new_unique().to_string(),
+            "wallet_path": wallet_path,
+            "l1_program_id": Pubkey::new_unique().to_string(),
+            "l2_program_id": Pubkey::new_unique().to_string(),
+            "nonce_account": Pubkey::new_unique().to_string(),
+            "state_path": state_path,
+        });
+        config
+            .as_object_mut()
+            .unwrap()
+            .extend(overrides.as_object().unwrap().clone());
+        (dir, serde_json::from_value(config).unwrap())
+    }
+
+    fn relayer(last_nonce: Option<u64>, overrides: serde_json::Value) -> (TempDir, Relayer) {
+        let (dir, config) = config(last_nonce, overrides);
+        (dir, Relayer::new(&config, None).unwrap())
+    }
+
+    fn mismatch() -> anyhow::Error {
+        DiscriminatorMismatch {
+            account: Pubkey::new_unique(),
+            expected: [1; 8],
+            found: vec![2; 8],
+        }
+        .into()
+    }
+
+    #[test]
+    fn spaces_transfers_after_the_first() {
+        let spacing = Duration::from_millis(200);
+        assert_eq!(submission_delay(spacing, None, false), Duration::ZERO);
+        assert_eq!(submission_delay(spacing, None, true), spacing);
+    }
+
+    #[test]
+    fn adds_throttle_delay_to_spacing() {
+        let spacing = Duration::from_millis(200);
+        let mut throttle = LatencyThrottle::new(Duration::from_secs(2), 2);
+
+        throttle.record(Duration::from_secs(1));
+        assert_eq!(submission_delay(spacing, Some(&throttle), true), spacing);
+
+        throttle.record(Duration::from_secs(5));
+        // 平均 3s，超出阈值 1s
+        assert_eq!(
+            submission_delay(spacing, Some(&throttle), false),
+            Duration::from_secs(1)
+        );
+        assert_eq!(
+            submission_delay(spacing, Some(&throttle), true),
+            spacing + Duration::from_secs(1)
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn honours_post_submit_delay() {
+        let started = time::Instant::now();
+        wait_post_submit(Duration::ZERO).await;
+        assert_eq!(started.elapsed(), Duration::ZERO);
+
+        wait_post_submit(Duration::from_millis(750)).await;
+        assert!(started.elapsed() >= Duration::from_millis(750));
+    }
+
+    #[test]
+    fn routes_selectors_to_their_builders() {
+        let default = builder();
+        let routes = BTreeMap::from([(0, builder()), (1, builder())]);
+
+        for selector in [0u8, 1] {
+            let route = select_route(&default, &routes, Some(selector)).unwrap();
+            assert_eq!(route.program_id, routes[&selector].program_id);
+            assert_eq!(route.nonce_accounts, routes[&selector].nonce_accounts);
+        }
+        assert_ne!(routes[&0].program_id, routes[&1].program_id);
+    }
+
+    #[test]
+    fn falls_back_to_default_builder_without_selector() {
+        let default = builder();
+        let routes = BTreeMap::from([(0, builder())]);
+
+        let route = select_route(&default, &routes, None).unwrap();
+        assert_eq!(route.program_id, default.program_id);
+        assert!(select_route(&default, &routes, Some(7)).is_err());
+    }
+
+    #[test]
+    fn routes_must_cover_every_selector_up_to_max_selector() {
+        let route = serde_json::json!({
+            "l2_program_id": Pubkey::new_unique().to_string(),
+            "nonce_account": Pubkey::new_unique().to_string(),
+        });
+        let (_dir, routed) = config(
+            None,
+            serde_json::json!({
+                "selector_offset": 80,
+                "max_selector": 2,
+                "routes": { "0": route, "1": route },
+            }),
+        );
+        let err = Relayer::new(&routed, None).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "Routes must cover selectors 0..=2, missing route for selector 2"
+        );
+
+        let (_dir, mut config) = config(None, serde_json::json!({ "selector_offset": 80 }));
+        config.routes = HashMap::from([("0".to_string(), serde_json::from_value(route).unwrap())]);
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("routes require max_selector"));
+    }
+
+    /// `getAccountInfo` mock of a message PDA transferring `amount` to a
+    /// fresh destination, with `selector` after the transfer fields
+    fn message_pda(amount: u64, selector: u8) -> HashMap<RpcRequest, serde_json::Value> {
+        let mut data = vec![0; 81];
+        data[40..72].copy_from_slice(Pubkey::new_unique().as_ref());
+        data[72..80].copy_from_slice(&amount.to_le_bytes());
+        data[80] = selector;
+        HashMap::from([(
+            RpcRequest::GetAccountInfo,
+            serde_json::json!({
+                "context": { "slot": 1 },
+                "value": {
+                    "lamports": 1_000_000,
+                    "data": [base64::encode(data), "base64"],
+                    "owner": Pubkey::new_unique().to_string(),
+                    "executable": false,
+                    "rentEpoch": 0,
+                },
+            }),
+        )])
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn keeps_a_cursor_per_route() {
+        let route = || {
+            serde_json::json!({
+                "l2_program_id": Pubkey::new_unique().to_string(),
+                "nonce_account": Pubkey::new_unique().to_string(),
+            })
+        };
+        let (_dir, mut relayer) = relayer(
+            Some(3),
+            serde_json::json!({
+                "selector_offset": 80,
+                "max_selector": 1,
+                "routes": { "0": route(), "1": route() },
+            }),
+        );
+        // 默认 nonce 账户之后按 selector 顺序读取各路由
+        let accounts: Vec<serde_json::Value> = [(&relayer.transaction_builder, 0)]
+            .into_iter()
+            .chain(relayer.routes.values().zip([3, 8]))
+            .map(|(builder, nonce)| nonce_account_json(&builder.program_id, nonce))
+            .collect();
+        relayer.l2_client = RpcClient::new_mock_with_mocks(
+            "succeeds".to_string(),
+            HashMap::from([(
+                RpcRequest::GetMultipleAccounts,
+                serde_json::json!({ "context": { "slot": 1 }, "value": accounts }),
+            )]),
+        );
+        relayer.cursors = relayer.read_cursors().unwrap();
+        assert_eq!(relayer.cursors.routes, BTreeMap::from([(0, 3), (1, 8)]));
+        assert_eq!(relayer.cursors.min(), 3);
+
+        // 路由 1 已越过 nonce 5，路由 0 仍需转发
+        relayer.l1_client =
+            RpcClient::new_mock_with_mocks("succeeds".to_string(), message_pda(1000, 1));
+        assert!(relayer.load_transfer(5).await.unwrap().is_none());
+        assert!(relayer.completed_nonces.contains(&5));
+        relayer.l1_client =
+            RpcClient::new_mock_with_mocks("succeeds".to_string(), message_pda(1000, 0));
+        let (transfer_info, _) = relayer.load_transfer(5).await.unwrap().unwrap();
+        assert_eq!(transfer_info.selector, Some(0));
+    }
+
+    #[test]
+    fn adopts_chain_nonce_within_divergence_threshold() {
+        let (_dir, mut relayer) = relayer(
+            Some(100),
+            serde_json::json!({ "cursor_divergence_threshold": 5, "cursor_divergence_policy": "halt" }),
+        );
+        relayer.reconcile_cursor(104).unwrap();
+        assert_eq!(relayer.last_nonce, Some(104));
+    }
+
+    #[test]
+    fn trust_chain_adopts_divergent_chain_nonce() {
+        let (_dir, mut relayer) = relayer(
+            Some(100),
+            serde_json::json!({ "cursor_divergence_threshold": 5, "cursor_divergence_policy": "trust_chain" }),
+        );
+        relayer.reconcile_cursor(500).unwrap();
+        assert_eq!(relayer.last_nonce, Some(500));
+    }
+
+    #[test]
+    fn trust_local_keeps_divergent_persisted_nonce() {
+        let (_dir, mut relayer) = relayer(
+            Some(100),
+            serde_json::json!({ "cursor_divergence_threshold": 5, "cursor_divergence_policy": "trust_local" }),
+        );
+        relayer.reconcile_cursor(500).unwrap();
+        assert_eq!(relayer.last_nonce, Some(100));
+    }
+
+    #[test]
+    fn halt_rejects_divergent_persisted_nonce() {
+        let (_dir, mut relayer) = relayer(
+            Some(100),
+            serde_json::json!({ "cursor_divergence_threshold": 5, "cursor_divergence_policy": "halt" }),
+        );
+        let err = relayer.reconcile_cursor(500).unwrap_err();
+        assert!(err.to_string().contains("diverges from on-chain nonce 500"));
+        assert_eq!(relayer.last_nonce, Some(100));
+    }
+
+    #[tokio::test]
+    async fn skip_policies_absorb_discriminator_mismatch() {
+        for policy in ["skip", "alert_and_skip"] {
+            let (_dir, relayer) = relayer(
+                None,
+                serde_json::json!({ "discriminator_mismatch_policy": policy }),
+            );
+            relayer
+                .handle_discriminator_mismatch(mismatch())
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn halt_policy_propagates_discriminator_mismatch() {
+        let (_dir, relayer) = relayer(
+            None,
+            serde_json::json!({ "discriminator_mismatch_policy": "halt" }),
+        );
+        let err = relayer
+            .handle_discriminator_mismatch(mismatch())
+            .await
+            .unwrap_err();
+        assert!(err.downcast_ref::<DiscriminatorMismatch>().is_some());
+    }
+
+    #[tokio::test]
+    async fn passes_through_other_errors_under_any_mismatch_policy() {
+        for policy in ["skip", "alert_and_skip", "halt"] {
+            let (_dir, relayer) = relayer(
+                None,
+                serde_json::json!({ "discriminator_mismatch_policy": policy }),
+            );
+            let err = relayer
+                .handle_discriminator_mismatch(anyhow::anyhow!("rpc unavailable"))
+                .await
+                .unwrap_err();
+            assert_eq!(err.to_string(), "rpc unavailable");
+        }
+    }
+
+    #[test]
+    fn skip_policies_advance_past_zero_amounts() {
+        for policy in ["skip_silently", "skip_with_log"] {
+            let (_dir, mut relayer) =
+                relayer(Some(7), serde_json::json!({ "zero_amount_policy": policy }));
+            assert!(relayer.skip_zero_amount(7).unwrap());
+            assert_eq!(relayer.last_nonce, Some(8));
+        }
+    }
+
+    #[test]
+    fn save_state_keeps_skips_written_mid_cycle() {
+        let (_dir, config) = config(Some(7), serde_json::json!({}));
+        let mut relayer = Relayer::new(&config, None).unwrap();
+        relayer.skipped_nonces.insert(3);
+        // 运行中的 relayer 读取状态之后，操作员再跳过一个 nonce
+        skip_nonce(&config, 9, None).unwrap();
+
+        relayer.advance_cursor(7).unwrap();
+        let state = RelayerState::load(config.state_path.as_ref().unwrap())
+            .unwrap()
+            .unwrap();
+        assert_eq!(state.last_nonce, Some(8));
+        assert_eq!(state.skipped_nonces, BTreeSet::from([3, 9]));
+    }
+
+    #[test]
+    fn closes_pdas_with_the_l1_wallet() {
+        let (dir, config) = config(None, serde_json::json!({ "close_pda_after_relay": true }));
+        let err = config.validate().unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("close_pda_after_relay requires l1_wallet_path"));
+
+        let l1_wallet = Keypair::new();
+        let l1_wallet_path = dir.path().join("l1-wallet.json");
+        solana_sdk::signature::write_keypair_file(&l1_wallet, &l1_wallet_path).unwrap();
+        let (_dir, relayer) = relayer(
+            None,
+            serde_json::json!({ "close_pda_after_relay": true, "l1_wallet_path": l1_wallet_path }),
+        );
+        assert_eq!(
+            relayer.l1_wallet.as_ref().unwrap().pubkey(),
+            l1_wallet.pubkey()
+        );
+
+        let (pda, _) = relayer.pda_manager.find_address(7);
+        let transaction = relayer
+            .transaction_builder
+            .close_pda_transaction(
+                &pda,
+                7,
+                relayer.l1_wallet.as_ref().unwrap(),
+                Hash::default(),
+            )
+            .unwrap();
+        assert_eq!(transaction.message.account_keys[0], l1_wallet.pubkey());
+        assert!(!transaction
+            .message
+            .account_keys
+            .contains(&relayer.signer.pubkey()));
+        transaction.verify().unwrap();
+    }
+
+    #[test]
+    fn resigns_destination_creating_relays_with_the_rent_payer() {
+        let rent_payer = Keypair::new();
+        let (dir, config) = config(None, serde_json::json!({}));
+        let rent_payer_path = dir.path().join("rent-payer.json");
+        solana_sdk::signature::write_keypair_file(&rent_payer, &rent_payer_path).unwrap();
+        let (_dir, relayer) = relayer(
+            None,
+            serde_json::json!({
+                "on_missing_destination": "create",
+                "rent_payer_path": rent_payer_path,
+                "wallet_path": config.wallet_path,
+            }),
+        );
+
+        let to = Pubkey::new_unique();
+        let mut signers = relayer.signers();
+        let instructions = [
+            system_instruction::transfer(&rent_payer.pubkey(), &to, 890_880),
+            relayer
+                .transaction_builder
+                .transfer_instruction(1000, 7, 255, &to, &signers)
+                .unwrap(),
+        ];
+        signers.push(relayer.rent_payer.as_ref().unwrap());
+        let mut transaction =
+            Transaction::new_with_payer(&instructions, Some(&signers[0].pubkey()));
+        transaction.sign(&signers, Hash::new_unique());
+        let signer_keys: Vec<Pubkey> = signers.iter().map(|signer| signer.pubkey()).collect();
+
+        // 只用手续费账户重新签名会缺少 rent payer
+        let recent_blockhash = Hash::new_unique();
+        assert!(transaction
+            .clone()
+            .try_sign(&relayer.signers(), recent_blockhash)
+            .is_err());
+        relayer
+            .resign(&mut transaction, &signer_keys, recent_blockhash)
+            .unwrap();
+        assert_eq!(transaction.message.recent_blockhash, recent_blockhash);
+        transaction.verify().unwrap();
+    }
+
+    /// Relayer at cursor 7 tracking nonces 7 and 8 as one pending aggregated
+    /// relay, answered by a mock L2 node, reporting relays to an event file
+    fn pending_relayer(
+        l2_url: &str,
+        mocks: HashMap<RpcRequest, serde_json::Value>,
+    ) -> (TempDir, Relayer) {
+        let (dir, mut config) = config(Some(7), serde_json::json!({ "event_sink": "file" }));
+        let events_path = dir.path().join("events.jsonl");
+        config.event_sink_path = Some(events_path.to_str().unwrap().to_string());
+        let mut relayer = Relayer::new(&config, None).unwrap();
+        relayer.l2_client = RpcClient::new_mock_with_mocks(l2_url.to_string(), mocks);
+        let pending = PendingConfirmation {
+            signature: Signature::new_unique().to_string(),
+            recent_blockhash: Hash::new_unique().to_string(),
+            transfer: Some(PendingTransfer {
+                selector: None,
+                amount: 3000,
+                destination: Pubkey::new_unique().to_string(),
+            }),
+            submitted_at: Some(0),
+        };
+        relayer.pending_confirmations = BTreeMap::from([(7, pending.clone()), (8, pending)]);
+        (dir, relayer)
+    }
+
+    /// RPC encoding of a nonce account at `nonce`, owned by `owner`
+    fn nonce_account_json(owner: &Pubkey, nonce: u64) -> serde_json::Value {
+        let mut data = vec![0; NonceLayout::default().min_len()];
+        data[8..16].copy_from_slice(&nonce.to_le_bytes());
+        serde_json::json!({
+            "lamports": 1_000_000,
+            "data": [base64::encode(data), "base64"],
+            "owner": owner.to_string(),
+            "executable": false,
+            "rentEpoch": 0,
+        })
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn max_uptime_exits_for_a_restart_after_saving_state() {
+        let (dir, mut relayer) = relayer(Some(7), serde_json::json!({ "max_uptime_secs": 0 }));
+        let nonce_account = nonce_account_json(&relayer.transaction_builder.program_id, 9);
+        relayer.l1_client = RpcClient::new_mock_with_mocks(
+            "succeeds".to_string(),
+            HashMap::from([(
+                RpcRequest::GetAccountInfo,
+                serde_json::json!({ "context": { "slot": 1 }, "value": nonce_account }),
+            )]),
+        );
+        relayer.l2_client = RpcClient::new_mock_with_mocks(
+            "succeeds".to_string(),
+            HashMap::from([(
+                RpcRequest::GetMultipleAccounts,
+                serde_json::json!({ "context": { "slot": 1 }, "value": [nonce_account] }),
+            )]),
+        );
+        relayer.last_nonce = Some(9);
+        let (_terminate_sender, terminate) = watch::channel(false);
+
+        let exit = relayer.monitor_and_relay(terminate).await.unwrap();
+        assert_eq!(exit, Exit::Restart);
+        assert_eq!(exit.code(), ExitCode::from(RESTART_EXIT_CODE));
+        shutdown(std::slice::from_ref(&relayer)).await.unwrap();
+        let state = RelayerState::load(dir.path().join("state.json")).unwrap();
+        assert_eq!(state.unwrap().last_nonce, Some(9));
+    }
+
+    fn relay_events(dir: &TempDir) -> Vec<serde_json::Value> {
+        std::fs::read_to_string(dir.path().join("events.jsonl"))
+            .unwrap_or_default()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn late_confirmations_complete_like_timely_ones() {
+        let (dir, mut relayer) = pending_relayer("succeeds", HashMap::new());
+        relayer.recheck_pending_confirmations().await.unwrap();
+
+        assert!(relayer.pending_confirmations.is_empty());
+        assert_eq!(relayer.last_nonce, Some(9));
+        let events = relay_events(&dir);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["transfer"]["nonce"], 8);
+        assert_eq!(events[0]["transfer"]["amount"], 3000);
+        assert!(relayer.metrics.render().contains("relayer_relays_total 1"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn failed_pending_relays_are_retried() {
+        let (dir, mut relayer) = pending_relayer("instruction_error", HashMap::new());
+        relayer.recheck_pending_confirmations().await.unwrap();
+
+        assert!(relayer.pending_confirmations.is_empty());
+        assert_eq!(relayer.last_nonce, Some(7));
+        assert!(relay_events(&dir).is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn expired_pending_relays_are_retried() {
+        let blockhash_valid = |valid| {
+            HashMap::from([(
+                RpcRequest::IsBlockhashValid,
+                serde_json::json!({ "context": { "slot": 1 }, "value": valid }),
+            )])
+        };
+        let (_dir, mut relayer) = pending_relayer("sig_not_found", blockhash_valid(true));
+        relayer.recheck_pending_confirmations().await.unwrap();
+        assert_eq!(relayer.pending_confirmations.len(), 2);
+
+        let (dir, mut relayer) = pending_relayer("sig_not_found", blockhash_valid(false));
+        relayer.recheck_pending_confirmations().await.unwrap();
+        assert!(relayer.pending_confirmations.is_empty());
+        assert_eq!(relayer.last_nonce, Some(7));
+        assert!(relay_events(&dir).is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn offline_signing_skips_what_the_online_path_would() {
+        let (_dir, mut relayer) = relayer(
+            None,
+            serde_json::json!({
+                "zero_amount_policy": "skip_with_log",
+                "relay_filter": { "min_amount": 100 },
+            }),
+        );
+        relayer.l2_client = RpcClient::new_mock("succeeds".to_string());
+        relayer.skipped_nonces.insert(5);
+
+        // 跳过集合中的 nonce 不读取 PDA
+        relayer.l1_client = RpcClient::new_mock("fails".to_string());
+        assert!(relayer.build_and_sign(5, 6).unwrap().is_empty());
+        for (nonce, amount) in [(6, 0), (7, 50)] {
+            relayer.l1_client =
+                RpcClient::new_mock_with_mocks("succeeds".to_string(), message_pda(amount, 0));
+            assert!(relayer.build_and_sign(nonce, nonce + 1).unwrap().is_empty());
+        }
+        relayer.l1_client =
+            RpcClient::new_mock_with_mocks("succeeds".to_string(), message_pda(500, 0));
+        let relays = relayer.build_and_sign(8, 9).unwrap();
+        assert_eq!(relays.len(), 1);
+        assert_eq!(relays[0].nonce, 8);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn skips_the_full_read_of_an_unchanged_watched_account() {
+        let (_dir, mut relayer) = relayer(
+            None,
+            serde_json::json!({ "skip_unchanged_watched_account": true }),
+        );
+        let mut data = vec![0xaa; NONCE_OFFSET];
+        data.extend_from_slice(&42u64.to_le_bytes());
+        let account = Account {
+            lamports: 1_000_000,
+            data,
+            owner: Pubkey::new_unique(),
+            executable: false,
+            rent_epoch: 0,
+        };
+        assert_eq!(relayer.unchanged_watched_nonce().unwrap(), None);
+        assert_eq!(relayer.watched_nonce(account).await.unwrap(), Some(42));
+
+        // 只返回 nonce 字节的切片
+        let slice = |nonce: u64, lamports: u64| {
+            HashMap::from([(
+                RpcRequest::GetAccountInfo,
+                serde_json::json!({
+                    "context": { "slot": 7 },
+                    "value": {
+                        "lamports": lamports,
+                        "data": [base64::encode(nonce.to_le_bytes()), "base64"],
+                        "owner": Pubkey::new_unique().to_string(),
+                        "executable": false,
+                        "rentEpoch": 0,
+                    },
+                }),
+            )])
+        };
+        relayer.l1_client =
+            RpcClient::new_mock_with_mocks("succeeds".to_string(), slice(42, 1_000_000));
+        assert_eq!(relayer.unchanged_watched_nonce().unwrap(), Some(42));
+        assert_eq!(relayer.watched_account_slot, Some(7));
+        relayer.l1_client =
+            RpcClient::new_mock_with_mocks("succeeds".to_string(), slice(43, 1_000_000));
+        assert_eq!(relayer.unchanged_watched_nonce().unwrap(), None);
+        relayer.l1_client =
+            RpcClient::new_mock_with_mocks("succeeds".to_string(), slice(42, 2_000_000));
+        assert_eq!(relayer.unchanged_watched_nonce().unwrap(), None);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn waiting_for_confirmation_beats_the_heartbeat() {
+        let (_dir, mut relayer) = relayer(None, serde_json::json!({ "confirm_timeout_secs": 0 }));
+        relayer.l2_client = RpcClient::new_mock_with_mocks(
+            "sig_not_found".to_string(),
+            HashMap::from([(
+                RpcRequest::IsBlockhashValid,
+                serde_json::json!({ "context": { "slot": 1 }, "value": true }),
+            )]),
+        );
+        std::thread::sleep(Duration::from_millis(50));
+        let idle = relayer.heartbeat.elapsed();
+
+        let signature = Signature::new_unique();
+        let confirmation = relayer
+            .confirm_transaction(
+                &signature,
+                &Hash::new_unique(),
+                CommitmentConfig::confirmed(),
+            )
+            .await
+            .unwrap();
+        assert!(
+            matches!(confirmation, Confirmation::TimedOut(timed_out) if timed_out == signature)
+        );
+        assert!(relayer.heartbeat.elapsed() < idle);
+    }
+
+    #[test]
+    fn jitters_the_rescan_window() {
+        let mut rng = rand::thread_rng();
+        assert_eq!(rescan_window(0, 10, &mut rng), 0);
+        assert_eq!(rescan_window(50, 0, &mut rng), 50);
+
+        let windows: BTreeSet<u64> = (0..200).map(|_| rescan_window(50, 10, &mut rng)).collect();
+        assert!(windows.iter().all(|window| (50..=60).contains(window)));
+        assert!(windows.len() > 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn rescan_stays_armed_until_every_nonce_was_rechecked() {
+        let (_dir, mut relayer) = relayer(Some(10), serde_json::json!({}));
+        // L1 节点不返回任何数据，每次读取 PDA 都失败
+        relayer.l1_client = RpcClient::new_mock("fails".to_string());
+        relayer.rescan_queue = (5..8).collect();
+
+        assert!(relayer.continue_rescan().await.is_err());
+        assert_eq!(relayer.rescan_queue, (5..8).collect());
+
+        relayer.on_transfer_error = TransferErrorPolicy::RetryLater;
+        assert!(relayer.continue_rescan().await.unwrap());
+        assert_eq!(relayer.rescan_queue, (5..8).collect());
+
+        relayer.on_transfer_error = TransferErrorPolicy::SkipAndContinue;
+        assert!(relayer.continue_rescan().await.unwrap());
+        assert!(relayer.rescan_queue.is_empty());
+        assert_eq!(relayer.last_nonce, Some(10));
+    }
+
+    #[test]
+    fn operator_commands_target_the_stream_state_file() {
+        let (_single_dir, single) = config(None, serde_json::json!({}));
+        let state_path = single.state_path.clone().unwrap();
+        assert_eq!(
+            command_state_path(&single, "resume", None).unwrap(),
+            state_path
+        );
+        assert!(command_state_path(&single, "resume", Some("usdc")).is_err());
+
+        let stream = |name: &str, offset: usize| {
+            serde_json::json!({
+                "name": name,
+                "offset": offset,
+                "nonce_account": Pubkey::new_unique().to_string(),
+            })
+        };
+        let (_streams_dir, streams) = config(
+            None,
+            serde_json::json!({ "nonce_streams": [stream("usdc", 8), stream("sol", 16)] }),
+        );
+        let err = command_state_path(&streams, "skip-nonce", None).unwrap_err();
+        assert!(err.to_string().contains("requires --stream"));
+        assert!(command_state_path(&streams, "skip-nonce", Some("eth")).is_err());
+
+        skip_nonce(&streams, 9, Some("sol")).unwrap();
+        let state_path = streams.state_path.clone().unwrap();
+        let sol_path = command_state_path(&streams, "skip-nonce", Some("sol")).unwrap();
+        assert_eq!(sol_path, format!("{}.sol", state_path));
+        let state = RelayerState::load(&sol_path).unwrap().unwrap();
+        assert_eq!(state.skipped_nonces, BTreeSet::from([9]));
+        assert!(RelayerState::load(&state_path).unwrap().is_none());
+    }
+
+    #[test]
+    fn relay_anyway_keeps_zero_amounts() {
+        let (_dir, mut relayer) = relayer(
+            Some(7),
+            serde_json::json!({ "zero_amount_policy": "relay_anyway" }),
+        );
+        assert!(!relayer.skip_zero_amount(7).unwrap());
+        assert_eq!(relayer.last_nonce, Some(7));
+    }
+
+    #[test]
+    fn reload_applies_to_nonce_streams() {
+        let streams = serde_json::json!({ "nonce_streams": [
+            { "name": "a", "offset": 8, "nonce_account": Pubkey::new_unique().to_string() },
+            { "name": "b", "offset": 16, "nonce_account": Pubkey::new_unique().to_string() },
+        ]});
+        let (_dir, config) = config(None, streams);
+        let stream = &config.nonce_streams[1];
+        let mut relayer = Relayer::new(&stream_config(&config, stream), None).unwrap();
+        relayer.stream = Some(stream.name.clone());
+
+        let mut reloaded = config.clone();
+        reloaded.post_submit_delay_ms = 1234;
+        relayer.reload(&reloaded).unwrap();
+
+        assert_eq!(relayer.post_submit_delay, Duration::from_millis(1234));
+        assert_eq!(relayer.config.post_submit_delay_ms, 1234);
+        assert_eq!(relayer.config.nonce_account, stream.nonce_account);
+        assert_eq!(
+            relayer.config.state_path,
+            stream_config(&config, stream).state_path
+        );
+
+        // 重新加载的配置里没有这个流时保留运行中的配置
+        reloaded.nonce_streams.truncate(1);
+        reloaded.post_submit_delay_ms = 99;
+        relayer.reload(&reloaded).unwrap();
+        assert_eq!(relayer.post_submit_delay, Duration::from_millis(1234));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn late_confirmations_post_to_the_success_webhook() {
+        let (url, mut received) = crate::alert::tests::webhook().await;
+        let (_dir, mut relayer) = pending_relayer("succeeds", HashMap::new());
+        relayer.alerter = Alerter::new(None, Some(url), None);
+        let destination = relayer.pending_confirmations[&7]
+            .transfer
+            .as_ref()
+            .unwrap()
+            .destination
+            .clone();
+        let signature = relayer.pending_confirmations[&7].signature.clone();
+        relayer.recheck_pending_confirmations().await.unwrap();
+
+        let payload = received.recv().await.unwrap();
+        assert_eq!(payload["nonce"], 8);
+        assert_eq!(payload["signature"], signature);
+        assert_eq!(payload["amount"], 3000);
+        assert_eq!(payload["destination"], destination);
+        assert!(payload["slot"].is_u64());
+
+        // 批量模式下通知留到本轮结束统一发送
+        let (_dir, mut relayer) = pending_relayer("succeeds", HashMap::new());
+        relayer.alerter = Alerter::new(None, Some("http://127.0.0.1:9".to_string()), None);
+        relayer.success_webhook_batch = true;
+        relayer.recheck_pending_confirmations().await.unwrap();
+        assert_eq!(relayer.pending_notifications.len(), 1);
+        assert_eq!(relayer.pending_notifications[0].nonce, 8);
+    }
+
+    #[tokio::test]
+    async fn shutdown_flushes_batched_alerts() {
+        let (url, mut received) = crate::alert::tests::webhook().await;
+        let (_dir, mut relayer) = relayer(None, serde_json::json!({}));
+        relayer.alerter = Alerter::new(Some(url), None, Some(Duration::from_secs(3600)));
+        relayer.alerter.send("rpc down").await;
+
+        shutdown(std::slice::from_ref(&relayer)).await.unwrap();
+        let summary = received.recv().await.unwrap();
+        assert!(summary["text"]
+            .as_str()
+            .unwrap()
+            .ends_with("- (1x) rpc down"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn fetches_each_message_pda_once_and_caches_missing_ones() {
+        let (_dir, mut relayer) = relayer(None, serde_json::json!({ "cache_missing_pdas": true }));
+        relayer.cursors.shards = vec![0];
+
+        // 除了一次 getAccountInfo 之外的请求都会失败
+        let version = (
+            RpcRequest::GetVersion,
+            serde_json::json!({ "solana-core": "1.14.29" }),
+        );
+        let mut mocks = message_pda(1000, 0);
+        mocks.extend([version.clone()]);
+        relayer.l1_client = RpcClient::new_mock_with_mocks("fails".to_string(), mocks);
+        let (transfer_info, _) = relayer.load_transfer(5).await.unwrap().unwrap();
+        assert_eq!(transfer_info.amount, 1000);
+
+        relayer.l1_client = RpcClient::new_mock_with_mocks(
+            "fails".to_string(),
+            HashMap::from([
+                version,
+                (
+                    RpcRequest::GetAccountInfo,
+                    serde_json::json!({ "context": { "slot": 1 }, "value": null }),
+                ),
+            ]),
+        );
+        assert!(relayer.load_transfer(6).await.unwrap().is_none());
+        assert!(relayer.missing_pdas.contains(&6));
+        assert!(relayer.load_transfer(6).await.unwrap().is_none());
+        assert!(relayer.load_transfer(7).await.is_err());
+    }
+
+    /// Watched account holding `nonce` at `NONCE_OFFSET`, padded to `len` bytes
+    fn watched_account(nonce: u64, len: usize) -> Account {
+        let mut data = vec![0; len];
+        data[NONCE_OFFSET..NONCE_OFFSET + 8].copy_from_slice(&nonce.to_le_bytes());
+        Account {
+            lamports: 1_000_000,
+            data,
+            owner: Pubkey::new_unique(),
+            executable: false,
+            rent_epoch: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn alerts_when_the_watched_account_changes_size() {
+        let (url, mut received) = crate::alert::tests::webhook().await;
+        let (_dir, mut relayer) = relayer(None, serde_json::json!({}));
+        relayer.alerter = Alerter::new(Some(url), None, None);
+
+        assert_eq!(
+            relayer.watched_nonce(watched_account(3, 32)).await.unwrap(),
+            Some(3)
+        );
+        assert_eq!(
+            relayer.watched_nonce(watched_account(4, 32)).await.unwrap(),
+            Some(4)
+        );
+        assert_eq!(
+            relayer.watched_nonce(watched_account(5, 24)).await.unwrap(),
+            Some(5)
+        );
+        assert_eq!(relayer.watched_account_size, Some(24));
+        let alert = received.recv().await.unwrap();
+        assert_eq!(
+            alert["text"],
+            format!(
+                "Watched account {} shrank from 32 to 24 bytes, check for an account migration",
+                relayer.watched_account
+            )
+        );
+
+        relayer.watched_nonce(watched_account(6, 40)).await.unwrap();
+        let alert = received.recv().await.unwrap();
+        assert!(alert["text"]
+            .as_str()
+            .unwrap()
+            .contains("grew from 24 to 40 bytes"));
+    }
+
+    #[test]
+    fn oversized_initial_nonce_needs_confirmation() {
+        let (_dir, mut fresh) =
+            relayer(None, serde_json::json!({ "max_initial_nonce_jump": 1000 }));
+        let err = fresh.reconcile_cursor(5000).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("First observed on-chain nonce 5000 exceeds max_initial_nonce_jump 1000"));
+        assert_eq!(fresh.last_nonce, None);
+        fresh.reconcile_cursor(1000).unwrap();
+        assert_eq!(fresh.last_nonce, Some(1000));
+
+        let (_dir, mut confirmed) = relayer(
+            None,
+            serde_json::json!({ "max_initial_nonce_jump": 1000, "allow_large_initial_nonce": true }),
+        );
+        confirmed.reconcile_cursor(5000).unwrap();
+        assert_eq!(confirmed.last_nonce, Some(5000));
+
+        // 有持久化状态时不做初始检查
+        let (_dir, mut persisted) = relayer(
+            Some(4990),
+            serde_json::json!({ "max_initial_nonce_jump": 1000, "cursor_divergence_threshold": 100 }),
+        );
+        persisted.reconcile_cursor(5000).unwrap();
+        assert_eq!(persisted.last_nonce, Some(5000));
+    }
+
+    #[test]
+    fn processing_order_changes_attempts_but_not_the_cursor() {
+        for (order, attempts) in [
+            ("ascending", vec![7, 8, 9, 10]),
+            ("descending", vec![10, 9, 8, 7]),
+        ] {
+            let (_dir, mut relayer) =
+                relayer(Some(7), serde_json::json!({ "processing_order": order }));
+            let pending = relayer.pending_nonces(7, 11);
+            assert_eq!(pending, attempts, "{}", order);
+
+            // cursor 只从底部连续推进
+            let mut cursors = Vec::new();
+            for nonce in pending {
+                relayer.advance_cursor(nonce).unwrap();
+                cursors.push(relayer.last_nonce.unwrap());
+            }
+            let expected = match order {
+                "ascending" => vec![8, 9, 10, 11],
+                _ => vec![7, 7, 7, 11],
+            };
+            assert_eq!(cursors, expected, "{}", order);
+        }
+
+        // 上一轮失败的 nonce 排在最前，cursor 之前的丢弃
+        let (_dir, mut relayer) = relayer(
+            Some(7),
+            serde_json::json!({ "processing_order": "descending" }),
+        );
+        relayer.retry_queue = BTreeSet::from([5, 8]);
+        assert_eq!(relayer.pending_nonces(7, 11), vec![8, 10, 9, 7]);
+        assert!(relayer.retry_queue.is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn rereads_message_pdas_that_are_still_short() {
+        let (_dir, mut relayer) = relayer(
+            None,
+            serde_json::json!({ "pda_read_retries": 1, "pda_read_retry_delay_ms": 0 }),
+        );
+        relayer.cursors.shards = vec![0];
+        let short = |relayer: &mut Relayer, nonce| {
+            let (pda, _) = relayer.pda_manager.find_address(nonce);
+            relayer.prefetched_pdas.insert(pda, watched_account(0, 40));
+        };
+
+        // 第一次读到的数据不完整，重读后正常解析
+        short(&mut relayer, 5);
+        relayer.l1_client =
+            RpcClient::new_mock_with_mocks("succeeds".to_string(), message_pda(1000, 0));
+        let (transfer_info, _) = relayer.load_transfer(5).await.unwrap().unwrap();
+        assert_eq!(transfer_info.amount, 1000);
+
+        // 重读次数用完后跳过并告警，而不是报错
+        short(&mut relayer, 6);
+        let mut data = vec![0; 40];
+        data[..8].copy_from_slice(&[1; 8]);
+        relayer.l1_client = RpcClient::new_mock_with_mocks(
+            "succeeds".to_string(),
+            HashMap::from([(
+                RpcRequest::GetAccountInfo,
+                serde_json::json!({
+                    "context": { "slot": 1 },
+                    "value": {
+                        "lamports": 1_000_000,
+                        "data": [base64::encode(data), "base64"],
+                        "owner": Pubkey::new_unique().to_string(),
+                        "executable": false,
+                        "rentEpoch": 0,
+                    },
+                }),
+            )]),
+        );
+        assert!(relayer.load_transfer(6).await.unwrap().is_none());
+        assert!(relayer
+            .metrics
+            .render()
+            .contains("relayer_skipped_total{reason=\"incomplete_pda\"} 1"));
+        assert!(!relayer.completed_nonces.contains(&6));
+    }
+
+    #[test]
+    fn aggregates_transfers_per_route_and_destination() {
+        let (alice, bob) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let transfer = |nonce, amount, to, selector| {
+            (
+                nonce,
+                TransferInfo {
+                    amount,
+                    to,
+                    selector,
+                    owner: None,
+                },
+                nonce as u8,
+            )
+        };
+
+        let groups = AggregatedTransfer::group([
+            transfer(9, 100, alice, None),
+            transfer(7, 10, alice, None),
+            transfer(8, 5, bob, None),
+            transfer(10, 1, alice, Some(1)),
+            transfer(11, 20, alice, None),
+        ])
+        .unwrap();
+        assert_eq!(groups.len(), 3);
+        let alice_group = &groups[&(None, alice)];
+        assert_eq!(alice_group.nonces, vec![9, 7, 11]);
+        assert_eq!(alice_group.amount, 130);
+        assert_eq!((alice_group.nonce, alice_group.bump), (11, 11));
+        let bob_group = &groups[&(None, bob)];
+        assert_eq!((bob_group.nonces.clone(), bob_group.amount), (vec![8], 5));
+        assert_eq!(groups[&(Some(1), alice)].nonces, vec![10]);
+
+        let err = AggregatedTransfer::group([
+            transfer(1, u64::MAX, alice, None),
+            transfer(2, 1, alice, None),
+        ])
+        .err()
+        .unwrap();
+        assert_eq!(
+            err.to_string(),
+            format!("Aggregated amount for {} overflows", alice)
+        );
+    }
+
+    #[test]
+    fn reads_every_shard_cursor() {
+        let shards = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let (_dir, mut relayer) = relayer(
+            None,
+            serde_json::json!({ "nonce_shards": shards.map(|shard| shard.to_string()) }),
+        );
+        let program_id = relayer.transaction_builder.program_id;
+        let read = |relayer: &mut Relayer, accounts: serde_json::Value| {
+            relayer.l2_client = RpcClient::new_mock_with_mocks(
+                "succeeds".to_string(),
+                HashMap::from([(
+                    RpcRequest::GetMultipleAccounts,
+                    serde_json::json!({ "context": { "slot": 1 }, "value": accounts }),
+                )]),
+            );
+            relayer.read_cursors()
+        };
+
+        let accounts = serde_json::json!([
+            nonce_account_json(&program_id, 12),
+            nonce_account_json(&program_id, 9),
+        ]);
+        assert_eq!(read(&mut relayer, accounts).unwrap().shards, vec![12, 9]);
+
+        // 缺失的分片账户报告具体的账户地址
+        let accounts = serde_json::json!([nonce_account_json(&program_id, 12), null]);
+        let err = read(&mut relayer, accounts).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains(&format!("Nonce account {} not found", shards[1])));
+    }
+
+    /// Mock node whose watched account holds `nonce` at `NONCE_OFFSET`
+    fn watched_account_node(nonce: u64) -> RpcClient {
+        let account = watched_account(nonce, 32);
+        RpcClient::new_mock_with_mocks(
+            "succeeds".to_string(),
+            HashMap::from([(
+                RpcRequest::GetAccountInfo,
+                serde_json::json!({
+                    "context": { "slot": 1 },
+                    "value": {
+                        "lamports": account.lamports,
+                        "data": [base64::encode(account.data), "base64"],
+                        "owner": account.owner.to_string(),
+                        "executable": false,
+                        "rentEpoch": 0,
+                    },
+                }),
+            )]),
+        )
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn blocks_relaying_when_the_verifier_disagrees() {
+        let (url, mut received) = crate::alert::tests::webhook().await;
+        let (_dir, mut relayer) =
+            relayer(None, serde_json::json!({ "verifier_nonce_tolerance": 5 }));
+        relayer.alerter = Alerter::new(Some(url), None, None);
+
+        relayer.verifier_client = Some(watched_account_node(50));
+        assert!(relayer.verify_watched_nonce(55).await.unwrap());
+        relayer.verifier_client = Some(watched_account_node(50));
+        assert!(!relayer.verify_watched_nonce(57).await.unwrap());
+        assert_eq!(
+            received.recv().await.unwrap()["text"],
+            "Watched nonce 57 disagrees with verifier endpoint nonce 50 (tolerance 5), not relaying"
+        );
+
+        relayer.verifier_client = None;
+        assert!(relayer.verify_watched_nonce(1000).await.unwrap());
+    }
+
+    #[test]
+    fn defers_relaying_until_the_gap_is_reached_then_drains() {
+        let (_dir, mut gated) = relayer(None, serde_json::json!({ "min_gap_to_relay": 3 }));
+        assert!(!gated.gap_reached(1));
+        assert!(!gated.gap_reached(2));
+        assert!(gated.gap_reached(3));
+        // 达到阈值后持续处理直到积压清空
+        assert!(gated.gap_reached(1));
+        assert!(gated.gap_reached(0));
+        assert!(gated.caught_up);
+        assert!(!gated.gap_reached(2));
+
+        let (_dir, mut default) = relayer(None, serde_json::json!({}));
+        assert_eq!(default.min_gap_to_relay, 1);
+        assert!(default.gap_reached(1));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn confirmed_relays_must_advance_the_nonce_account() {
+        let (url, mut received) = crate::alert::tests::webhook().await;
+        let node = |relayer: &Relayer, nonce| {
+            let account = nonce_account_json(&relayer.transaction_builder.program_id, nonce);
+            HashMap::from([(
+                RpcRequest::GetAccountInfo,
+                serde_json::json!({ "context": { "slot": 1 }, "value": account }),
+            )])
+        };
+
+        // 交易已确认但 nonce 账户没有推进
+        let (dir, mut relayer) = pending_relayer("succeeds", HashMap::new());
+        relayer.verify_state_change = true;
+        relayer.alerter = Alerter::new(Some(url), None, None);
+        relayer.l2_client =
+            RpcClient::new_mock_with_mocks("succeeds".to_string(), node(&relayer, 8));
+        relayer.recheck_pending_confirmations().await.unwrap();
+        assert_eq!(relayer.last_nonce, Some(7));
+        assert!(relay_events(&dir).is_empty());
+        let alert = received.recv().await.unwrap();
+        assert!(alert["text"]
+            .as_str()
+            .unwrap()
+            .starts_with("Relay of nonce 8 confirmed in "));
+        assert!(alert["text"].as_str().unwrap().ends_with("is still at 8"));
+
+        let (dir, mut relayer) = pending_relayer("succeeds", HashMap::new());
+        relayer.verify_state_change = true;
+        relayer.l2_client =
+            RpcClient::new_mock_with_mocks("succeeds".to_string(), node(&relayer, 9));
+        relayer.recheck_pending_confirmations().await.unwrap();
+        assert_eq!(relayer.last_nonce, Some(9));
+        assert_eq!(relay_events(&dir).len(), 1);
+    }
+
+    /// Mock L2 node returning a confirmed relay transaction with these logs
+    fn node_with_logs(logs: &[&str]) -> RpcClient {
+        node_with_meta(serde_json::json!({
+            "err": null,
+            "status": { "Ok": null },
+            "fee": 5000,
+            "preBalances": [],
+            "postBalances": [],
+            "logMessages": logs,
+        }))
+    }
+
+    /// Node returning a confirmed transaction with `meta` from `getTransaction`
+    fn node_with_meta(meta: serde_json::Value) -> RpcClient {
+        let transaction = Transaction::new_with_payer(&[], Some(&Pubkey::new_unique()));
+        RpcClient::new_mock_with_mocks(
+            "succeeds".to_string(),
+            HashMap::from([(
+                RpcRequest::GetTransaction,
+                serde_json::json!({
+                    "slot": 2,
+                    "transaction": [
+                        base64::encode(bincode::serialize(&transaction).unwrap()),
+                        "base64",
+                    ],
+                    "meta": meta,
+                    "blockTime": null,
+                }),
+            )]),
+        )
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn alerts_when_relay_logs_lack_the_expected_line() {
+        let (url, mut received) = crate::alert::tests::webhook().await;
+        let (_dir, mut relayer) = relayer(
+            None,
+            serde_json::json!({ "expected_log_substring": "Program log: relayed" }),
+        );
+        relayer.alerter = Alerter::new(Some(url), None, None);
+
+        relayer.l2_client = node_with_logs(&[
+            "Program invoke [1]",
+            "Program log: relayed nonce 7",
+            "Program success",
+        ]);
+        relayer
+            .check_expected_log(7, &Signature::new_unique())
+            .await;
+
+        let signature = Signature::new_unique();
+        relayer.l2_client = node_with_logs(&["Program invoke [1]", "Program success"]);
+        relayer.check_expected_log(9, &signature).await;
+
+        assert_eq!(
+            received.recv().await.unwrap()["text"],
+            format!(
+                "Relay of nonce 9 confirmed in {} but its logs lack 'Program log: relayed'",
+                signature
+            )
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn counts_skipped_nonces_by_reason() {
+        let (_dir, mut relayer) = relayer(
+            Some(3),
+            serde_json::json!({
+                "zero_amount_policy": "skip_with_log",
+                "relay_filter": { "max_amount": 500 },
+            }),
+        );
+        relayer.cursors.shards = vec![0];
+        relayer.skipped_nonces.insert(3);
+        let skipped = |relayer: &Relayer, reason: &str| {
+            let line = format!("relayer_skipped_total{{reason=\"{}\"}} ", reason);
+            relayer
+                .metrics
+                .render()
+                .lines()
+                .find_map(|sample| sample.strip_prefix(line.as_str()))
+                .unwrap()
+                .parse::<u64>()
+                .unwrap()
+        };
+
+        assert!(relayer.load_transfer(3).await.unwrap().is_none());
+        relayer.l1_client = RpcClient::new_mock("succeeds".to_string());
+        assert!(relayer.load_transfer(4).await.unwrap().is_none());
+        relayer.l1_client =
+            RpcClient::new_mock_with_mocks("succeeds".to_string(), message_pda(0, 0));
+        assert!(relayer.load_transfer(5).await.unwrap().is_none());
+        relayer.l1_client =
+            RpcClient::new_mock_with_mocks("succeeds".to_string(), message_pda(1000, 0));
+        assert!(relayer.load_transfer(6).await.unwrap().is_none());
+        relayer.l1_client =
+            RpcClient::new_mock_with_mocks("succeeds".to_string(), message_pda(100, 0));
+        assert!(relayer.load_transfer(7).await.unwrap().is_some());
+
+        for reason in ["operator_skipped", "missing_pda", "zero_amount", "filtered"] {
+            assert_eq!(skipped(&relayer, reason), 1, "{}", reason);
+        }
+        for reason in ["incomplete_pda", "already_relayed", "transfer_failed"] {
+            assert_eq!(skipped(&relayer, reason), 0, "{}", reason);
+        }
+        // 被过滤的 nonce 默认不推进 cursor
+        assert_eq!(relayer.last_nonce, Some(4));
+        assert!(relayer.completed_nonces.contains(&5));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn alerts_when_the_fee_payer_nears_rent_exemption() {
+        let (url, mut received) = crate::alert::tests::webhook().await;
+        let (_dir, mut relayer) = relayer(
+            None,
+            serde_json::json!({ "fee_payer_fee_reserve_lamports": 10_000 }),
+        );
+        relayer.alerter = Alerter::new(Some(url), None, None);
+        relayer.l2_client = RpcClient::new_mock_with_mocks(
+            "succeeds".to_string(),
+            HashMap::from([(
+                RpcRequest::GetMinimumBalanceForRentExemption,
+                serde_json::json!(890_880),
+            )]),
+        );
+        relayer.min_fee_payer_balance = Some(relayer.fee_payer_threshold().unwrap());
+        assert_eq!(relayer.min_fee_payer_balance, Some(900_880));
+
+        let balance_node = |balance: u64| {
+            RpcClient::new_mock_with_mocks(
+                "succeeds".to_string(),
+                HashMap::from([(
+                    RpcRequest::GetBalance,
+                    serde_json::json!({ "context": { "slot": 1 }, "value": balance }),
+                )]),
+            )
+        };
+        // 余额恢复前不重复告警
+        for balance in [900_881, 900_880, 900_879, 900_000, 2_000_000, 1] {
+            relayer.l2_client = balance_node(balance);
+            relayer.check_fee_payer_balance().await.unwrap();
+        }
+
+        let fee_payer = relayer.signer.pubkey();
+        assert_eq!(
+            received.recv().await.unwrap()["text"],
+            format!(
+                "Fee payer {} balance 900879 lamports is below 900880 lamports, it can't keep paying fees and stay rent exempt",
+                fee_payer
+            )
+        );
+        assert!(received.recv().await.unwrap()["text"]
+            .as_str()
+            .unwrap()
+            .contains("balance 1 lamports is below 900880"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn transfer_error_policies_handle_a_failure_mid_batch() {
+        for policy in ["halt", "skip_and_continue", "retry_later"] {
+            let (_dir, mut relayer) =
+                relayer(Some(7), serde_json::json!({ "on_transfer_error": policy }));
+            relayer.cursors.shards = vec![0];
+            // 7 和 9 直接跳过，8 读取 PDA 时失败
+            relayer.skipped_nonces = BTreeSet::from([7, 9]);
+            relayer.l1_client = RpcClient::new_mock("fails".to_string());
+
+            let result = relayer.relay_pending(vec![7, 8, 9]).await;
+            match policy {
+                "halt" => {
+                    assert!(result.is_err());
+                    assert_eq!(relayer.last_nonce, Some(8));
+                    assert!(!relayer.completed_nonces.contains(&9));
+                }
+                "skip_and_continue" => {
+                    result.unwrap();
+                    assert_eq!(relayer.last_nonce, Some(10));
+                    assert!(relayer.retry_queue.is_empty());
+                }
+                _ => {
+                    result.unwrap();
+                    assert_eq!(relayer.last_nonce, Some(8));
+                    assert!(relayer.completed_nonces.contains(&9));
+                    assert_eq!(relayer.retry_queue, BTreeSet::from([8]));
+                }
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn measures_clock_skew_against_a_skewed_cluster() {
+        // 集群时间比本地慢 120s
+        let skewed_node = || {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs();
+            RpcClient::new_mock_with_mocks(
+                "succeeds".to_string(),
+                HashMap::from([(RpcRequest::GetBlockTime, serde_json::json!(now - 120))]),
+            )
+        };
+
+        let (_dir, mut tolerated) =
+            relayer(None, serde_json::json!({ "max_clock_skew_secs": 300 }));
+        tolerated.l2_client = skewed_node();
+        tolerated.check_clock_skew().unwrap();
+        assert!((120..=121).contains(&tolerated.clock_skew.as_secs()));
+
+        let (_dir, mut warned) = relayer(None, serde_json::json!({ "max_clock_skew_secs": 30 }));
+        warned.l2_client = skewed_node();
+        warned.check_clock_skew().unwrap();
+        assert!((120..=121).contains(&warned.clock_skew.as_secs()));
+
+        let (_dir, mut halted) = relayer(
+            None,
+            serde_json::json!({ "max_clock_skew_secs": 30, "halt_on_clock_skew": true }),
+        );
+        halted.l2_client = skewed_node();
+        let err = halted.check_clock_skew().unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("exceeds max_clock_skew_secs (30s)"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn missing_pdas_become_gaps_after_the_grace_period() {
+        let (url, mut received) = crate::alert::tests::webhook().await;
+        let (_dir, mut relayer) = relayer(
+            Some(5),
+            serde_json::json!({ "pda_missing_grace_secs": 60, "cache_missing_pdas": true }),
+        );
+        relayer.alerter = Alerter::new(Some(url), None, None);
+        relayer.cursors.shards = vec![0];
+        relayer.l1_client = RpcClient::new_mock("succeeds".to_string());
+
+        // 宽限期内只是暂时跳过
+        assert!(relayer.load_transfer(5).await.unwrap().is_none());
+        assert!(relayer.load_transfer(5).await.unwrap().is_none());
+        assert!(relayer.missing_first_seen.contains_key(&5));
+        assert!(!relayer.confirmed_gaps.contains(&5));
+        assert!(!relayer.missing_pdas.contains(&5));
+
+        // 模拟跨多轮经过宽限期
+        let first_seen = relayer.missing_first_seen.get_mut(&5).unwrap();
+        *first_seen -= Duration::from_secs(61);
+        assert!(relayer.load_transfer(5).await.unwrap().is_none());
+        assert!(relayer.confirmed_gaps.contains(&5));
+        assert!(relayer.missing_pdas.contains(&5));
+        assert!(!relayer.missing_first_seen.contains_key(&5));
+        assert_eq!(
+            received.recv().await.unwrap()["text"],
+            "Message PDA for nonce 5 still missing after 61s, treating it as a gap"
+        );
+        assert_eq!(relayer.last_nonce, Some(5));
+    }
+
+    /// Mock L2 WebSocket endpoint that accepts one `signatureSubscribe` and
+    /// notifies it with `err`, returning its URL
+    fn signature_notifier(err: serde_json::Value) -> String {
+        let listener = std::net::TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut socket = tungstenite::accept(stream).unwrap();
+            let request: serde_json::Value =
+                serde_json::from_str(&socket.read_message().unwrap().into_text().unwrap()).unwrap();
+            assert_eq!(request["method"], "signatureSubscribe");
+            for message in [
+                serde_json::json!({ "jsonrpc": "2.0", "result": 42, "id": request["id"] }),
+                serde_json::json!({
+                    "jsonrpc": "2.0",
+                    "method": "signatureNotification",
+                    "params": {
+                        "result": { "context": { "slot": 5 }, "value": { "err": err } },
+                        "subscription": 42,
+                    },
+                }),
+            ] {
+                socket
+                    .write_message(tungstenite::Message::Text(message.to_string()))
+                    .unwrap();
+            }
+            // 断开连接，订阅线程随之退出
+        });
+        url
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn confirms_relays_through_signature_notifications() {
+        let signature = Signature::new_unique();
+        let recent_blockhash = Hash::new_unique();
+        let ws_relayer = |url: String| {
+            relayer(
+                None,
+                serde_json::json!({ "use_ws_confirmation": true, "l2_ws_url": url }),
+            )
+        };
+
+        let (_dir, confirmed) = ws_relayer(signature_notifier(serde_json::Value::Null));
+        let subscription = confirmed
+            .subscribe_signature(&signature, CommitmentConfig::confirmed())
+            .unwrap();
+        let confirmation = confirmed
+            .confirm_via_subscription(&signature, &recent_blockhash, &subscription)
+            .await
+            .unwrap();
+        assert!(
+            matches!(confirmation, Some(Confirmation::Confirmed(landed)) if landed == signature)
+        );
+
+        let (_dir, failed) = ws_relayer(signature_notifier(
+            serde_json::json!({ "InstructionError": [0, { "Custom": 1 }] }),
+        ));
+        let subscription = failed
+            .subscribe_signature(&signature, CommitmentConfig::confirmed())
+            .unwrap();
+        assert!(failed
+            .confirm_via_subscription(&signature, &recent_blockhash, &subscription)
+            .await
+            .is_err());
+
+        // 订阅失败时退回轮询
+        let closed = std::net::TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let url = format!("ws://{}", closed.local_addr().unwrap());
+        drop(closed);
+        let (_dir, unreachable) = ws_relayer(url);
+        assert!(unreachable
+            .subscribe_signature(&signature, CommitmentConfig::confirmed())
+            .is_none());
+
+        let (_dir, polling) = relayer(None, serde_json::json!({}));
+        assert!(polling
+            .subscribe_signature(&signature, CommitmentConfig::confirmed())
+            .is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn checks_missing_destinations_before_relaying() {
+        let transfer_info = TransferInfo {
+            amount: 1000,
+            to: Pubkey::new_unique(),
+            selector: None,
+            owner: None,
+        };
+        let existing = serde_json::json!({
+            "context": { "slot": 1 },
+            "value": {
+                "lamports": 1_000_000,
+                "data": ["", "base64"],
+                "owner": Pubkey::default().to_string(),
+                "executable": false,
+                "rentEpoch": 0,
+            },
+        });
+
+        for policy in ["relay", "skip", "create"] {
+            let (_dir, mut relayer) = relayer(
+                None,
+                serde_json::json!({ "on_missing_destination": policy }),
+            );
+            relayer.l2_client = RpcClient::new_mock_with_mocks(
+                "succeeds".to_string(),
+                HashMap::from([(RpcRequest::GetAccountInfo, existing.clone())]),
+            );
+            let instructions = relayer.destination_instructions(&transfer_info).unwrap();
+            assert_eq!(instructions, Some(Vec::new()), "{}", policy);
+        }
+
+        // 目标账户不存在时跳过
+        let (_dir, mut skipping) = relayer(
+            None,
+            serde_json::json!({ "on_missing_destination": "skip" }),
+        );
+        skipping.l2_client = RpcClient::new_mock("succeeds".to_string());
+        assert_eq!(
+            skipping.destination_instructions(&transfer_info).unwrap(),
+            None
+        );
+
+        // 目标账户不存在时由 rent payer 转入免租金额
+        let (_dir, mut creating) = relayer(
+            None,
+            serde_json::json!({ "on_missing_destination": "create" }),
+        );
+        creating.l2_client = RpcClient::new_mock_with_mocks(
+            "succeeds".to_string(),
+            HashMap::from([(
+                RpcRequest::GetMinimumBalanceForRentExemption,
+                serde_json::json!(890_880),
+            )]),
+        );
+        let funding =
+            system_instruction::transfer(&creating.signer.pubkey(), &transfer_info.to, 890_880);
+        for _ in 0..2 {
+            assert_eq!(
+                creating.destination_instructions(&transfer_info).unwrap(),
+                Some(vec![funding.clone()])
+            );
+        }
+    }
+
+    #[test]
+    fn cools_down_only_after_a_catch_up() {
+        let (_dir, cooled) = relayer(
+            None,
+            serde_json::json!({ "post_catchup_cooldown_secs": 30 }),
+        );
+        let (_dir, uncooled) = relayer(None, serde_json::json!({}));
+        let mut relayers = vec![cooled, uncooled];
+
+        // 空闲轮次
+        assert!(relayers[0].gap_reached(0));
+        assert_eq!(post_catchup_cooldown(&mut relayers), None);
+
+        // 追赶完成后只冷却一次
+        assert!(relayers[0].gap_reached(4));
+        assert_eq!(post_catchup_cooldown(&mut relayers), None);
+        assert!(relayers[0].gap_reached(0));
+        assert_eq!(
+            post_catchup_cooldown(&mut relayers),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(post_catchup_cooldown(&mut relayers), None);
+
+        // 未配置冷却时追赶完成也不等待
+        assert!(relayers[1].gap_reached(4));
+        assert!(relayers[1].gap_reached(0));
+        assert_eq!(post_catchup_cooldown(&mut relayers), None);
+        assert!(!relayers[1].caught_up);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn pauses_submissions_at_the_pending_confirmation_limit() {
+        let unconfirmed = || {
+            RpcClient::new_mock_with_mocks(
+                "sig_not_found".to_string(),
+                HashMap::from([(
+                    RpcRequest::IsBlockhashValid,
+                    serde_json::json!({ "context": { "slot": 1 }, "value": true }),
+                )]),
+            )
+        };
+        let (_dir, mut relayer) = pending_relayer("succeeds", HashMap::new());
+        relayer.l2_client = unconfirmed();
+        relayer.max_pending_confirmations = Some(3);
+        assert!(!relayer.at_pending_limit().await.unwrap());
+
+        relayer.max_pending_confirmations = Some(2);
+        assert!(relayer.at_pending_limit().await.unwrap());
+        assert_eq!(relayer.pending_confirmations.len(), 2);
+
+        // 达到上限时不提交新的转发
+        relayer.l2_client = unconfirmed();
+        relayer.cursors.shards = vec![0];
+        relayer.skipped_nonces.insert(9);
+        relayer.relay_pending(vec![9]).await.unwrap();
+        assert!(!relayer.completed_nonces.contains(&9));
+
+        // 确认后恢复提交
+        relayer.l2_client = RpcClient::new_mock("succeeds".to_string());
+        assert!(!relayer.at_pending_limit().await.unwrap());
+        assert!(relayer.pending_confirmations.is_empty());
+        relayer.relay_pending(vec![9]).await.unwrap();
+        assert_eq!(relayer.last_nonce, Some(10));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn excessive_lag_pauses_relaying_until_resumed() {
+        let (url, mut received) = crate::alert::tests::webhook().await;
+        let (_dir, config) = config(
+            Some(7),
+            serde_json::json!({ "emergency_lag_threshold": 100 }),
+        );
+        let mut relayer = Relayer::new(&config, None).unwrap();
+        relayer.alerter = Alerter::new(Some(url), None, None);
+
+        assert!(!relayer.emergency_pause(100).await.unwrap());
+        assert!(relayer.emergency_pause(101).await.unwrap());
+        assert_eq!(
+            received.recv().await.unwrap()["text"],
+            "Lag of 101 nonces exceeds emergency_lag_threshold 100, relaying paused until an operator runs `resume`"
+        );
+        let state_path = config.state_path.as_deref().unwrap();
+        assert!(
+            RelayerState::load(state_path)
+                .unwrap()
+                .unwrap()
+                .emergency_paused
+        );
+        assert!(relayer.metrics.status().paused);
+
+        // 暂停期间每轮直接返回，不读取任何账户
+        relayer.l1_client = RpcClient::new_mock("fails".to_string());
+        relayer.l2_client = RpcClient::new_mock("fails".to_string());
+        relayer.run_cycle().await.unwrap();
+        assert!(relayer.emergency_paused);
+
+        // resume 后允许追赶，直到积压回到阈值以内
+        resume(&config, None).unwrap();
+        let _ = relayer.run_cycle().await;
+        assert!(!relayer.emergency_paused);
+        assert!(!relayer.emergency_pause(5000).await.unwrap());
+        assert!(!relayer.emergency_pause(40).await.unwrap());
+        assert!(relayer.emergency_pause(5000).await.unwrap());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn decision_trace_records_why_a_nonce_was_skipped() {
+        let (_dir, mut traced) = relayer(
+            Some(5),
+            serde_json::json!({ "decision_trace": true, "relay_filter": { "max_amount": 500 } }),
+        );
+        traced.cursors.shards = vec![0];
+        traced.l1_client =
+            RpcClient::new_mock_with_mocks("succeeds".to_string(), message_pda(1000, 0));
+
+        traced.begin_decision(5);
+        assert!(traced.load_transfer(5).await.unwrap().is_none());
+        let decision = traced.decision.clone().unwrap();
+        let (pda, _) = traced.pda_manager.find_address(5);
+        assert_eq!(decision.nonce, 5);
+        assert_eq!(decision.pda, Some(pda.to_string()));
+        assert_eq!(decision.pda_exists, Some(true));
+        assert_eq!(decision.amount, Some(1000));
+        assert_eq!(decision.policies, vec!["skip_set", "relay_filter"]);
+        assert_eq!(decision.action, Action::Skipped);
+        assert!(decision.destination.is_some());
+        assert_eq!(decision.reason.as_deref(), Some("filtered"));
+        traced.finish_decision(&Ok(()));
+        assert!(traced.decision.is_none());
+
+        let (_dir, mut untraced) = relayer(Some(5), serde_json::json!({}));
+        untraced.begin_decision(5);
+        assert!(untraced.decision.is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn program_ids_must_be_executable() {
+        let program_node = |executable: bool| {
+            RpcClient::new_mock_with_mocks(
+                "succeeds".to_string(),
+                HashMap::from([(
+                    RpcRequest::GetAccountInfo,
+                    serde_json::json!({
+                        "context": { "slot": 1 },
+                        "value": {
+                            "lamports": 1_000_000,
+                            "data": ["", "base64"],
+                            "owner": Pubkey::new_unique().to_string(),
+                            "executable": executable,
+                            "rentEpoch": 0,
+                        },
+                    }),
+                )]),
+            )
+        };
+        let (_dir, mut relayer) = relayer(None, serde_json::json!({ "verify_program_ids": true }));
+
+        relayer.l1_client = program_node(true);
+        relayer.l2_client = program_node(true);
+        relayer.verify_program_ids().unwrap();
+
+        relayer.l1_client = program_node(true);
+        relayer.l2_client = program_node(false);
+        let err = relayer.verify_program_ids().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "L2 program {} isn't executable, check the configured program ID",
+                relayer.transaction_builder.program_id
+            )
+        );
+
+        relayer.l1_client = RpcClient::new_mock("succeeds".to_string());
+        let err = relayer.verify_program_ids().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("L1 program {} doesn't exist", relayer.l1_program_id)
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn confirmation_quorum_tolerates_one_disagreeing_node() {
+        let (_dir, mut relayer) = relayer(None, serde_json::json!({}));
+        let signature = Signature::new_unique();
+        relayer.confirmation_quorum = 2;
+        relayer.confirm_timeout = Some(Duration::ZERO);
+
+        // l2_url 已确认，另外两个节点一个未见到交易
+        relayer.quorum_clients = vec![
+            RpcClient::new_mock("sig_not_found".to_string()),
+            RpcClient::new_mock("succeeds".to_string()),
+        ];
+        assert!(relayer.quorum_confirmed(&signature).await);
+
+        relayer.quorum_clients = vec![
+            RpcClient::new_mock("sig_not_found".to_string()),
+            RpcClient::new_mock("instruction_error".to_string()),
+        ];
+        assert!(!relayer.quorum_confirmed(&signature).await);
+
+        relayer.confirmation_quorum = 3;
+        relayer.quorum_clients = vec![
+            RpcClient::new_mock("succeeds".to_string()),
+            RpcClient::new_mock("succeeds".to_string()),
+        ];
+        assert!(relayer.quorum_confirmed(&signature).await);
+    }
+
+    #[test]
+    fn coalesces_rapid_changes_into_one_pass() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        for nonce in 1..=3 {
+            sender.send(nonce).unwrap();
+        }
+        // 窗口结束后才到达的变化留给下一次处理
+        let late = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(300));
+            sender.send(4).unwrap();
+        });
+        let next = |remaining| receiver.recv_timeout(remaining).ok();
+
+        let first = receiver.recv().unwrap();
+        assert_eq!(
+            coalesce(first, Duration::from_millis(100), next),
+            vec![1, 2, 3]
+        );
+        late.join().unwrap();
+        let first = receiver.recv().unwrap();
+        assert_eq!(coalesce(first, Duration::ZERO, next), vec![4]);
+    }
+
+    #[test]
+    fn filtered_nonces_hold_the_cursor_unless_advance_past() {
+        let transfer_info = TransferInfo {
+            amount: 1000,
+            to: Pubkey::new_unique(),
+            selector: None,
+            owner: None,
+        };
+        for advance_past in [false, true] {
+            let (_dir, mut relayer) = relayer(
+                Some(5),
+                serde_json::json!({
+                    "relay_filter": { "max_amount": 500, "advance_past": advance_past },
+                }),
+            );
+            assert!(relayer.filtered(5, &transfer_info).unwrap());
+            let expected = if advance_past { 6 } else { 5 };
+            assert_eq!(relayer.last_nonce, Some(expected));
+        }
+    }
+
+    #[test]
+    fn rejects_nonce_accounts_owned_by_another_program() {
+        let wrong_owner = Pubkey::new_unique();
+        let read = |relayer: &mut Relayer| {
+            relayer.l2_client = RpcClient::new_mock_with_mocks(
+                "succeeds".to_string(),
+                HashMap::from([(
+                    RpcRequest::GetMultipleAccounts,
+                    serde_json::json!({
+                        "context": { "slot": 1 },
+                        "value": [nonce_account_json(&wrong_owner, 12)],
+                    }),
+                )]),
+            );
+            relayer.read_cursors()
+        };
+
+        let (_dir, mut checked) = relayer(
+            None,
+            serde_json::json!({ "verify_nonce_account_owner": true }),
+        );
+        let err = read(&mut checked).unwrap_err();
+        let mismatch = err.downcast_ref::<NonceAccountOwnerMismatch>().unwrap();
+        assert_eq!(mismatch.found, wrong_owner);
+        assert_eq!(mismatch.expected, checked.transaction_builder.program_id);
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Nonce account {} is owned by {}, expected the L2 program {}",
+                checked.transaction_builder.nonce_accounts[0],
+                wrong_owner,
+                checked.transaction_builder.program_id
+            )
+        );
+
+        let (_dir, mut unchecked) = relayer(None, serde_json::json!({}));
+        assert_eq!(read(&mut unchecked).unwrap().shards, vec![12]);
+    }
+
+    #[test]
+    fn skips_cycles_on_a_regressed_slot() {
+        let slot_node = |slot: u64| {
+            RpcClient::new_mock_with_mocks(
+                "succeeds".to_string(),
+                HashMap::from([(RpcRequest::GetSlot, serde_json::json!(slot))]),
+            )
+        };
+        let (_dir, mut guarded) =
+            relayer(None, serde_json::json!({ "slot_regression_tolerance": 10 }));
+        let mut regressed = |l1_slot, l2_slot| {
+            guarded.l1_client = slot_node(l1_slot);
+            guarded.l2_client = slot_node(l2_slot);
+            guarded.slot_regressed().unwrap()
+        };
+
+        assert!(!regressed(100, 200));
+        assert!(!regressed(90, 195));
+        // 切换到落后的 L1 节点
+        assert!(regressed(89, 201));
+        assert!(!regressed(101, 201));
+        assert!(regressed(101, 150));
+        assert_eq!(guarded.highest_slots, (101, 201));
+
+        let (_dir, mut unguarded) = relayer(None, serde_json::json!({}));
+        unguarded.l1_client = RpcClient::new_mock("fails".to_string());
+        assert!(!unguarded.slot_regressed().unwrap());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn prefetches_pending_pdas_in_one_request() {
+        let (_dir, mut bulk) = relayer(
+            None,
+            serde_json::json!({ "bulk_pda_fetch": true, "bulk_pda_filter": "0102" }),
+        );
+        bulk.cursors.shards = vec![0];
+        let program_account = |pubkey: Pubkey, amount: u64| {
+            let mut data = vec![0; 81];
+            data[..2].copy_from_slice(&[1, 2]);
+            data[40..72].copy_from_slice(Pubkey::new_unique().as_ref());
+            data[72..80].copy_from_slice(&amount.to_le_bytes());
+            serde_json::json!({
+                "pubkey": pubkey.to_string(),
+                "account": {
+                    "lamports": 1_000_000,
+                    "data": [base64::encode(data), "base64"],
+                    "owner": Pubkey::new_unique().to_string(),
+                    "executable": false,
+                    "rentEpoch": 0,
+                },
+            })
+        };
+        // 节点返回待处理的 7、8 和一条早已处理的消息
+        let accounts = serde_json::json!([
+            program_account(bulk.pda_manager.find_address(7).0, 700),
+            program_account(bulk.pda_manager.find_address(8).0, 800),
+            program_account(bulk.pda_manager.find_address(2).0, 200),
+        ]);
+        bulk.l1_client = RpcClient::new_mock_with_mocks(
+            "succeeds".to_string(),
+            HashMap::from([(RpcRequest::GetProgramAccounts, accounts)]),
+        );
+        bulk.prefetch_pdas(&[7, 8, 9]);
+        let prefetched: HashSet<Pubkey> = bulk.prefetched_pdas.keys().copied().collect();
+        assert_eq!(
+            prefetched,
+            HashSet::from([
+                bulk.pda_manager.find_address(7).0,
+                bulk.pda_manager.find_address(8).0,
+            ])
+        );
+
+        // 预取的 PDA 不再单独请求
+        bulk.l1_client = RpcClient::new_mock("fails".to_string());
+        let (transfer_info, _) = bulk.load_transfer(8).await.unwrap().unwrap();
+        assert_eq!(transfer_info.amount, 800);
+
+        // 批量请求失败时清空预取，逐个读取
+        bulk.prefetch_pdas(&[7, 8, 9]);
+        assert!(bulk.prefetched_pdas.is_empty());
+    }
+
+    /// JSON-RPC node answering each request with the result of `respond`,
+    /// or an error where it returns `None`, returning the node URL
+    async fn serve_rpc(
+        mut respond: impl FnMut(&serde_json::Value) -> Option<serde_json::Value> + Send + 'static,
+    ) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = vec![0; 4096];
+                // 读完请求头和 Content-Length 指定的请求体
+                let body = loop {
+                    let read = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..read]);
+                    let text = String::from_utf8_lossy(&request);
+                    if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                        let length = head
+                            .lines()
+                            .find_map(|line| {
+                                line.to_ascii_lowercase()
+                                    .strip_prefix("content-length:")
+                                    .map(|value| value.trim().parse::<usize>().unwrap())
+                            })
+                            .unwrap_or(0);
+                        if body.len() >= length || read == 0 {
+                            break body.to_string();
+                        }
+                    }
+                };
+                let request: serde_json::Value = serde_json::from_str(&body).unwrap();
+                // 客户端先查询节点版本以选择请求格式
+                let result = match request["method"].as_str() {
+                    Some("getVersion") => Some(serde_json::json!({ "solana-core": "1.14.29" })),
+                    _ => respond(&request),
+                };
+                let response = match result {
+                    Some(result) => {
+                        serde_json::json!({ "jsonrpc": "2.0", "id": request["id"], "result": result })
+                    }
+                    None => serde_json::json!({
+                        "jsonrpc": "2.0",
+                        "id": request["id"],
+                        "error": { "code": -32000, "message": "request failed" },
+                    }),
+                }
+                .to_string();
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+                    response.len(),
+                    response
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        url
+    }
+
+    /// Node answering `getAccountInfo` from `accounts` by pubkey, failing the
+    /// read of any other account
+    async fn serve_accounts(accounts: HashMap<Pubkey, serde_json::Value>) -> String {
+        serve_rpc(move |request| {
+            let pubkey: Pubkey = request["params"][0].as_str().unwrap().parse().unwrap();
+            accounts
+                .get(&pubkey)
+                .map(|account| serde_json::json!({ "context": { "slot": 1 }, "value": account }))
+        })
+        .await
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn splits_a_failed_batch_to_isolate_the_bad_transfer() {
+        let (_dir, mut batched) = relayer(
+            Some(7),
+            serde_json::json!({
+                "aggregate_by_destination": true,
+                "split_failed_batches": true,
+                "on_transfer_error": "retry_later",
+                "instruction_template": [
+                    { "source": "amount", "width": 8 },
+                    { "source": "nonce", "width": 8 },
+                ],
+            }),
+        );
+        batched.cursors.shards = vec![0];
+        let to = Pubkey::new_unique();
+        let mut accounts = HashMap::new();
+        // 合并时三笔都能解析，逐个重发时读取 8 的 PDA 出错
+        for nonce in [7u64, 8, 9] {
+            let mut data = vec![0; 81];
+            data[40..72].copy_from_slice(to.as_ref());
+            data[72..80].copy_from_slice(&(nonce * 100).to_le_bytes());
+            let pda = batched.pda_manager.find_address(nonce).0;
+            if nonce != 8 {
+                accounts.insert(
+                    pda,
+                    serde_json::json!({
+                        "lamports": 1_000_000,
+                        "data": [base64::encode(&data), "base64"],
+                        "owner": Pubkey::new_unique().to_string(),
+                        "executable": false,
+                        "rentEpoch": 0,
+                    }),
+                );
+            }
+            let account = Account {
+                lamports: 1_000_000,
+                data,
+                owner: Pubkey::new_unique(),
+                executable: false,
+                rent_epoch: 0,
+            };
+            batched.prefetched_pdas.insert(pda, account);
+        }
+        batched.l1_client = RpcClient::new(serve_accounts(accounts).await);
+        // 合并交易被节点拒绝，之后的提交正常
+        batched.l2_client = RpcClient::new_mock_with_mocks(
+            "succeeds".to_string(),
+            HashMap::from([(
+                RpcRequest::SendTransaction,
+                serde_json::json!(Signature::new_unique().to_string()),
+            )]),
+        );
+
+        batched.relay_aggregated(&[7, 8, 9]).await.unwrap();
+        // 7 和 9 单独转发成功，8 留待下一轮重试
+        assert_eq!(batched.last_nonce, Some(8));
+        assert!(batched.completed_nonces.contains(&9));
+        assert_eq!(batched.retry_queue, BTreeSet::from([8]));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn confirmed_relays_that_failed_on_chain_are_failures() {
+        let (_dir, mut verified) = relayer(
+            Some(7),
+            serde_json::json!({ "verify_tx_success": true, "on_transfer_error": "retry_later" }),
+        );
+        verified.cursors.shards = vec![0];
+        let message = |verified: &mut Relayer| {
+            let mut data = vec![0; 81];
+            data[40..72].copy_from_slice(Pubkey::new_unique().as_ref());
+            data[72..80].copy_from_slice(&1000u64.to_le_bytes());
+            let (pda, _) = verified.pda_manager.find_address(7);
+            let account = Account {
+                lamports: 1_000_000,
+                data,
+                owner: Pubkey::new_unique(),
+                executable: false,
+                rent_epoch: 0,
+            };
+            verified.prefetched_pdas.insert(pda, account);
+        };
+        let meta = |err: serde_json::Value| {
+            serde_json::json!({
+                "err": err,
+                "status": { "Ok": null },
+                "fee": 5000,
+                "preBalances": [],
+                "postBalances": [],
+            })
+        };
+
+        // 交易已确认但执行失败，按转发失败处理
+        message(&mut verified);
+        verified.l2_client = node_with_meta(meta(
+            serde_json::json!({ "InstructionError": [0, "InvalidArgument"] }),
+        ));
+        verified.relay_pending(vec![7]).await.unwrap();
+        assert_eq!(verified.last_nonce, Some(7));
+        assert_eq!(verified.retry_queue, BTreeSet::from([7]));
+
+        message(&mut verified);
+        verified.l2_client = node_with_meta(meta(serde_json::Value::Null));
+        verified.relay_pending(vec![7]).await.unwrap();
+        assert_eq!(verified.last_nonce, Some(8));
+    }
+
+    #[tokio::test]
+    async fn empty_watched_accounts_are_not_yet_initialized() {
+        let (_dir, mut relayer) = relayer(None, serde_json::json!({}));
+        let mut account = watched_account(0, 24);
+
+        // 没有数据的账户只是尚未初始化
+        account.data.clear();
+        assert_eq!(relayer.watched_nonce(account.clone()).await.unwrap(), None);
+        assert_eq!(relayer.watched_account_size, None);
+
+        // 有数据但长度不足是布局问题
+        account.data = vec![0; NONCE_OFFSET + 4];
+        assert!(relayer.watched_nonce(account).await.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn retries_startup_reads_while_the_node_warms_up() {
+        let start = |startup_retries: u32| async move {
+            let (dir, mut starting) = relayer(
+                Some(7),
+                serde_json::json!({
+                    "startup_retries": startup_retries,
+                    "startup_retry_delay_secs": 0,
+                }),
+            );
+            let nonce_account = nonce_account_json(&starting.transaction_builder.program_id, 7);
+            // L2 节点的前两次请求失败，之后恢复
+            let mut failures = 2;
+            let l2_url = serve_rpc(move |request| {
+                if failures > 0 {
+                    failures -= 1;
+                    return None;
+                }
+                let value = match request["method"].as_str().unwrap() {
+                    "getSlot" => return Some(serde_json::json!(1)),
+                    "getBlockTime" => return None,
+                    "getMinimumBalanceForRentExemption" => return Some(serde_json::json!(20)),
+                    "getMultipleAccounts" => serde_json::json!([nonce_account]),
+                    _ => serde_json::Value::Null,
+                };
+                Some(serde_json::json!({ "context": { "slot": 1 }, "value": value }))
+            })
+            .await;
+            starting.l2_client = RpcClient::new(l2_url);
+            let watched = watched_account(7, 40);
+            starting.l1_client = RpcClient::new_mock_with_mocks(
+                "succeeds".to_string(),
+                HashMap::from([(
+                    RpcRequest::GetAccountInfo,
+                    serde_json::json!({
+                        "context": { "slot": 1 },
+                        "value": {
+                            "lamports": watched.lamports,
+                            "data": [base64::encode(&watched.data), "base64"],
+                            "owner": watched.owner.to_string(),
+                            "executable": false,
+                            "rentEpoch": 0,
+                        },
+                    }),
+                )]),
+            );
+            let result = starting.start_with_retries().await;
+            (dir, starting, result)
+        };
+
+        let (_dir, started, result) = start(2).await;
+        result.unwrap();
+        assert_eq!(started.min_fee_payer_balance, Some(5_000_020));
+
+        let (_dir, _, result) = start(1).await;
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn reads_the_l2_cursor_only_on_reconcile_cycles() {
+        let (_dir, mut trusting) = relayer(
+            Some(7),
+            serde_json::json!({ "trust_local_cursor": true, "l2_reconcile_every_n_cycles": 3 }),
+        );
+        // 首轮必须读取链上 cursor 校验本地状态
+        assert_eq!(trusting.local_cursor(), None);
+        trusting.cursor_reconciled = true;
+
+        // None 表示本轮读取 L2 nonce 账户
+        let cycles: Vec<Option<u64>> = (0..6).map(|_| trusting.local_cursor()).collect();
+        assert_eq!(cycles, vec![Some(7), Some(7), None, Some(7), Some(7), None]);
+
+        let (_dir, mut reading) = relayer(Some(7), serde_json::json!({}));
+        reading.cursor_reconciled = true;
+        assert!((0..3).all(|_| reading.local_cursor().is_none()));
+    }
+
+    /// Mock pubsub node answering `accountSubscribe` with one notification
+    /// per nonce, returning its URL
+    fn account_notifier(owner: Pubkey, nonces: Vec<u64>) -> String {
+        let listener = std::net::TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut socket = tungstenite::accept(stream).unwrap();
+            let request: serde_json::Value =
+                serde_json::from_str(&socket.read_message().unwrap().into_text().unwrap()).unwrap();
+            assert_eq!(request["method"], "accountSubscribe");
+            let subscribed =
+                serde_json::json!({ "jsonrpc": "2.0", "result": 42, "id": request["id"] });
+            let notifications = nonces.into_iter().map(|nonce| {
+                serde_json::json!({
+                    "jsonrpc": "2.0",
+                    "method": "accountNotification",
+                    "params": {
+                        "result": {
+                            "context": { "slot": 5 },
+                            "value": nonce_account_json(&owner, nonce),
+                        },
+                        "subscription": 42,
+                    },
+                })
+            });
+            for message in std::iter::once(subscribed).chain(notifications) {
+                socket
+                    .write_message(tungstenite::Message::Text(message.to_string()))
+                    .unwrap();
+            }
+            // 断开连接，已发送的通知仍会先送达
+        });
+        url
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn confirms_relays_when_the_nonce_account_advances() {
+        let (_dir, mut watching) = relayer(
+            None,
+            serde_json::json!({
+                "confirmation_strategy": "confirm_state_change",
+                "l2_ws_url": "ws://127.0.0.1:1",
+            }),
+        );
+        // 第一次更新仍停在 7，推进到 8 后才算确认
+        let program_id = watching.transaction_builder.program_id;
+        watching.l2_ws_url = Some(account_notifier(program_id, vec![7, 8]));
+        watching.l2_client = RpcClient::new_mock("succeeds".to_string());
+        let transaction = Transaction::new_with_payer(&[], Some(&Pubkey::new_unique()));
+
+        let confirmation = watching.submit_and_confirm(7, &transaction).await.unwrap();
+        assert!(matches!(
+            confirmation,
+            Confirmation::Confirmed(signature) if signature == transaction.signatures[0]
+        ));
+
+        watching.l2_ws_url = None;
+        let result = watching.submit_and_confirm(7, &transaction).await;
+        assert!(matches!(
+            result,
+            Err(err) if err.to_string().contains("confirm_state_change requires l2_ws_url")
+        ));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn submits_nothing_inside_a_maintenance_window() {
+        let now = chrono::Utc::now();
+        let (_dir, mut maintained) = relayer(
+            Some(7),
+            serde_json::json!({
+                "maintenance_windows": [{
+                    "start": (now - chrono::Duration::hours(1)).to_rfc3339(),
+                    "end": (now + chrono::Duration::hours(1)).to_rfc3339(),
+                }],
+            }),
+        );
+        maintained.l1_client = watched_account_node(9);
+        let nonce_account = nonce_account_json(&maintained.transaction_builder.program_id, 7);
+        maintained.l2_client = RpcClient::new_mock_with_mocks(
+            "succeeds".to_string(),
+            HashMap::from([(
+                RpcRequest::GetMultipleAccounts,
+                serde_json::json!({ "context": { "slot": 1 }, "value": [nonce_account] }),
+            )]),
+        );
+
+        // 窗口内只读取两条链的状态，不提交转发
+        maintained.run_cycle().await.unwrap();
+        assert!(maintained.in_maintenance);
+        assert_eq!(maintained.last_nonce, Some(7));
+        assert!(maintained.completed_nonces.is_empty());
+        assert!(maintained.pending_confirmations.is_empty());
+        // 维护期间仍然上报延迟
+        let status = maintained.metrics.status();
+        assert_eq!((status.l1_nonce, status.l2_nonce, status.lag), (9, 7, 2));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn relays_only_up_to_the_finalized_l1_nonce() {
+        let (_dir, mut finalized) = relayer(
+            Some(7),
+            serde_json::json!({ "relay_on_finalized_l1": true }),
+        );
+        // confirmed 读数已到 10，finalized 读数只到 8
+        let node_account = |nonce| {
+            let account = watched_account(nonce, 32);
+            serde_json::json!({
+                "lamports": account.lamports,
+                "data": [base64::encode(account.data), "base64"],
+                "owner": account.owner.to_string(),
+                "executable": false,
+                "rentEpoch": 0,
+            })
+        };
+        let l1_url = serve_rpc(move |request| {
+            let nonce = match request["params"][1]["commitment"].as_str() {
+                Some("finalized") => 8,
+                _ => 10,
+            };
+            Some(serde_json::json!({ "context": { "slot": 1 }, "value": node_account(nonce) }))
+        })
+        .await;
+        finalized.l1_client = RpcClient::new_with_commitment(l1_url, CommitmentConfig::confirmed());
+        let nonce_account = nonce_account_json(&finalized.transaction_builder.program_id, 7);
+        finalized.l2_client = RpcClient::new_mock_with_mocks(
+            "succeeds".to_string(),
+            HashMap::from([(
+                RpcRequest::GetMultipleAccounts,
+                serde_json::json!({ "context": { "slot": 1 }, "value": [nonce_account] }),
+            )]),
+        );
+        finalized.skipped_nonces = (7..10).collect();
+
+        finalized.run_cycle().await.unwrap();
+        // 延迟按 confirmed 读数上报，只处理到 finalized 的 nonce
+        assert_eq!(finalized.metrics.status().l1_nonce, 10);
+        assert_eq!(finalized.last_nonce, Some(8));
+    }
 }
@@ -0,0 +1,69 @@
+//! Scheduled maintenance windows.
+//! Built from the `maintenance_windows` config; while one is open the
+//! relayer keeps reading both chains and reporting lag but submits nothing,
+//! e.g. during an L2 program upgrade.
+
+use crate::config::MaintenanceWindowConfig;
+use anyhow::Result;
+use chrono::{DateTime, NaiveTime, Utc};
+
+/// Compiled maintenance window, in UTC
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MaintenanceWindow {
+    /// One-off range between two timestamps
+    Once {
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    },
+    /// Range recurring every day, wrapping past midnight when `end` is
+    /// before `start`
+    Daily { start: NaiveTime, end: NaiveTime },
+}
+
+impl MaintenanceWindow {
+    pub fn from_config(config: &MaintenanceWindowConfig) -> Result<Self> {
+        let timestamps = (
+            DateTime::parse_from_rfc3339(&config.start),
+            DateTime::parse_from_rfc3339(&config.end),
+        );
+        if let (Ok(start), Ok(end)) = timestamps {
+            let (start, end) = (start.with_timezone(&Utc), end.with_timezone(&Utc));
+            if end <= start {
+                return Err(anyhow::anyhow!(
+                    "Maintenance window ends at {} before it starts at {}",
+                    config.end,
+                    config.start
+                ));
+            }
+            return Ok(Self::Once { start, end });
+        }
+
+        let time = |value: &str| {
+            NaiveTime::parse_from_str(value, "%H:%M").map_err(|_| {
+                anyhow::anyhow!(
+                    "Invalid maintenance window time '{}', expected an RFC 3339 timestamp or HH:MM",
+                    value
+                )
+            })
+        };
+        Ok(Self::Daily {
+            start: time(&config.start)?,
+            end: time(&config.end)?,
+        })
+    }
+
+    /// Whether `now` falls inside the window, its end excluded
+    pub fn contains(&self, now: DateTime<Utc>) -> bool {
+        match *self {
+            Self::Once { start, end } => start <= now && now < end,
+            Self::Daily { start, end } => {
+                let time = now.time();
+                if start <= end {
+                    start <= time && time < end
+                } else {
+                    start <= time || time < end
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,579 @@
+//! Relayer metrics.
+//! Served in the OpenMetrics text format over a minimal HTTP endpoint.
+
+use anyhow::Result;
+use serde::Serialize;
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+use std::collections::BTreeMap;
+use std::fmt::{self, Write as _};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Crate version the relayer was built from
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Git commit the relayer was built from, `unknown` outside a git checkout
+pub const GIT_HASH: &str = env!("GIT_HASH");
+
+/// Why a nonce wasn't relayed, used as the `reason` metric label
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SkipReason {
+    /// Marked as skipped with the skip-nonce command
+    OperatorSkipped,
+    /// The message PDA doesn't exist (yet)
+    MissingPda,
+    /// The message PDA data stayed too short to parse
+    IncompletePda,
+    /// The message PDA has an unexpected discriminator
+    DiscriminatorMismatch,
+    /// The message carries a zero amount
+    ZeroAmount,
+    /// The nonce already landed on L2
+    AlreadyRelayed,
+    /// Relaying failed and `on_transfer_error` skipped it
+    TransferFailed,
+    /// The L2 destination account doesn't exist
+    MissingDestination,
+    /// The transfer falls outside `relay_filter`
+    Filtered,
+}
+
+impl SkipReason {
+    const ALL: [SkipReason; 9] = [
+        SkipReason::OperatorSkipped,
+        SkipReason::MissingPda,
+        SkipReason::IncompletePda,
+        SkipReason::DiscriminatorMismatch,
+        SkipReason::ZeroAmount,
+        SkipReason::AlreadyRelayed,
+        SkipReason::TransferFailed,
+        SkipReason::MissingDestination,
+        SkipReason::Filtered,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            SkipReason::OperatorSkipped => "operator_skipped",
+            SkipReason::MissingPda => "missing_pda",
+            SkipReason::IncompletePda => "incomplete_pda",
+            SkipReason::DiscriminatorMismatch => "discriminator_mismatch",
+            SkipReason::ZeroAmount => "zero_amount",
+            SkipReason::AlreadyRelayed => "already_relayed",
+            SkipReason::TransferFailed => "transfer_failed",
+            SkipReason::MissingDestination => "missing_destination",
+            SkipReason::Filtered => "filtered",
+        }
+    }
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SkipReason::OperatorSkipped => "marked as skipped",
+            SkipReason::MissingPda => "message PDA not found",
+            SkipReason::IncompletePda => "message PDA data incomplete",
+            SkipReason::DiscriminatorMismatch => "unexpected PDA discriminator",
+            SkipReason::ZeroAmount => "zero amount",
+            SkipReason::AlreadyRelayed => "already relayed",
+            SkipReason::TransferFailed => "transfer failed",
+            SkipReason::MissingDestination => "destination account not found",
+            SkipReason::Filtered => "outside the relay filter",
+        })
+    }
+}
+
+/// Exemplar linking a counter increment to the relay transaction behind it
+struct Exemplar {
+    trace_id: String,
+    timestamp: f64,
+}
+
+/// Requests sent to one RPC endpoint
+#[derive(Default)]
+struct RpcStats {
+    successes: u64,
+    failures: u64,
+    latency_seconds: f64,
+}
+
+#[derive(Default)]
+struct MetricValues {
+    relays_total: u64,
+    last_relay: Option<Exemplar>,
+    l1_nonce: u64,
+    l2_nonce: u64,
+    skipped: BTreeMap<SkipReason, u64>,
+    /// Amount relayed per labelled destination
+    relayed_amounts: BTreeMap<String, u64>,
+    /// Amount relayed to destinations past the label limit
+    relayed_amount_other: u64,
+    /// Relaying paused on excessive lag
+    paused: bool,
+    /// Requests per RPC endpoint label
+    rpc: BTreeMap<String, RpcStats>,
+}
+
+/// Relayer status, written as one JSON line to status queries:
+///
+/// `{"version":"0.1.0","commit":"1a43312","l1_nonce":120,"l2_nonce":118,"lag":2,"last_relay":1760500000.123,"paused":false}`
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct Status {
+    pub version: &'static str,
+    pub commit: &'static str,
+    pub l1_nonce: u64,
+    pub l2_nonce: u64,
+    /// Nonces waiting to be relayed
+    pub lag: u64,
+    /// Unix time of the last confirmed relay, in seconds
+    pub last_relay: Option<f64>,
+    /// Relaying paused on excessive lag until an operator resumes it
+    pub paused: bool,
+}
+
+/// Metrics shared between the relayer and the metrics endpoint
+pub struct Metrics {
+    /// Attach the latest relay signature as an exemplar to the relay counter
+    exemplars: bool,
+    /// Most destinations labelled in the relayed amount metric, `None` if
+    /// it's disabled
+    max_destination_labels: Option<usize>,
+    values: Mutex<MetricValues>,
+}
+
+impl Metrics {
+    pub fn new(exemplars: bool, max_destination_labels: Option<usize>) -> Self {
+        Self {
+            exemplars,
+            max_destination_labels,
+            values: Mutex::new(MetricValues::default()),
+        }
+    }
+
+    /// Counts a confirmed relay transaction
+    pub fn record_relay(&self, signature: &Signature) {
+        let mut values = self.values.lock().unwrap();
+        values.relays_total += 1;
+        // 交易签名作为 trace id，便于从指标跳转到具体交易
+        values.last_relay = Some(Exemplar {
+            trace_id: signature.to_string(),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs_f64())
+                .unwrap_or_default(),
+        });
+    }
+
+    /// Adds a confirmed relay's amount to its destination's total
+    pub fn record_relayed_amount(&self, destination: &Pubkey, amount: u64) {
+        let max_labels = match self.max_destination_labels {
+            Some(max_labels) => max_labels,
+            None => return,
+        };
+        let mut values = self.values.lock().unwrap();
+        let destination = destination.to_string();
+        // 超出上限的目标地址合并到 other，避免标签基数无限增长
+        let total = if values.relayed_amounts.contains_key(&destination)
+            || values.relayed_amounts.len() < max_labels
+        {
+            values.relayed_amounts.entry(destination).or_default()
+        } else {
+            &mut values.relayed_amount_other
+        };
+        *total = total.saturating_add(amount);
+    }
+
+    /// Counts an RPC request to the endpoint with the given label
+    pub fn record_rpc(&self, endpoint: &str, success: bool, latency: Duration) {
+        let mut values = self.values.lock().unwrap();
+        let stats = match values.rpc.get_mut(endpoint) {
+            Some(stats) => stats,
+            None => values.rpc.entry(endpoint.to_string()).or_default(),
+        };
+        if success {
+            stats.successes += 1;
+        } else {
+            stats.failures += 1;
+        }
+        stats.latency_seconds += latency.as_secs_f64();
+    }
+
+    /// Records whether relaying is paused on excessive lag
+    pub fn set_paused(&self, paused: bool) {
+        self.values.lock().unwrap().paused = paused;
+    }
+
+    /// Current status for status queries
+    pub fn status(&self) -> Status {
+        let values = self.values.lock().unwrap();
+        Status {
+            version: VERSION,
+            commit: GIT_HASH,
+            l1_nonce: values.l1_nonce,
+            l2_nonce: values.l2_nonce,
+            lag: values.l1_nonce.saturating_sub(values.l2_nonce),
+            last_relay: values.last_relay.as_ref().map(|relay| relay.timestamp),
+            paused: values.paused,
+        }
+    }
+
+    /// Counts a nonce that wasn't relayed
+    pub fn record_skip(&self, reason: SkipReason) {
+        *self
+            .values
+            .lock()
+            .unwrap()
+            .skipped
+            .entry(reason)
+            .or_default() += 1;
+    }
+
+    /// Records the nonces observed in the latest cycle
+    pub fn set_nonces(&self, l1_nonce: u64, l2_nonce: u64) {
+        let mut values = self.values.lock().unwrap();
+        values.l1_nonce = l1_nonce;
+        values.l2_nonce = l2_nonce;
+    }
+
+    /// Renders every metric in the OpenMetrics text format
+    pub fn render(&self) -> String {
+        let values = self.values.lock().unwrap();
+        let mut out = String::new();
+
+        out.push_str("# HELP relayer_build Version and commit of the running build.\n");
+        out.push_str("# TYPE relayer_build info\n");
+        let _ = writeln!(
+            out,
+            "relayer_build_info{{version=\"{}\",commit=\"{}\"}} 1",
+            VERSION, GIT_HASH
+        );
+
+        out.push_str("# HELP relayer_relays Confirmed relay transactions.\n");
+        out.push_str("# TYPE relayer_relays counter\n");
+        let _ = write!(out, "relayer_relays_total {}", values.relays_total);
+        if let (true, Some(exemplar)) = (self.exemplars, &values.last_relay) {
+            let _ = write!(
+                out,
+                " # {{trace_id=\"{}\"}} 1 {:.3}",
+                exemplar.trace_id, exemplar.timestamp
+            );
+        }
+        out.push('\n');
+
+        out.push_str("# HELP relayer_skipped Nonces not relayed, by reason.\n");
+        out.push_str("# TYPE relayer_skipped counter\n");
+        for reason in SkipReason::ALL {
+            let _ = writeln!(
+                out,
+                "relayer_skipped_total{{reason=\"{}\"}} {}",
+                reason.label(),
+                values.skipped.get(&reason).copied().unwrap_or_default()
+            );
+        }
+
+        if self.max_destination_labels.is_some() {
+            out.push_str(
+                "# HELP relayer_relayed_amount Amount relayed, in lamports or token base units, by destination.\n",
+            );
+            out.push_str("# TYPE relayer_relayed_amount counter\n");
+            for (destination, amount) in &values.relayed_amounts {
+                let _ = writeln!(
+                    out,
+                    "relayer_relayed_amount_total{{destination=\"{}\"}} {}",
+                    destination, amount
+                );
+            }
+            let _ = writeln!(
+                out,
+                "relayer_relayed_amount_total{{destination=\"other\"}} {}",
+                values.relayed_amount_other
+            );
+        }
+
+        if !values.rpc.is_empty() {
+            out.push_str("# HELP relayer_rpc_requests RPC requests, by endpoint and outcome.\n");
+            out.push_str("# TYPE relayer_rpc_requests counter\n");
+            for (endpoint, stats) in &values.rpc {
+                let _ = writeln!(
+                    out,
+                    "relayer_rpc_requests_total{{endpoint=\"{}\",outcome=\"success\"}} {}",
+                    endpoint, stats.successes
+                );
+                let _ = writeln!(
+                    out,
+                    "relayer_rpc_requests_total{{endpoint=\"{}\",outcome=\"failure\"}} {}",
+                    endpoint, stats.failures
+                );
+            }
+            out.push_str("# HELP relayer_rpc_latency_seconds RPC request latency, by endpoint.\n");
+            out.push_str("# TYPE relayer_rpc_latency_seconds summary\n");
+            for (endpoint, stats) in &values.rpc {
+                let _ = writeln!(
+                    out,
+                    "relayer_rpc_latency_seconds_sum{{endpoint=\"{}\"}} {:.6}",
+                    endpoint, stats.latency_seconds
+                );
+                let _ = writeln!(
+                    out,
+                    "relayer_rpc_latency_seconds_count{{endpoint=\"{}\"}} {}",
+                    endpoint,
+                    stats.successes + stats.failures
+                );
+            }
+        }
+
+        out.push_str("# HELP relayer_l1_nonce Nonce of the watched L1 account.\n");
+        out.push_str("# TYPE relayer_l1_nonce gauge\n");
+        let _ = writeln!(out, "relayer_l1_nonce {}", values.l1_nonce);
+        out.push_str("# HELP relayer_l2_nonce Relayed cursor of the L2 nonce account.\n");
+        out.push_str("# TYPE relayer_l2_nonce gauge\n");
+        let _ = writeln!(out, "relayer_l2_nonce {}", values.l2_nonce);
+
+        out.push_str("# EOF\n");
+        out
+    }
+}
+
+/// Serves `GET /metrics`, and `GET /config` when given the redacted config
+/// JSON, on the listener until the process exits
+pub async fn serve(listener: TcpListener, metrics: Arc<Metrics>, config: Option<Arc<String>>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let metrics = metrics.clone();
+                let config = config.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = handle_connection(stream, &metrics, config.as_deref()).await {
+                        println!("Metrics request failed: {}", err);
+                    }
+                });
+            }
+            Err(err) => println!("Failed to accept metrics connection: {}", err),
+        }
+    }
+}
+
+/// Writes the status as one JSON line to every connection and closes it,
+/// until the process exits
+pub async fn serve_status(listener: TcpListener, metrics: Arc<Metrics>) {
+    loop {
+        match listener.accept().await {
+            Ok((mut stream, _)) => {
+                let metrics = metrics.clone();
+                tokio::spawn(async move {
+                    let result = async {
+                        let mut line = serde_json::to_string(&metrics.status())?;
+                        line.push('\n');
+                        stream.write_all(line.as_bytes()).await?;
+                        stream.shutdown().await?;
+                        Ok::<_, anyhow::Error>(())
+                    }
+                    .await;
+                    if let Err(err) = result {
+                        println!("Status query failed: {}", err);
+                    }
+                });
+            }
+            Err(err) => println!("Failed to accept status connection: {}", err),
+        }
+    }
+}
+
+async fn handle_connection(
+    mut stream: TcpStream,
+    metrics: &Metrics,
+    config: Option<&String>,
+) -> Result<()> {
+    let mut buf = [0u8; 1024];
+    let len = stream.read(&mut buf).await?;
+    let request = String::from_utf8_lossy(&buf[..len]);
+    let path = request.split_whitespace().nth(1).unwrap_or("/");
+
+    let (status, content_type, body) = match (path, config) {
+        ("/metrics", _) => ("200 OK", CONTENT_TYPE, metrics.render()),
+        ("/config", Some(config)) => ("200 OK", "application/json", config.clone()),
+        _ => ("404 Not Found", "text/plain", "not found\n".to_string()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn status_port_answers_with_one_json_line() {
+        let metrics = Arc::new(Metrics::new(false, None));
+        metrics.set_nonces(12, 9);
+        metrics.record_relay(&Signature::new_unique());
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve_status(listener, metrics));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        assert_eq!(response.lines().count(), 1);
+        let status: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(status["version"], VERSION);
+        assert_eq!(status["commit"], GIT_HASH);
+        assert_eq!(status["l1_nonce"], 12);
+        assert_eq!(status["l2_nonce"], 9);
+        assert_eq!(status["lag"], 3);
+        assert!(status["last_relay"].is_f64());
+        assert_eq!(status["paused"], false);
+    }
+
+    /// Checks the OpenMetrics text format rules scrapers rely on: every
+    /// sample belongs to a declared family, exemplars only follow counter
+    /// totals with at most 128 characters of labels, and `# EOF` ends it.
+    /// Returns the exemplar label sets found
+    fn parse_openmetrics(text: &str) -> Vec<String> {
+        let mut families: BTreeMap<&str, &str> = BTreeMap::new();
+        let mut exemplars = Vec::new();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.last(), Some(&"# EOF"));
+        assert!(text.ends_with('\n'));
+
+        for line in &lines[..lines.len() - 1] {
+            if let Some(rest) = line.strip_prefix("# TYPE ") {
+                let (name, kind) = rest.split_once(' ').unwrap();
+                assert!(families.insert(name, kind).is_none(), "{}", line);
+                continue;
+            }
+            if line.starts_with("# HELP ") {
+                continue;
+            }
+
+            let (sample, exemplar) = match line.split_once(" # ") {
+                Some((sample, exemplar)) => (sample, Some(exemplar)),
+                None => (*line, None),
+            };
+            let (series, value) = sample.rsplit_once(' ').unwrap();
+            value.parse::<f64>().unwrap();
+            let name = series.split('{').next().unwrap();
+            let (family, kind) = families
+                .iter()
+                .find(|(family, _)| {
+                    ["", "_total", "_info", "_sum", "_count"]
+                        .iter()
+                        .any(|suffix| name == format!("{}{}", family, suffix))
+                })
+                .unwrap_or_else(|| panic!("undeclared sample: {}", line));
+
+            if let Some(exemplar) = exemplar {
+                assert_eq!(*kind, "counter", "exemplar on {}", family);
+                assert!(name.ends_with("_total"), "{}", line);
+                let labels = exemplar
+                    .strip_prefix('{')
+                    .and_then(|exemplar| exemplar.split_once('}'))
+                    .unwrap();
+                let label_chars: usize = labels
+                    .0
+                    .split(',')
+                    .map(|label| {
+                        let (name, value) = label.split_once('=').unwrap();
+                        name.len() + value.trim_matches('"').len()
+                    })
+                    .sum();
+                assert!(label_chars <= 128, "{}", line);
+                let mut values = labels.1.split_whitespace();
+                values.next().unwrap().parse::<f64>().unwrap();
+                if let Some(timestamp) = values.next() {
+                    timestamp.parse::<f64>().unwrap();
+                }
+                assert_eq!(values.next(), None);
+                exemplars.push(labels.0.to_string());
+            }
+        }
+        exemplars
+    }
+
+    #[test]
+    fn reports_the_build_version_and_commit() {
+        assert!(!VERSION.is_empty());
+        assert!(!GIT_HASH.is_empty());
+        let rendered = Metrics::new(false, None).render();
+        assert!(rendered.contains("# TYPE relayer_build info\n"));
+        assert!(rendered.contains(&format!(
+            "relayer_build_info{{version=\"{}\",commit=\"{}\"}} 1\n",
+            VERSION, GIT_HASH
+        )));
+    }
+
+    #[test]
+    fn renders_valid_openmetrics_with_relay_exemplars() {
+        let signature = Signature::new_unique();
+        let metrics = Metrics::new(true, Some(1));
+        assert!(parse_openmetrics(&metrics.render()).is_empty());
+
+        metrics.record_relay(&Signature::new_unique());
+        metrics.record_relay(&signature);
+        metrics.record_relayed_amount(&Pubkey::new_unique(), 10);
+        metrics.record_relayed_amount(&Pubkey::new_unique(), 20);
+        metrics.record_skip(SkipReason::MissingPda);
+        metrics.record_rpc("l2", true, Duration::from_millis(30));
+        metrics.set_nonces(12, 9);
+        let rendered = metrics.render();
+
+        assert_eq!(
+            parse_openmetrics(&rendered),
+            vec![format!("trace_id=\"{}\"", signature)]
+        );
+        assert!(rendered.contains(&format!(
+            "relayer_relays_total 2 # {{trace_id=\"{}\"}} 1 ",
+            signature
+        )));
+
+        let metrics = Metrics::new(false, None);
+        metrics.record_relay(&signature);
+        let rendered = metrics.render();
+        assert!(parse_openmetrics(&rendered).is_empty());
+        assert!(rendered.contains("relayer_relays_total 1\n"));
+    }
+
+    #[test]
+    fn accumulates_relayed_amounts_per_destination() {
+        let (first, second, third) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let metrics = Metrics::new(false, Some(2));
+        metrics.record_relayed_amount(&first, 100);
+        metrics.record_relayed_amount(&second, 20);
+        metrics.record_relayed_amount(&first, 50);
+        // 超出标签上限的目标地址计入 other
+        metrics.record_relayed_amount(&third, 7);
+        metrics.record_relayed_amount(&third, 3);
+        metrics.record_relayed_amount(&second, 5);
+        let rendered = metrics.render();
+        parse_openmetrics(&rendered);
+
+        for (destination, amount) in [
+            (first.to_string(), 150),
+            (second.to_string(), 25),
+            ("other".to_string(), 10),
+        ] {
+            assert!(rendered.contains(&format!(
+                "relayer_relayed_amount_total{{destination=\"{}\"}} {}\n",
+                destination, amount
+            )));
+        }
+        assert!(!rendered.contains(&third.to_string()));
+
+        let rendered = Metrics::new(false, None).render();
+        assert!(!rendered.contains("relayer_relayed_amount"));
+    }
+}
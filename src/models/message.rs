@@ -1,21 +1,263 @@
+use crate::config::Endianness;
 use anyhow::Result;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::BTreeMap;
+use std::fmt;
 
+/// Offset of the nonce in the watched account, after the discriminator
+pub const NONCE_OFFSET: usize = 8;
+
+#[derive(Debug)]
 pub struct NonceStatus {
     pub nonce: u64,
 }
 
 impl NonceStatus {
-    pub fn from_bytes(data: &[u8]) -> Result<Self> {
-        if data.len() < 16 {
+    /// Parses the nonce counter at `offset`, `NONCE_OFFSET` unless the
+    /// watched account packs several counters
+    pub fn from_bytes(data: &[u8], offset: usize, endianness: Endianness) -> Result<Self> {
+        // 最短长度由偏移量推导
+        let min_len = offset.saturating_add(8);
+        if data.len() < min_len {
             return Err(anyhow::anyhow!(
-                "Invalid data length: expected at least 16 bytes, got {}",
+                "Invalid data length: expected at least {} bytes, got {}",
+                min_len,
                 data.len()
             ));
         }
 
-        let nonce_bytes: [u8; 8] = data[8..16].try_into()?;
-        let nonce = u64::from_le_bytes(nonce_bytes);
-
+        let nonce = endianness.read_u64(read_bytes(data, offset)?);
         Ok(Self { nonce })
     }
+
+    /// Parses several named nonce counters packed in one account
+    pub fn read_counters<'a>(
+        data: &[u8],
+        counters: impl IntoIterator<Item = (&'a str, usize)>,
+        endianness: Endianness,
+    ) -> Result<BTreeMap<String, u64>> {
+        counters
+            .into_iter()
+            .map(|(name, offset)| {
+                let status = Self::from_bytes(data, offset, endianness)
+                    .map_err(|e| anyhow::anyhow!("Nonce counter {}: {}", name, e))?;
+                Ok((name.to_string(), status.nonce))
+            })
+            .collect()
+    }
+}
+
+/// Reads the `N` bytes at `offset`, failing instead of panicking when they
+/// run past the end of the data
+pub fn read_bytes<const N: usize>(data: &[u8], offset: usize) -> Result<[u8; N]> {
+    offset
+        .checked_add(N)
+        .and_then(|end| data.get(offset..end))
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "{}-byte field at offset {} is out of bounds for {} bytes of data",
+                N,
+                offset,
+                data.len()
+            )
+        })
+}
+
+/// Cross-chain transfer parsed from a message PDA
+#[derive(Debug)]
+pub struct TransferInfo {
+    pub amount: u64,
+    pub to: Pubkey,
+    /// L2 destination selector, present when a selector offset is configured
+    pub selector: Option<u8>,
+    /// Wallet stored in the PDA when `to` is its derived associated token account
+    pub owner: Option<Pubkey>,
+}
+
+/// Account whose leading bytes don't match the expected Anchor discriminator
+#[derive(Debug)]
+pub struct DiscriminatorMismatch {
+    pub account: Pubkey,
+    pub expected: [u8; 8],
+    pub found: Vec<u8>,
+}
+
+impl fmt::Display for DiscriminatorMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Unexpected discriminator for account {}: expected {:?}, found {:?}",
+            self.account, self.expected, self.found
+        )
+    }
+}
+
+impl std::error::Error for DiscriminatorMismatch {}
+
+/// Message PDA holding less data than a full message, usually because the
+/// account write hasn't reached the RPC node yet
+#[derive(Debug)]
+pub struct InsufficientAccountData {
+    pub expected: usize,
+    pub found: usize,
+}
+
+impl fmt::Display for InsufficientAccountData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Insufficient PDA account data length: expected {} bytes, got {} bytes",
+            self.expected, self.found
+        )
+    }
+}
+
+impl std::error::Error for InsufficientAccountData {}
+
+/// Message PDA whose stored nonce differs from the nonce it was derived from
+#[derive(Debug)]
+pub struct PdaNonceMismatch {
+    pub pda: Pubkey,
+    pub expected: u64,
+    pub found: u64,
+}
+
+impl fmt::Display for PdaNonceMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Message PDA {} stores nonce {} but was derived for nonce {}",
+            self.pda, self.found, self.expected
+        )
+    }
+}
+
+impl std::error::Error for PdaNonceMismatch {}
+
+/// L2 nonce account owned by a program other than the L2 program
+#[derive(Debug)]
+pub struct NonceAccountOwnerMismatch {
+    pub account: Pubkey,
+    pub expected: Pubkey,
+    pub found: Pubkey,
+}
+
+impl fmt::Display for NonceAccountOwnerMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Nonce account {} is owned by {}, expected the L2 program {}",
+            self.account, self.found, self.expected
+        )
+    }
+}
+
+impl std::error::Error for NonceAccountOwnerMismatch {}
+
+/// Checks the account data against an expected discriminator, if one is configured
+pub fn check_discriminator(
+    account: &Pubkey,
+    data: &[u8],
+    expected: Option<&[u8; 8]>,
+) -> Result<()> {
+    if let Some(expected) = expected {
+        let found = &data[..data.len().min(8)];
+        if found != expected {
+            return Err(DiscriminatorMismatch {
+                account: *account,
+                expected: *expected,
+                found: found.to_vec(),
+            }
+            .into());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_bytes_in_bounds() {
+        let data = [1u8, 2, 3, 4, 5];
+        assert_eq!(read_bytes::<2>(&data, 0).unwrap(), [1, 2]);
+        assert_eq!(read_bytes::<2>(&data, 3).unwrap(), [4, 5]);
+        assert_eq!(read_bytes::<0>(&data, 5).unwrap(), [0u8; 0]);
+    }
+
+    #[test]
+    fn rejects_reads_past_the_data() {
+        let data = [0u8; 8];
+        let err = read_bytes::<8>(&data, 1).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "8-byte field at offset 1 is out of bounds for 8 bytes of data"
+        );
+        assert!(read_bytes::<1>(&data, usize::MAX).is_err());
+    }
+
+    #[test]
+    fn round_trips_little_endian_nonces() {
+        for nonce in [0, 1, 0x0102_0304_0506_0708, u64::MAX] {
+            let mut data = vec![0xff; NONCE_OFFSET];
+            data.extend_from_slice(&nonce.to_le_bytes());
+            let status = NonceStatus::from_bytes(&data, NONCE_OFFSET, Endianness::Le).unwrap();
+            assert_eq!(status.nonce, nonce);
+        }
+
+        let mut data = vec![0; NONCE_OFFSET];
+        data.extend_from_slice(&[0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01]);
+        let status = NonceStatus::from_bytes(&data, NONCE_OFFSET, Endianness::Le).unwrap();
+        assert_eq!(status.nonce, 0x0102_0304_0506_0708);
+    }
+
+    #[test]
+    fn reads_big_endian_counters() {
+        let mut data = vec![0; NONCE_OFFSET];
+        data.extend_from_slice(&[0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08]);
+        let le = NonceStatus::from_bytes(&data, NONCE_OFFSET, Endianness::Le).unwrap();
+        let be = NonceStatus::from_bytes(&data, NONCE_OFFSET, Endianness::Be).unwrap();
+        assert_eq!(le.nonce, 0x0807_0605_0403_0201);
+        assert_eq!(be.nonce, 0x0102_0304_0506_0708);
+
+        let mut data = vec![0; 24];
+        data[8..16].copy_from_slice(&5u64.to_be_bytes());
+        data[16..24].copy_from_slice(&9u64.to_be_bytes());
+        let counters =
+            NonceStatus::read_counters(&data, [("usdc", 8), ("sol", 16)], Endianness::Be).unwrap();
+        assert_eq!(
+            counters,
+            BTreeMap::from([("usdc".to_string(), 5), ("sol".to_string(), 9)])
+        );
+    }
+
+    #[test]
+    fn rejects_short_nonce_accounts() {
+        let data = [0u8; NONCE_OFFSET + 7];
+        let err = NonceStatus::from_bytes(&data, NONCE_OFFSET, Endianness::Le).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid data length: expected at least 16 bytes, got 15"
+        );
+        assert!(NonceStatus::from_bytes(&data, usize::MAX, Endianness::Le).is_err());
+    }
+
+    #[test]
+    fn reads_named_counters() {
+        let mut data = vec![0; 24];
+        data[8..16].copy_from_slice(&5u64.to_le_bytes());
+        data[16..24].copy_from_slice(&9u64.to_le_bytes());
+
+        let counters =
+            NonceStatus::read_counters(&data, [("usdc", 8), ("sol", 16)], Endianness::Le).unwrap();
+        assert_eq!(
+            counters,
+            BTreeMap::from([("usdc".to_string(), 5), ("sol".to_string(), 9)])
+        );
+
+        let err = NonceStatus::read_counters(&data, [("eth", 20)], Endianness::Le).unwrap_err();
+        assert!(err.to_string().starts_with("Nonce counter eth:"));
+    }
 }
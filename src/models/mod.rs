@@ -1 +1 @@
-pub mod message; 
\ No newline at end of file
+pub mod message;
@@ -0,0 +1,108 @@
+//! Machine-readable stdout for piping into `jq` or a log shipper.
+//! With `ndjson_stdout` set, the process's stdout is moved onto stderr so
+//! human logs no longer mix with the JSON lines, which keep the original
+//! stdout. Every relay outcome is then one `decision` line on stdout, see
+//! `decision::Decision` for the schema, along with `relay` events from
+//! `event_sink = "stdout"`.
+
+use anyhow::Result;
+use std::fs::File;
+use std::io::{self, Write};
+use std::sync::{Mutex, OnceLock};
+
+/// Original stdout, once human logs were moved to stderr
+static STDOUT: OnceLock<Mutex<File>> = OnceLock::new();
+
+/// Moves stdout onto stderr, keeping the original stdout for JSON lines
+#[cfg(unix)]
+pub fn redirect_stdout() -> Result<()> {
+    use std::os::fd::{AsFd, AsRawFd};
+
+    io::stdout().flush()?;
+    let stdout = io::stdout().as_fd().try_clone_to_owned()?;
+    // 之后所有 println! 输出都写入 stderr
+    if unsafe { libc::dup2(io::stderr().as_raw_fd(), io::stdout().as_raw_fd()) } < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    let _ = STDOUT.set(Mutex::new(File::from(stdout)));
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn redirect_stdout() -> Result<()> {
+    Err(anyhow::anyhow!("ndjson_stdout is only supported on Unix"))
+}
+
+/// Writes one JSON line to stdout, bypassing the redirect
+pub fn write_line(line: &str) -> io::Result<()> {
+    match STDOUT.get() {
+        Some(stdout) => {
+            let mut stdout = stdout.lock().unwrap();
+            writeln!(stdout, "{}", line)?;
+            stdout.flush()
+        }
+        None => writeln!(io::stdout().lock(), "{}", line),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decision::{Action, Decision};
+    use std::process::Command;
+
+    const CHILD: &str = "NDJSON_STDOUT_CHILD";
+
+    #[test]
+    fn writes_one_json_line_per_outcome_to_stdout() {
+        // 子进程里重定向 stdout，避免影响测试进程自身的输出
+        if std::env::var_os(CHILD).is_some() {
+            redirect_stdout().unwrap();
+            println!("human log line");
+            for (nonce, action) in [
+                (7, Action::Relayed),
+                (8, Action::Skipped),
+                (9, Action::Failed),
+            ] {
+                let mut decision = Decision::new(nonce);
+                decision.decide(action, "test");
+                decision.emit();
+            }
+            return;
+        }
+
+        let output = Command::new(std::env::current_exe().unwrap())
+            .args([
+                "ndjson::tests::writes_one_json_line_per_outcome_to_stdout",
+                "--exact",
+                "--nocapture",
+                "--test-threads=1",
+            ])
+            .env(CHILD, "1")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let stderr = String::from_utf8(output.stderr).unwrap();
+
+        // 测试框架在重定向前打印的 "test ... " 前缀之后，stdout 只有 JSON 行
+        let json = &stdout[stdout.find('{').unwrap()..];
+        let lines: Vec<serde_json::Value> = json
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        for (line, (nonce, action)) in
+            lines
+                .iter()
+                .zip([(7, "relayed"), (8, "skipped"), (9, "failed")])
+        {
+            assert_eq!(line["type"], "decision");
+            assert_eq!(line["nonce"], nonce);
+            assert_eq!(line["action"], action);
+            assert_eq!(line["reason"], "test");
+        }
+        assert!(!stdout.contains("human log line"));
+        assert!(stderr.contains("human log line"));
+    }
+}
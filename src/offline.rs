@@ -0,0 +1,72 @@
+//! Signed relay transactions exchanged between the two phases of offline
+//! signing: `build-and-sign` writes them, `submit-signed` sends them.
+//!
+//! The file holds one JSON object per line with the nonce and the
+//! bincode-serialized transaction in base64.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use solana_sdk::transaction::Transaction;
+use std::fs;
+use std::path::Path;
+
+/// A signed relay transaction waiting to be submitted
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct SignedRelay {
+    pub nonce: u64,
+    /// Base64 of the bincode-serialized signed transaction
+    pub transaction: String,
+}
+
+impl SignedRelay {
+    pub fn new(nonce: u64, transaction: &Transaction) -> Result<Self> {
+        Ok(Self {
+            nonce,
+            transaction: base64::encode(bincode::serialize(transaction)?),
+        })
+    }
+
+    /// Decodes the transaction, checking it's still fully signed
+    pub fn decode(&self) -> Result<Transaction> {
+        let bytes = base64::decode(&self.transaction)
+            .map_err(|e| anyhow::anyhow!("Invalid base64 for nonce {}: {}", self.nonce, e))?;
+        let transaction: Transaction = bincode::deserialize(&bytes)
+            .map_err(|e| anyhow::anyhow!("Invalid transaction for nonce {}: {}", self.nonce, e))?;
+        transaction
+            .verify()
+            .map_err(|e| anyhow::anyhow!("Bad signature for nonce {}: {}", self.nonce, e))?;
+        Ok(transaction)
+    }
+}
+
+/// Writes signed relays to `path`, one JSON object per line
+pub fn write_signed<P: AsRef<Path>>(path: P, relays: &[SignedRelay]) -> Result<()> {
+    let mut data = String::new();
+    for relay in relays {
+        data.push_str(&serde_json::to_string(relay)?);
+        data.push('\n');
+    }
+    fs::write(path.as_ref(), data)
+        .map_err(|e| anyhow::anyhow!("Failed to write {}: {}", path.as_ref().display(), e))
+}
+
+/// Reads signed relays written by `write_signed`
+pub fn read_signed<P: AsRef<Path>>(path: P) -> Result<Vec<SignedRelay>> {
+    let path = path.as_ref();
+    let data = fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path.display(), e))?;
+    data.lines()
+        .filter(|line| !line.trim().is_empty())
+        .enumerate()
+        .map(|(i, line)| {
+            serde_json::from_str(line).map_err(|e| {
+                anyhow::anyhow!(
+                    "Invalid signed relay on line {} of {}: {}",
+                    i + 1,
+                    path.display(),
+                    e
+                )
+            })
+        })
+        .collect()
+}
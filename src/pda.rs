@@ -1,52 +1,416 @@
+use crate::{
+    config::Endianness,
+    error::RelayerError,
+    models::message::{
+        check_discriminator, read_bytes, InsufficientAccountData, PdaNonceMismatch, TransferInfo,
+    },
+};
 use anyhow::Result;
-use solana_client::rpc_client::RpcClient;
-use solana_sdk::pubkey::Pubkey;
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::{
+    rpc_client::RpcClient,
+    rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+    rpc_filter::{Memcmp, RpcFilterType},
+};
+use solana_sdk::{account::Account, pubkey::Pubkey};
+use spl_associated_token_account::get_associated_token_address;
+use std::collections::{HashMap, HashSet};
+
+/// Offset of the destination in a message PDA
+const TO_OFFSET: usize = 40;
+/// Offset of the transfer amount in a message PDA
+const AMOUNT_OFFSET: usize = 72;
+/// Shortest message PDA data holding every field read, derived from the
+/// last field so the length check can't drift from the offsets
+const MESSAGE_MIN_LEN: usize = AMOUNT_OFFSET + 8;
 
 pub struct PdaManager {
     program_id: Pubkey,
     watched_account: Pubkey,
+    selector_offset: Option<usize>,
+    discriminator: Option<[u8; 8]>,
+    /// Mint the destination associated token account is derived for
+    destination_mint: Option<Pubkey>,
+    /// Offset of the nonce stored in the PDA, checked against the derivation
+    nonce_offset: Option<usize>,
+    /// Byte order of the nonce seed
+    seed_endianness: Endianness,
+    /// Extra seed distinguishing the message PDAs of one nonce stream
+    stream_seed: Option<Vec<u8>>,
 }
 
 impl PdaManager {
-    pub fn new(program_id: Pubkey, watched_account: Pubkey) -> Self {
+    pub fn new(
+        program_id: Pubkey,
+        watched_account: Pubkey,
+        selector_offset: Option<usize>,
+        discriminator: Option<[u8; 8]>,
+        destination_mint: Option<Pubkey>,
+        nonce_offset: Option<usize>,
+        seed_endianness: Endianness,
+    ) -> Self {
         Self {
             program_id,
             watched_account,
+            selector_offset,
+            discriminator,
+            destination_mint,
+            nonce_offset,
+            seed_endianness,
+            stream_seed: None,
         }
     }
 
+    /// Derives message PDAs with an extra seed after the watched account
+    pub fn set_stream_seed(&mut self, seed: Vec<u8>) {
+        self.stream_seed = Some(seed);
+    }
+
     pub fn find_address(&self, nonce: u64) -> (Pubkey, u8) {
-        let seeds = [
-            b"nonce",
-            self.watched_account.as_ref(),
-            &nonce.to_le_bytes(),
-        ];
+        let nonce_bytes = self.seed_endianness.u64_bytes(nonce);
+        let mut seeds: Vec<&[u8]> = vec![b"nonce", self.watched_account.as_ref()];
+        if let Some(stream_seed) = &self.stream_seed {
+            seeds.push(stream_seed);
+        }
+        seeds.push(&nonce_bytes);
 
         Pubkey::find_program_address(&seeds, &self.program_id)
     }
 
-    pub async fn get_transfer_info(
+    /// Fetches a message PDA, returning `None` if it doesn't exist
+    pub fn fetch_account(&self, client: &RpcClient, pda: &Pubkey) -> Result<Option<Account>> {
+        Ok(client
+            .get_account_with_commitment(pda, client.commitment())
+            .map_err(RelayerError::from)?
+            .value)
+    }
+
+    /// Fetches the message PDAs among `pdas` in one `getProgramAccounts`
+    /// call, selecting the program's accounts holding `filter` at `offset`.
+    /// Message PDAs not returned may still exist and need a direct fetch
+    pub fn fetch_program_accounts(
         &self,
         client: &RpcClient,
+        pdas: &HashSet<Pubkey>,
+        offset: usize,
+        filter: &[u8],
+    ) -> Result<HashMap<Pubkey, Account>> {
+        let config = RpcProgramAccountsConfig {
+            filters: Some(vec![RpcFilterType::Memcmp(Memcmp::new_raw_bytes(
+                offset,
+                filter.to_vec(),
+            ))]),
+            account_config: RpcAccountInfoConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                commitment: Some(client.commitment()),
+                ..RpcAccountInfoConfig::default()
+            },
+            ..RpcProgramAccountsConfig::default()
+        };
+        let accounts = client
+            .get_program_accounts_with_config(&self.program_id, config)
+            .map_err(RelayerError::from)?;
+        // 只保留待处理范围内的 PDA，已处理的消息可能很多
+        Ok(accounts
+            .into_iter()
+            .filter(|(pubkey, _)| pdas.contains(pubkey))
+            .collect())
+    }
+
+    /// Transfer target for a stored destination, with the owner wallet when
+    /// it's the derived associated token account
+    pub fn destination(&self, stored_to: Pubkey) -> (Pubkey, Option<Pubkey>) {
+        // 代币模式下保存的是钱包地址，转账目标为其关联代币账户
+        match &self.destination_mint {
+            Some(mint) => (
+                get_associated_token_address(&stored_to, mint),
+                Some(stored_to),
+            ),
+            None => (stored_to, None),
+        }
+    }
+
+    /// Parses the message PDA derived for `nonce`
+    pub fn get_transfer_info(
+        &self,
         pda: &Pubkey,
-    ) -> Result<(u64, Pubkey)> {
-        let account = client.get_account(pda)?;
-        const EXPECTED_SIZE: usize = 87;
-
-        if account.data.len() < EXPECTED_SIZE {
-            return Err(anyhow::anyhow!(
-                "Insufficient PDA account data length: expected {} bytes, got {} bytes",
-                EXPECTED_SIZE,
-                account.data.len()
-            ));
+        account: &Account,
+        nonce: u64,
+    ) -> Result<TransferInfo> {
+        check_discriminator(pda, &account.data, self.discriminator.as_ref())?;
+
+        let min_len = match self.nonce_offset {
+            Some(offset) => MESSAGE_MIN_LEN.max(offset.saturating_add(8)),
+            None => MESSAGE_MIN_LEN,
+        };
+        if account.data.len() < min_len {
+            return Err(InsufficientAccountData {
+                expected: min_len,
+                found: account.data.len(),
+            }
+            .into());
         }
 
-        let to_bytes: [u8; 32] = account.data[40..72].try_into()?;
-        let to = Pubkey::from(to_bytes);
+        // 推导或布局错误会导致转发错误的数据
+        if let Some(offset) = self.nonce_offset {
+            let found = u64::from_le_bytes(read_bytes(&account.data, offset)?);
+            if found != nonce {
+                return Err(PdaNonceMismatch {
+                    pda: *pda,
+                    expected: nonce,
+                    found,
+                }
+                .into());
+            }
+        }
+
+        let (to, owner) =
+            self.destination(Pubkey::from(read_bytes::<32>(&account.data, TO_OFFSET)?));
+        let amount = u64::from_le_bytes(read_bytes(&account.data, AMOUNT_OFFSET)?);
+
+        let selector = match self.selector_offset {
+            Some(offset) => Some(*account.data.get(offset).ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Selector offset {} is out of bounds for PDA data of {} bytes",
+                    offset,
+                    account.data.len()
+                )
+            })?),
+            None => None,
+        };
+
+        Ok(TransferInfo {
+            amount,
+            to,
+            selector,
+            owner,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SELECTOR_OFFSET: usize = MESSAGE_MIN_LEN;
+
+    fn manager(selector_offset: Option<usize>, destination_mint: Option<Pubkey>) -> PdaManager {
+        PdaManager::new(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            selector_offset,
+            None,
+            destination_mint,
+            None,
+            Endianness::Le,
+        )
+    }
+
+    fn message(to: &Pubkey, amount: u64, selector: u8) -> Account {
+        let mut data = vec![0u8; SELECTOR_OFFSET + 1];
+        data[TO_OFFSET..TO_OFFSET + 32].copy_from_slice(to.as_ref());
+        data[AMOUNT_OFFSET..AMOUNT_OFFSET + 8].copy_from_slice(&amount.to_le_bytes());
+        data[SELECTOR_OFFSET] = selector;
+        Account {
+            data,
+            ..Account::default()
+        }
+    }
+
+    #[test]
+    fn routes_messages_by_selector() {
+        let manager = manager(Some(SELECTOR_OFFSET), None);
+        let (first, second) = (Pubkey::new_unique(), Pubkey::new_unique());
+
+        let info = manager
+            .get_transfer_info(&Pubkey::new_unique(), &message(&first, 10, 0), 0)
+            .unwrap();
+        assert_eq!((info.to, info.amount, info.selector), (first, 10, Some(0)));
+
+        let info = manager
+            .get_transfer_info(&Pubkey::new_unique(), &message(&second, 20, 1), 1)
+            .unwrap();
+        assert_eq!((info.to, info.amount, info.selector), (second, 20, Some(1)));
+    }
+
+    #[test]
+    fn falls_back_to_default_route_without_selector() {
+        let manager = manager(None, None);
+        let to = Pubkey::new_unique();
+
+        let info = manager
+            .get_transfer_info(&Pubkey::new_unique(), &message(&to, 10, 1), 0)
+            .unwrap();
+        assert_eq!(info.selector, None);
+        assert_eq!((info.to, info.owner), (to, None));
+    }
+
+    #[test]
+    fn rejects_selector_past_the_data() {
+        let manager = manager(Some(SELECTOR_OFFSET + 1), None);
+        let err = manager
+            .get_transfer_info(
+                &Pubkey::new_unique(),
+                &message(&Pubkey::new_unique(), 10, 0),
+                0,
+            )
+            .unwrap_err();
+        assert!(err.to_string().contains("out of bounds"));
+    }
+
+    /// Asserts data of exactly `min_len` bytes parses and one byte less is
+    /// rejected by the length check rather than a field read
+    fn assert_min_len(manager: &PdaManager, mut data: Vec<u8>, min_len: usize, nonce: u64) {
+        data.truncate(min_len);
+        let account = Account {
+            data,
+            ..Account::default()
+        };
+        manager
+            .get_transfer_info(&Pubkey::new_unique(), &account, nonce)
+            .unwrap();
+
+        let mut short = account;
+        short.data.pop();
+        let err = manager
+            .get_transfer_info(&Pubkey::new_unique(), &short, nonce)
+            .unwrap_err();
+        let err = err.downcast_ref::<InsufficientAccountData>().unwrap();
+        assert_eq!((err.expected, err.found), (min_len, min_len - 1));
+    }
+
+    #[test]
+    fn length_check_covers_every_field_read() {
+        let to = Pubkey::new_unique();
+        assert_min_len(
+            &manager(None, None),
+            message(&to, 10, 0).data,
+            MESSAGE_MIN_LEN,
+            0,
+        );
+
+        // nonce 字段位于末尾时，最小长度随之增加
+        let nonce_offset = MESSAGE_MIN_LEN + 4;
+        let manager = PdaManager::new(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            None,
+            None,
+            None,
+            Some(nonce_offset),
+            Endianness::Le,
+        );
+        let mut data = message(&to, 10, 0).data;
+        data.resize(nonce_offset, 0);
+        data.extend_from_slice(&7u64.to_le_bytes());
+        assert_min_len(&manager, data, nonce_offset + 8, 7);
+    }
+
+    #[test]
+    fn rejects_pdas_storing_another_nonce() {
+        let nonce_offset = MESSAGE_MIN_LEN;
+        let checked = PdaManager::new(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            None,
+            None,
+            None,
+            Some(nonce_offset),
+            Endianness::Le,
+        );
+        let to = Pubkey::new_unique();
+        let mut account = message(&to, 10, 0);
+        account.data.resize(nonce_offset, 0);
+        account.data.extend_from_slice(&7u64.to_le_bytes());
+        let pda = Pubkey::new_unique();
+
+        let info = checked.get_transfer_info(&pda, &account, 7).unwrap();
+        assert_eq!((info.to, info.amount), (to, 10));
+
+        let err = checked.get_transfer_info(&pda, &account, 8).unwrap_err();
+        let mismatch = err.downcast_ref::<PdaNonceMismatch>().unwrap();
+        assert_eq!(
+            (mismatch.pda, mismatch.expected, mismatch.found),
+            (pda, 8, 7)
+        );
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Message PDA {} stores nonce 7 but was derived for nonce 8",
+                pda
+            )
+        );
+
+        // 未开启校验时不读取 nonce 字段
+        manager(None, None)
+            .get_transfer_info(&pda, &account, 8)
+            .unwrap();
+    }
+
+    #[test]
+    fn derives_address_and_bump_from_nonce_seeds() {
+        let mut manager = manager(None, None);
+        let (pda, bump) = manager.find_address(7);
+        let seeds: &[&[u8]] = &[
+            b"nonce",
+            manager.watched_account.as_ref(),
+            &7u64.to_le_bytes(),
+        ];
+        assert_eq!(
+            (pda, bump),
+            Pubkey::find_program_address(seeds, &manager.program_id)
+        );
+        // bump 必须能与种子一起重新推导出同一地址
+        let mut signer_seeds = seeds.to_vec();
+        let bump_seed = [bump];
+        signer_seeds.push(&bump_seed);
+        assert_eq!(
+            Pubkey::create_program_address(&signer_seeds, &manager.program_id).unwrap(),
+            pda
+        );
+
+        manager.set_stream_seed(b"usdc".to_vec());
+        let seeds: &[&[u8]] = &[
+            b"nonce",
+            manager.watched_account.as_ref(),
+            b"usdc",
+            &7u64.to_le_bytes(),
+        ];
+        assert_eq!(
+            manager.find_address(7),
+            Pubkey::find_program_address(seeds, &manager.program_id)
+        );
+    }
+
+    #[test]
+    fn derives_address_from_big_endian_nonce_seeds() {
+        let le = manager(None, None);
+        let be = PdaManager::new(
+            le.program_id,
+            le.watched_account,
+            None,
+            None,
+            None,
+            None,
+            Endianness::Be,
+        );
+        let seeds: &[&[u8]] = &[b"nonce", be.watched_account.as_ref(), &7u64.to_be_bytes()];
+        assert_eq!(
+            be.find_address(7),
+            Pubkey::find_program_address(seeds, &be.program_id)
+        );
+        assert_ne!(be.find_address(7), le.find_address(7));
+    }
 
-        let amount_bytes: [u8; 8] = account.data[72..80].try_into()?;
-        let amount = u64::from_le_bytes(amount_bytes);
+    #[test]
+    fn derives_token_destination_for_mint() {
+        let mint = Pubkey::new_unique();
+        let wallet = Pubkey::new_unique();
 
-        Ok((amount, to))
+        assert_eq!(manager(None, None).destination(wallet), (wallet, None));
+        assert_eq!(
+            manager(None, Some(mint)).destination(wallet),
+            (get_associated_token_address(&wallet, &mint), Some(wallet))
+        );
     }
 }
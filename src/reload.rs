@@ -0,0 +1,103 @@
+//! Live configuration reload.
+//! A SIGHUP re-reads the config file into a shared cell, which the relayer
+//! picks up between cycles, applying only the fields that can change live.
+
+use crate::config::RelayerConfig;
+use anyhow::Result;
+use serde_json::Value;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// Config reloaded on SIGHUP, waiting to be applied by the relayer
+pub type ConfigCell = Arc<Mutex<Option<RelayerConfig>>>;
+
+/// Fields applied without a restart, everything else needs one
+pub const LIVE_FIELDS: &[&str] = &[
+    "post_submit_delay_ms",
+    "confirm_timeout_secs",
+    "max_pending_confirmations",
+    "confirmation_strategy",
+    "confirmation_retries",
+    "min_gap_to_relay",
+    "max_nonces_per_run",
+    "emergency_lag_threshold",
+    "inter_transfer_delay_ms",
+    "coalesce_window_ms",
+    "post_catchup_cooldown_secs",
+    "processing_order",
+    "on_transfer_error",
+    "on_missing_destination",
+    "relay_filter",
+    "maintenance_windows",
+    "verify_state_change",
+    "decision_trace",
+    "expected_log_substring",
+    "fee_payer_fee_reserve_lamports",
+    "cursor_divergence_threshold",
+    "cursor_divergence_policy",
+    "discriminator_mismatch_policy",
+    "zero_amount_policy",
+    "pda_read_retries",
+    "pda_read_retry_delay_ms",
+    "pda_missing_grace_secs",
+    "verifier_nonce_tolerance",
+    "slot_regression_tolerance",
+    "success_webhook_batch",
+];
+
+/// Outcome of merging a reloaded config into the running one
+pub struct Reload {
+    /// Running config with the live fields taken from the reloaded one
+    pub config: RelayerConfig,
+    /// Live fields that changed
+    pub applied: Vec<String>,
+    /// Changed fields that only take effect after a restart
+    pub ignored: Vec<String>,
+}
+
+/// Merges the live fields of `reloaded` into `running`
+pub fn merge(running: &RelayerConfig, reloaded: &RelayerConfig) -> Result<Reload> {
+    let mut merged = serde_json::to_value(running)?;
+    let reloaded = serde_json::to_value(reloaded)?;
+    let (fields, new_fields) = match (&mut merged, reloaded) {
+        (Value::Object(fields), Value::Object(new_fields)) => (fields, new_fields),
+        _ => return Err(anyhow::anyhow!("Config doesn't serialize to an object")),
+    };
+
+    let mut applied = Vec::new();
+    let mut ignored = Vec::new();
+    for (name, value) in new_fields {
+        if fields.get(&name) == Some(&value) {
+            continue;
+        }
+        if LIVE_FIELDS.contains(&name.as_str()) {
+            fields.insert(name.clone(), value);
+            applied.push(name);
+        } else {
+            ignored.push(name);
+        }
+    }
+
+    Ok(Reload {
+        config: serde_json::from_value(merged)?,
+        applied,
+        ignored,
+    })
+}
+
+/// Re-reads and validates the config file on every SIGHUP, leaving the
+/// result in `cell`. An invalid file keeps the running config
+#[cfg(unix)]
+pub async fn watch_sighup(path: PathBuf, cell: ConfigCell) -> Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = signal(SignalKind::hangup())?;
+    while hangup.recv().await.is_some() {
+        println!("SIGHUP received, reloading {}", path.display());
+        match RelayerConfig::load(&path) {
+            Ok(config) => *cell.lock().unwrap() = Some(config),
+            Err(err) => println!("Config reload failed, keeping the running config: {}", err),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,384 @@
+//! Relay transaction signing backends.
+//! The fee payer either signs in process from a keypair file, through a
+//! remote signing service or on a Ledger device, so the private key never
+//! has to enter the relayer. A Ledger signs one relay at a time over USB,
+//! which limits the relayer to a few relays per second at best.
+
+use crate::config::{RelayerConfig, SignerBackend};
+use anyhow::Result;
+use serde::Deserialize;
+use serde_json::json;
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{read_keypair_file, Keypair, Signature},
+    signer::{Signer, SignerError},
+};
+use std::{str::FromStr, time::Duration};
+
+/// Signer used for the relay fee payer
+pub trait RelaySigner: Signer {
+    /// Short description of the backend for startup logs
+    fn describe(&self) -> String;
+}
+
+impl RelaySigner for Keypair {
+    fn describe(&self) -> String {
+        format!("local keypair {}", self.pubkey())
+    }
+}
+
+/// Creates the fee payer signer selected by `signer_backend`
+pub fn load_signer(config: &RelayerConfig) -> Result<Box<dyn RelaySigner>> {
+    match config.signer_backend {
+        SignerBackend::Keypair => {
+            let keypair = read_keypair_file(&config.wallet_path)
+                .map_err(|e| anyhow::anyhow!("Failed to read keypair file: {}", e))?;
+            Ok(Box::new(keypair))
+        }
+        SignerBackend::Remote => {
+            let url = config.remote_signer_url.clone().ok_or_else(|| {
+                anyhow::anyhow!("signer_backend = \"remote\" requires remote_signer_url")
+            })?;
+            let pubkey = config.remote_signer_pubkey.as_deref().ok_or_else(|| {
+                anyhow::anyhow!("signer_backend = \"remote\" requires remote_signer_pubkey")
+            })?;
+            let pubkey = Pubkey::from_str(pubkey)
+                .map_err(|e| anyhow::anyhow!("Invalid remote signer pubkey: {}", e))?;
+            Ok(Box::new(RemoteSigner::new(url, pubkey)))
+        }
+        #[cfg(feature = "ledger")]
+        SignerBackend::Ledger => Ok(Box::new(ledger::load(config)?)),
+        #[cfg(not(feature = "ledger"))]
+        SignerBackend::Ledger => Err(anyhow::anyhow!(
+            "signer_backend = \"ledger\" requires building with the ledger feature"
+        )),
+    }
+}
+
+#[cfg(feature = "ledger")]
+mod ledger {
+    use super::RelaySigner;
+    use crate::config::RelayerConfig;
+    use anyhow::Result;
+    use solana_remote_wallet::{
+        ledger::LedgerWallet,
+        locator::Locator,
+        remote_keypair::generate_remote_keypair,
+        remote_wallet::{maybe_wallet_manager, RemoteWallet, RemoteWalletError, RemoteWalletType},
+    };
+    use solana_sdk::{
+        derivation_path::DerivationPath,
+        pubkey::Pubkey,
+        signature::Signature,
+        signer::{Signer, SignerError},
+    };
+    use std::sync::Arc;
+
+    /// Device signing with a derived key, the Ledger itself outside tests
+    pub trait LedgerDevice {
+        fn sign_message(
+            &self,
+            derivation_path: &DerivationPath,
+            data: &[u8],
+        ) -> Result<Signature, RemoteWalletError>;
+    }
+
+    impl LedgerDevice for LedgerWallet {
+        fn sign_message(
+            &self,
+            derivation_path: &DerivationPath,
+            data: &[u8],
+        ) -> Result<Signature, RemoteWalletError> {
+            RemoteWallet::sign_message(self, derivation_path, data)
+        }
+    }
+
+    /// Fee payer key held on a Ledger, each signature confirmed on the device
+    pub struct LedgerSigner<D> {
+        device: Arc<D>,
+        derivation_path: DerivationPath,
+        pubkey: Pubkey,
+        path: String,
+    }
+
+    impl<D: LedgerDevice> Signer for LedgerSigner<D> {
+        fn try_pubkey(&self) -> Result<Pubkey, SignerError> {
+            Ok(self.pubkey)
+        }
+
+        fn try_sign_message(&self, message: &[u8]) -> Result<Signature, SignerError> {
+            self.device
+                .sign_message(&self.derivation_path, message)
+                .map_err(SignerError::from)
+        }
+
+        fn is_interactive(&self) -> bool {
+            true
+        }
+    }
+
+    impl<D: LedgerDevice> RelaySigner for LedgerSigner<D> {
+        fn describe(&self) -> String {
+            format!("Ledger {} ({})", self.path, self.pubkey)
+        }
+    }
+
+    /// Opens the fee payer key on the Ledger at `ledger_locator`
+    pub fn load(config: &RelayerConfig) -> Result<LedgerSigner<LedgerWallet>> {
+        let locator = Locator::new_from_path(&config.ledger_locator)
+            .map_err(|e| anyhow::anyhow!("Invalid ledger_locator: {}", e))?;
+        let derivation_path = match &config.ledger_derivation_path {
+            Some(path) => DerivationPath::from_key_str(path)
+                .map_err(|e| anyhow::anyhow!("Invalid ledger_derivation_path: {}", e))?,
+            None => DerivationPath::default(),
+        };
+        let wallet_manager = maybe_wallet_manager()
+            .map_err(|e| anyhow::anyhow!("Failed to open Ledger: {}", e))?
+            .ok_or_else(|| anyhow::anyhow!("No Ledger device found"))?;
+        // 不要求在设备上确认公钥，只有签名需要确认
+        let keypair = generate_remote_keypair(
+            locator,
+            derivation_path,
+            &wallet_manager,
+            false,
+            "fee payer",
+        )
+        .map_err(|e| anyhow::anyhow!("Failed to read the fee payer from the Ledger: {}", e))?;
+        let RemoteWalletType::Ledger(device) = keypair.wallet_type;
+        Ok(LedgerSigner {
+            device,
+            derivation_path: keypair.derivation_path,
+            pubkey: keypair.pubkey,
+            path: keypair.path,
+        })
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use solana_sdk::signature::Keypair;
+        use std::sync::Mutex;
+
+        /// Device signing with an in-memory key, recording each request
+        struct MockLedger {
+            keypair: Keypair,
+            reject: bool,
+            requests: Mutex<Vec<(DerivationPath, Vec<u8>)>>,
+        }
+
+        impl LedgerDevice for MockLedger {
+            fn sign_message(
+                &self,
+                derivation_path: &DerivationPath,
+                data: &[u8],
+            ) -> Result<Signature, RemoteWalletError> {
+                self.requests
+                    .lock()
+                    .unwrap()
+                    .push((derivation_path.clone(), data.to_vec()));
+                if self.reject {
+                    return Err(RemoteWalletError::UserCancel);
+                }
+                Ok(self.keypair.sign_message(data))
+            }
+        }
+
+        fn signer(reject: bool) -> LedgerSigner<MockLedger> {
+            let keypair = Keypair::new();
+            LedgerSigner {
+                pubkey: keypair.pubkey(),
+                device: Arc::new(MockLedger {
+                    keypair,
+                    reject,
+                    requests: Mutex::new(Vec::new()),
+                }),
+                derivation_path: DerivationPath::new_bip44(Some(3), Some(1)),
+                path: "usb://ledger?key=3/1".to_string(),
+            }
+        }
+
+        #[test]
+        fn signs_on_the_device_with_the_configured_path() {
+            let signer = signer(false);
+            let signature = signer.try_sign_message(b"relay").unwrap();
+
+            assert!(signature.verify(signer.pubkey().as_ref(), b"relay"));
+            assert!(signer.is_interactive());
+            assert_eq!(
+                *signer.device.requests.lock().unwrap(),
+                vec![(
+                    DerivationPath::new_bip44(Some(3), Some(1)),
+                    b"relay".to_vec()
+                )]
+            );
+            assert_eq!(
+                signer.describe(),
+                format!("Ledger usb://ledger?key=3/1 ({})", signer.pubkey())
+            );
+        }
+
+        #[test]
+        fn rejection_on_the_device_is_a_user_cancel() {
+            let signer = signer(true);
+            assert!(matches!(
+                signer.try_sign_message(b"relay"),
+                Err(SignerError::UserCancel(_))
+            ));
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct SignResponse {
+    signature: String,
+}
+
+/// Signs messages through an HTTP signing service, e.g. an HSM front end.
+///
+/// Each message is posted as `{"pubkey": ..., "message": <base64>}` and the
+/// service answers with `{"signature": <base58>}`.
+pub struct RemoteSigner {
+    client: reqwest::Client,
+    url: String,
+    pubkey: Pubkey,
+}
+
+impl RemoteSigner {
+    pub fn new(url: String, pubkey: Pubkey) -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .unwrap_or_default(),
+            url,
+            pubkey,
+        }
+    }
+
+    async fn request_signature(&self, message: &[u8]) -> Result<Signature, SignerError> {
+        let response = self
+            .client
+            .post(&self.url)
+            .json(&json!({
+                "pubkey": self.pubkey.to_string(),
+                "message": base64::encode(message),
+            }))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| SignerError::Connection(e.to_string()))?;
+        let body: SignResponse = response
+            .json()
+            .await
+            .map_err(|e| SignerError::Protocol(e.to_string()))?;
+
+        let signature = Signature::from_str(&body.signature)
+            .map_err(|e| SignerError::Protocol(format!("invalid signature: {}", e)))?;
+        // 不信任签名服务，返回的签名必须能用配置的公钥验证
+        if !signature.verify(self.pubkey.as_ref(), message) {
+            return Err(SignerError::KeypairPubkeyMismatch);
+        }
+        Ok(signature)
+    }
+}
+
+impl Signer for RemoteSigner {
+    fn try_pubkey(&self) -> Result<Pubkey, SignerError> {
+        Ok(self.pubkey)
+    }
+
+    fn try_sign_message(&self, message: &[u8]) -> Result<Signature, SignerError> {
+        // Signer 是同步接口，与 RpcClient 一样在当前 runtime 上阻塞等待
+        tokio::task::block_in_place(|| {
+            tokio::runtime::Handle::current().block_on(self.request_signature(message))
+        })
+    }
+
+    fn is_interactive(&self) -> bool {
+        false
+    }
+}
+
+impl RelaySigner for RemoteSigner {
+    fn describe(&self) -> String {
+        format!("remote signer {} at {}", self.pubkey, self.url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::{hash::Hash, message::Message, system_instruction, transaction::Transaction};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Local signing service answering with `key`'s signature of each posted message
+    async fn signing_service(key: Keypair) -> String {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let url = format!("http://{}/sign", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = Vec::new();
+                let mut buf = [0u8; 4096];
+                // 请求体是一个 JSON 对象，读到它完整为止
+                let body: serde_json::Value = loop {
+                    let len = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..len]);
+                    let text = String::from_utf8_lossy(&request).to_string();
+                    if let Some((_, body)) = text.split_once("\r\n\r\n") {
+                        if let Ok(body) = serde_json::from_str(body) {
+                            break body;
+                        }
+                    }
+                };
+                let message = base64::decode(body["message"].as_str().unwrap()).unwrap();
+                let response =
+                    json!({ "signature": key.sign_message(&message).to_string() }).to_string();
+                stream
+                    .write_all(
+                        format!(
+                            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                            response.len(),
+                            response
+                        )
+                        .as_bytes(),
+                    )
+                    .await
+                    .unwrap();
+            }
+        });
+        url
+    }
+
+    fn transfer(payer: &Pubkey) -> Transaction {
+        let instruction = system_instruction::transfer(payer, &Pubkey::new_unique(), 1);
+        Transaction::new_unsigned(Message::new(&[instruction], Some(payer)))
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn remote_signer_signs_relays() {
+        let key = Keypair::new();
+        let pubkey = key.pubkey();
+        let signer = RemoteSigner::new(signing_service(key).await, pubkey);
+        assert_eq!(signer.pubkey(), pubkey);
+
+        let signer: Box<dyn RelaySigner> = Box::new(signer);
+        let mut transaction = transfer(&pubkey);
+        transaction
+            .try_sign(&[signer.as_ref() as &dyn Signer], Hash::new_unique())
+            .unwrap();
+        transaction.verify().unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn remote_signatures_must_match_the_configured_pubkey() {
+        let pubkey = Pubkey::new_unique();
+        let signer = RemoteSigner::new(signing_service(Keypair::new()).await, pubkey);
+
+        let mut transaction = transfer(&pubkey);
+        let err = transaction
+            .try_sign(&[&signer as &dyn Signer], Hash::new_unique())
+            .unwrap_err();
+        assert_eq!(err, SignerError::KeypairPubkeyMismatch);
+    }
+}
@@ -0,0 +1,81 @@
+//! Persisted relayer state.
+//! Keeps the relay cursor on disk so it survives restarts.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+/// State written to the configured `state_path` as JSON
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct RelayerState {
+    pub last_nonce: Option<u64>,
+    /// Nonces whose message PDA was found missing
+    #[serde(default)]
+    pub missing_pdas: BTreeSet<u64>,
+    /// Nonces an operator marked as permanently skipped
+    #[serde(default)]
+    pub skipped_nonces: BTreeSet<u64>,
+    /// Submitted relays that timed out unconfirmed, keyed by nonce
+    #[serde(default)]
+    pub pending_confirmations: BTreeMap<u64, PendingConfirmation>,
+    /// Set when lag exceeded `emergency_lag_threshold`, cleared by `resume`
+    #[serde(default)]
+    pub emergency_paused: bool,
+    /// Set by `resume`, lets the catch-up run past the threshold until lag
+    /// drops back under it
+    #[serde(default)]
+    pub emergency_resumed: bool,
+}
+
+/// Relay transaction whose outcome is still unknown
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PendingConfirmation {
+    pub signature: String,
+    pub recent_blockhash: String,
+    /// Transfer the relay carries, so a late confirmation is reported like
+    /// any other. Absent in state written before it was recorded
+    #[serde(default)]
+    pub transfer: Option<PendingTransfer>,
+    /// Submission time in Unix seconds
+    #[serde(default)]
+    pub submitted_at: Option<u64>,
+}
+
+/// Transfer carried by a pending relay, the total of an aggregated one
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct PendingTransfer {
+    pub selector: Option<u8>,
+    pub amount: u64,
+    pub destination: String,
+}
+
+impl RelayerState {
+    /// Loads state from a JSON file, returning `None` if the file doesn't exist yet
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Option<Self>> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let data = fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read state file {}: {}", path.display(), e))?;
+        let state = serde_json::from_str(&data)
+            .map_err(|e| anyhow::anyhow!("Invalid state file {}: {}", path.display(), e))?;
+
+        Ok(Some(state))
+    }
+
+    /// Writes state atomically by renaming a temporary file over the target
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let tmp_path = path.with_extension("tmp");
+
+        fs::write(&tmp_path, serde_json::to_vec_pretty(self)?)?;
+        fs::rename(&tmp_path, path)
+            .map_err(|e| anyhow::anyhow!("Failed to write state file {}: {}", path.display(), e))?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,190 @@
+//! Tracing spans and their OpenTelemetry export.
+//! Each monitor cycle, relay and RPC call runs in a span; with
+//! `otlp_endpoint` set they are exported over OTLP/HTTP from a background
+//! batch processor, so a slow or unreachable collector never holds up
+//! relaying.
+
+use anyhow::Result;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{runtime, trace, Resource};
+use solana_client::rpc_request::RpcRequest;
+use tracing_subscriber::layer::SubscriberExt;
+
+/// Service name spans are exported under
+const SERVICE_NAME: &str = "sol-bridge-relayer";
+
+/// Installs the OTLP exporter as the global tracing subscriber. Must be
+/// called within the tokio runtime
+pub fn init(endpoint: &str) -> Result<()> {
+    // 导出失败只记录警告，不影响转发
+    opentelemetry::global::set_error_handler(|err| {
+        println!("Warning: trace export failed: {}", err);
+    })?;
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .http()
+                .with_endpoint(endpoint),
+        )
+        .with_trace_config(
+            trace::config()
+                .with_resource(Resource::new([KeyValue::new("service.name", SERVICE_NAME)])),
+        )
+        .install_batch(runtime::Tokio)
+        .map_err(|e| anyhow::anyhow!("Failed to start OTLP exporter for {}: {}", endpoint, e))?;
+
+    let subscriber =
+        tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer));
+    tracing::subscriber::set_global_default(subscriber)?;
+    println!("Exporting traces to {}", endpoint);
+    Ok(())
+}
+
+/// Exports the spans still queued in the batch processor and stops it,
+/// a no-op without `otlp_endpoint`
+pub async fn shutdown() {
+    // 关闭时会阻塞等待导出完成，放到阻塞线程执行
+    if let Err(err) =
+        tokio::task::spawn_blocking(opentelemetry::global::shutdown_tracer_provider).await
+    {
+        println!("Warning: trace export shutdown failed: {}", err);
+    }
+}
+
+/// Span enclosing one monitor cycle, the parent of the relay spans in it
+pub fn cycle_span(stream: &Option<String>) -> tracing::Span {
+    tracing::info_span!("cycle", stream = stream.as_deref().unwrap_or("default"))
+}
+
+/// Span grouping every log event of one relay under a fresh correlation ID
+pub fn relay_span(nonce: u64) -> tracing::Span {
+    let correlation_id = format!("{:016x}", rand::random::<u64>());
+    tracing::info_span!("relay", %correlation_id, nonce)
+}
+
+/// Span of one JSON-RPC call, a child of the relay or cycle making it
+pub fn rpc_span(request: RpcRequest, endpoint: &str) -> tracing::Span {
+    tracing::info_span!("rpc", method = %request, endpoint)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_sdk::testing::trace::InMemorySpanExporterBuilder;
+    use std::collections::BTreeMap;
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+    /// Fields of a span or event, formatted with `Debug`
+    #[derive(Default, Clone)]
+    struct Fields(BTreeMap<String, String>);
+
+    impl Visit for Fields {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            self.0
+                .insert(field.name().to_string(), format!("{:?}", value));
+        }
+    }
+
+    /// Records every event together with the fields of its enclosing spans
+    #[derive(Default, Clone)]
+    struct EventCapture(Arc<Mutex<Vec<BTreeMap<String, String>>>>);
+
+    impl<S> Layer<S> for EventCapture
+    where
+        S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+    {
+        fn on_new_span(
+            &self,
+            attrs: &tracing::span::Attributes<'_>,
+            id: &tracing::span::Id,
+            ctx: Context<'_, S>,
+        ) {
+            let mut fields = Fields::default();
+            attrs.record(&mut fields);
+            ctx.span(id).unwrap().extensions_mut().insert(fields);
+        }
+
+        fn on_event(&self, event: &tracing::Event<'_>, ctx: Context<'_, S>) {
+            let mut fields = Fields::default();
+            for span in ctx.event_scope(event).into_iter().flatten() {
+                if let Some(span_fields) = span.extensions().get::<Fields>() {
+                    for (name, value) in &span_fields.0 {
+                        fields
+                            .0
+                            .entry(name.clone())
+                            .or_insert_with(|| value.clone());
+                    }
+                }
+            }
+            event.record(&mut fields);
+            self.0.lock().unwrap().push(fields.0);
+        }
+    }
+
+    #[test]
+    fn exports_nested_cycle_relay_and_rpc_spans() {
+        let exporter = InMemorySpanExporterBuilder::new().build();
+        let provider = trace::TracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let _cycle = cycle_span(&Some("usdc".to_string())).entered();
+            let _relay = relay_span(7).entered();
+            rpc_span(RpcRequest::SendTransaction, "127.0.0.1:8899").in_scope(|| {});
+        });
+
+        // 简单处理器在后台线程导出
+        provider.force_flush();
+        let spans = exporter.get_finished_spans().unwrap();
+        let span = |name: &str| {
+            spans
+                .iter()
+                .find(|span| span.name == name)
+                .unwrap_or_else(|| panic!("no {} span", name))
+        };
+        let (cycle, relay, rpc) = (span("cycle"), span("relay"), span("rpc"));
+        assert_eq!(relay.parent_span_id, cycle.span_context.span_id());
+        assert_eq!(rpc.parent_span_id, relay.span_context.span_id());
+        assert_eq!(rpc.span_context.trace_id(), cycle.span_context.trace_id());
+        assert!(rpc
+            .attributes
+            .iter()
+            .any(|kv| kv.key.as_str() == "method" && kv.value.as_str() == "sendTransaction"));
+    }
+
+    #[test]
+    fn relay_span_fields_reach_every_child_event() {
+        let capture = EventCapture::default();
+        let subscriber = tracing_subscriber::registry().with(capture.clone());
+
+        tracing::subscriber::with_default(subscriber, || {
+            for nonce in [7, 8] {
+                let _relay = relay_span(nonce).entered();
+                tracing::info!(amount = 1000, "built relay transaction");
+                rpc_span(RpcRequest::SendTransaction, "127.0.0.1:8899")
+                    .in_scope(|| tracing::info!("submitted relay transaction"));
+            }
+        });
+
+        let events = capture.0.lock().unwrap();
+        assert_eq!(events.len(), 4);
+        for (event, nonce) in events.iter().zip(["7", "7", "8", "8"]) {
+            assert_eq!(event["nonce"], nonce);
+            assert_eq!(event["correlation_id"].len(), 16);
+        }
+        assert_eq!(events[0]["amount"], "1000");
+        assert_eq!(events[1]["method"], "sendTransaction");
+        // 同一次转发共享 correlation ID，不同转发各不相同
+        assert_eq!(events[0]["correlation_id"], events[1]["correlation_id"]);
+        assert_eq!(events[2]["correlation_id"], events[3]["correlation_id"]);
+        assert_ne!(events[0]["correlation_id"], events[2]["correlation_id"]);
+    }
+}
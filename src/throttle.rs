@@ -0,0 +1,118 @@
+//! Adaptive submission throttle.
+//! Tracks the confirmation latency of recent relays; while its average is
+//! above the threshold, fewer relays may await confirmation and each
+//! submission waits for the excess, easing off a congested L2 until
+//! confirmations speed up again.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Rolling window of confirmation latencies
+#[derive(Debug, Clone)]
+pub struct LatencyThrottle {
+    threshold: Duration,
+    window: usize,
+    samples: VecDeque<Duration>,
+    throttled: bool,
+}
+
+impl LatencyThrottle {
+    pub fn new(threshold: Duration, window: usize) -> Self {
+        Self {
+            threshold,
+            window,
+            samples: VecDeque::with_capacity(window),
+            throttled: false,
+        }
+    }
+
+    /// Records the latency of a confirmed relay, returning the new state
+    /// when it crossed the threshold in either direction
+    pub fn record(&mut self, latency: Duration) -> Option<bool> {
+        if self.samples.len() == self.window {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(latency);
+
+        let throttled = self.average() > self.threshold;
+        if throttled == self.throttled {
+            return None;
+        }
+        self.throttled = throttled;
+        Some(throttled)
+    }
+
+    /// Average latency over the window, zero before any relay confirmed
+    pub fn average(&self) -> Duration {
+        if self.samples.is_empty() {
+            return Duration::ZERO;
+        }
+        self.samples.iter().sum::<Duration>() / self.samples.len() as u32
+    }
+
+    /// Pending confirmation limit scaled down by how far the average
+    /// latency exceeds the threshold, never below one
+    pub fn pending_limit(&self, limit: usize) -> usize {
+        let average = self.average();
+        if average <= self.threshold {
+            return limit;
+        }
+        // 按 threshold / average 的比例缩小并发
+        let scaled = limit as u128 * self.threshold.as_millis() / average.as_millis();
+        (scaled as usize).max(1)
+    }
+
+    /// Extra wait before each submission, the latency above the threshold
+    pub fn delay(&self) -> Duration {
+        self.average().saturating_sub(self.threshold)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn leaves_limits_alone_under_the_threshold() {
+        let mut throttle = LatencyThrottle::new(ms(1000), 4);
+        assert_eq!(throttle.record(ms(400)), None);
+        assert_eq!(throttle.record(ms(800)), None);
+
+        assert_eq!(throttle.average(), ms(600));
+        assert_eq!(throttle.pending_limit(8), 8);
+        assert_eq!(throttle.delay(), Duration::ZERO);
+    }
+
+    #[test]
+    fn rising_latency_reduces_concurrency() {
+        let mut throttle = LatencyThrottle::new(ms(1000), 2);
+        assert_eq!(throttle.record(ms(1000)), None);
+        assert_eq!(throttle.record(ms(3000)), Some(true));
+
+        // 平均 2s，并发减半
+        assert_eq!(throttle.pending_limit(8), 4);
+        assert_eq!(throttle.delay(), ms(1000));
+
+        throttle.record(ms(7000));
+        // 平均 5s，并发降到五分之一
+        assert_eq!(throttle.pending_limit(8), 1);
+        assert_eq!(throttle.pending_limit(1), 1);
+        assert_eq!(throttle.delay(), ms(4000));
+    }
+
+    #[test]
+    fn lifts_once_latency_recovers() {
+        let mut throttle = LatencyThrottle::new(ms(1000), 2);
+        throttle.record(ms(5000));
+        assert!(throttle.delay() > Duration::ZERO);
+
+        assert_eq!(throttle.record(ms(500)), None);
+        assert_eq!(throttle.record(ms(500)), Some(false));
+        assert_eq!(throttle.pending_limit(8), 8);
+        assert_eq!(throttle.delay(), Duration::ZERO);
+    }
+}
@@ -6,64 +6,1134 @@
  * @LastEditors: Yulin
  * @LastEditTime: 2024-11-20 22:20:50
  */
+use crate::{
+    config::{parse_hex, InstructionField, InstructionSource, VersionPosition},
+    error::RelayerError,
+    memo::MemoKey,
+};
 use anyhow::Result;
-use solana_client::rpc_client::RpcClient;
+use borsh::BorshSerialize;
+use solana_client::{nonce_utils, rpc_client::RpcClient};
 use solana_sdk::{
+    clock::{DEFAULT_MS_PER_SLOT, MAX_PROCESSING_AGE},
+    commitment_config::CommitmentConfig,
+    compute_budget::ComputeBudgetInstruction,
+    hash::Hash,
     instruction::{AccountMeta, Instruction},
     pubkey::Pubkey,
     signer::Signer,
+    system_instruction,
     transaction::Transaction,
 };
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Instruction and transaction settings shared by every builder
+#[derive(Debug, Clone)]
+pub struct BuildOptions {
+    /// Largest serialized transaction accepted, in bytes
+    pub max_transaction_size: usize,
+    /// Instruction data layout
+    pub data_template: Vec<DataField>,
+    /// Durable nonce used in place of a recent blockhash
+    pub durable_nonce: Option<DurableNonce>,
+    /// Recent blockhash shared by every build
+    pub blockhash_cache: Arc<BlockhashCache>,
+    /// Compute unit price escalation, `None` for no priority fee
+    pub priority_fee: Option<PriorityFee>,
+    /// L1 instruction closing message PDAs after their relay, if enabled
+    pub close_pda: Option<ClosePda>,
+    /// L2 instruction creating a missing nonce account, if enabled
+    pub init_nonce_account: Option<InitNonceAccount>,
+    /// Key of the HMAC memo attached to every relay, if enabled
+    pub memo_key: Option<MemoKey>,
+}
+
+/// L1 program instruction that closes a relayed message PDA, laid out as
+/// `discriminator | nonce: u64` over `[pda (writable), recipient (signer)]`
+#[derive(Debug, Clone)]
+pub struct ClosePda {
+    pub program_id: Pubkey,
+    pub discriminator: [u8; 8],
+}
+
+/// Anchor discriminator of the default `close_message` instruction
+pub fn close_message_discriminator() -> [u8; 8] {
+    let hash = solana_sdk::hash::hash(b"global:close_message");
+    let mut discriminator = [0u8; 8];
+    discriminator.copy_from_slice(&hash.to_bytes()[..8]);
+    discriminator
+}
+
+/// L2 program instruction that creates a nonce account, laid out as
+/// `discriminator` over `[nonce account (writable), payer (writable, signer),
+/// system program]` followed by `accounts` read-only
+#[derive(Debug, Clone)]
+pub struct InitNonceAccount {
+    pub discriminator: [u8; 8],
+    pub accounts: Vec<Pubkey>,
+}
+
+/// Anchor discriminator of the default `initialize` instruction
+pub fn initialize_discriminator() -> [u8; 8] {
+    let hash = solana_sdk::hash::hash(b"global:initialize");
+    let mut discriminator = [0u8; 8];
+    discriminator.copy_from_slice(&hash.to_bytes()[..8]);
+    discriminator
+}
+
+/// Compute unit price that grows with how long a nonce has been pending
+#[derive(Debug, Clone, Copy)]
+pub struct PriorityFee {
+    /// Price of a freshly seen nonce, in micro-lamports
+    pub base: u64,
+    /// Added for every second the nonce has been pending
+    pub increment_per_sec: u64,
+    pub max: Option<u64>,
+}
+
+impl PriorityFee {
+    /// Compute unit price for a nonce pending for `pending_for`
+    pub fn compute_unit_price(&self, pending_for: Duration) -> u64 {
+        let price = self
+            .base
+            .saturating_add(self.increment_per_sec.saturating_mul(pending_for.as_secs()));
+        self.max.map_or(price, |max| price.min(max))
+    }
+}
+
+/// Compute unit price raised on every resend of a relay whose blockhash
+/// expired before it landed
+#[derive(Debug, Clone, Copy)]
+pub struct FeeEscalation {
+    /// Added to the compute unit price on every resend
+    pub step: u64,
+    /// Resends of one nonce, counted across cycles
+    pub max_attempts: u32,
+    pub max: Option<u64>,
+}
+
+impl FeeEscalation {
+    /// Compute unit price of resend `attempt` of a relay first priced at `base`
+    pub fn compute_unit_price(&self, base: u64, attempt: u32) -> u64 {
+        let price = base.saturating_add(self.step.saturating_mul(attempt.into()));
+        self.max.map_or(price, |max| price.min(max))
+    }
+}
+
+/// Compute unit price set by the transaction, if it has a priority fee
+pub fn compute_unit_price(transaction: &Transaction) -> Option<u64> {
+    let message = &transaction.message;
+    message.instructions.iter().find_map(|instruction| {
+        let program_id = message
+            .account_keys
+            .get(usize::from(instruction.program_id_index))?;
+        if *program_id != solana_sdk::compute_budget::id() {
+            return None;
+        }
+        // SetComputeUnitPrice 的 borsh 编码为 tag 3 加 u64
+        match instruction.data.split_first() {
+            Some((3, price)) => Some(u64::from_le_bytes(price.try_into().ok()?)),
+            _ => None,
+        }
+    })
+}
+
+/// Rewrites the compute unit price of a built transaction, which must be
+/// signed again afterwards. Returns whether it had a priority fee to rewrite
+pub fn set_compute_unit_price(transaction: &mut Transaction, price: u64) -> bool {
+    let message = &mut transaction.message;
+    let data = ComputeBudgetInstruction::set_compute_unit_price(price).data;
+    for instruction in &mut message.instructions {
+        let is_compute_budget = message
+            .account_keys
+            .get(usize::from(instruction.program_id_index))
+            == Some(&solana_sdk::compute_budget::id());
+        if is_compute_budget && instruction.data.first() == data.first() {
+            instruction.data = data;
+            return true;
+        }
+    }
+    false
+}
+
+/// Durable nonce account advanced by every relay transaction
+#[derive(Debug, Clone)]
+pub struct DurableNonce {
+    pub account: Pubkey,
+    /// Nonce authority, one of the relay transaction signers
+    pub authority: Pubkey,
+}
+
+/// Recent blockhash shared between builds, refreshed once it's older than a
+/// number of slots so transactions aren't signed with a nearly expired hash
+#[derive(Debug)]
+pub struct BlockhashCache {
+    max_age_slots: u64,
+    /// Oldest blockhash accepted from a fetch, in slots since it was produced
+    max_fetch_age_slots: Option<u64>,
+    commitment: CommitmentConfig,
+    cached: Mutex<Option<CachedBlockhash>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct CachedBlockhash {
+    blockhash: Hash,
+    /// Slots since the blockhash was produced, when it was fetched
+    age_at_fetch: u64,
+    fetched_at: Instant,
+}
+
+impl BlockhashCache {
+    pub fn new(
+        max_age_slots: u64,
+        max_fetch_age_slots: Option<u64>,
+        commitment: CommitmentConfig,
+    ) -> Self {
+        Self {
+            max_age_slots,
+            max_fetch_age_slots,
+            commitment,
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// Returns the cached blockhash, fetching a fresh one if it's stale
+    pub fn get(&self, client: &RpcClient) -> Result<Hash> {
+        let mut cached = self.cached.lock().unwrap();
+        match *cached {
+            Some(entry) if !self.is_expired(&entry) => Ok(entry.blockhash),
+            _ => {
+                let entry = self.fetch(client)?;
+                *cached = Some(entry);
+                Ok(entry.blockhash)
+            }
+        }
+    }
+
+    /// Fetches the latest blockhash at the configured commitment, refetching
+    /// at `processed` if it's older than `max_blockhash_age_slots`
+    fn fetch(&self, client: &RpcClient) -> Result<CachedBlockhash> {
+        let mut commitment = self.commitment;
+        loop {
+            let (blockhash, last_valid_block_height) = client
+                .get_latest_blockhash_with_commitment(commitment)
+                .map_err(RelayerError::from)?;
+            let block_height = client
+                .get_block_height_with_commitment(CommitmentConfig::processed())
+                .map_err(RelayerError::from)?;
+            // last_valid_block_height 为生成时的区块高度加上 MAX_PROCESSING_AGE
+            let produced_at = last_valid_block_height.saturating_sub(MAX_PROCESSING_AGE as u64);
+            let age_at_fetch = block_height.saturating_sub(produced_at);
+
+            match self.max_fetch_age_slots {
+                Some(max_age) if age_at_fetch > max_age => {
+                    if commitment == CommitmentConfig::processed() {
+                        return Err(anyhow::anyhow!(
+                            "Latest blockhash is {} slots old, above max_blockhash_age_slots ({})",
+                            age_at_fetch,
+                            max_age
+                        ));
+                    }
+                    println!(
+                        "Blockhash at {:?} commitment is {} slots old, refetching at processed",
+                        commitment.commitment, age_at_fetch
+                    );
+                    commitment = CommitmentConfig::processed();
+                }
+                _ => {
+                    return Ok(CachedBlockhash {
+                        blockhash,
+                        age_at_fetch,
+                        fetched_at: Instant::now(),
+                    })
+                }
+            }
+        }
+    }
+
+    /// Whether a transaction signed with `blockhash` should be re-signed
+    /// before submitting
+    pub fn is_stale(&self, blockhash: &Hash) -> bool {
+        match *self.cached.lock().unwrap() {
+            Some(entry) => entry.blockhash != *blockhash || self.is_expired(&entry),
+            None => true,
+        }
+    }
+
+    /// Slots since the fetch are estimated from the elapsed time, so checking
+    /// the age costs no RPC call
+    fn is_expired(&self, entry: &CachedBlockhash) -> bool {
+        let age_slots = entry.age_at_fetch
+            + entry.fetched_at.elapsed().as_millis() as u64 / DEFAULT_MS_PER_SLOT;
+        if age_slots < self.max_age_slots {
+            return false;
+        }
+        println!(
+            "Cached blockhash {} is ~{} slots old, refreshing",
+            entry.blockhash, age_slots
+        );
+        true
+    }
+}
+
+/// Arguments of the `relay_message` instruction, Borsh-serialized after
+/// the discriminator the way Anchor lays them out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, BorshSerialize)]
+pub struct RelayArgs {
+    pub amount: u64,
+    pub nonce: u64,
+}
+
+/// `relay_message` instruction discriminator
+const RELAY_MESSAGE_DISCRIMINATOR: [u8; 8] = [187, 90, 182, 138, 51, 248, 175, 98];
+
+/// Validated instruction data field
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DataField {
+    /// Transfer amount, little-endian in the given number of bytes
+    Amount(usize),
+    /// Message nonce, little-endian in the given number of bytes
+    Nonce(usize),
+    Destination,
+    Bump,
+    Literal(Vec<u8>),
+    /// Amount and nonce as Borsh-serialized `RelayArgs`
+    RelayArgs,
+}
+
+/// Validates the configured instruction template, falling back to the
+/// `relay_message` layout with the given discriminator when none is configured
+pub fn compile_template(
+    fields: &[InstructionField],
+    include_bump: bool,
+    discriminator: Option<[u8; 8]>,
+) -> Result<Vec<DataField>> {
+    if fields.is_empty() {
+        let mut template = vec![
+            DataField::Literal(
+                discriminator
+                    .unwrap_or(RELAY_MESSAGE_DISCRIMINATOR)
+                    .to_vec(),
+            ),
+            DataField::RelayArgs,
+        ];
+        if include_bump {
+            template.push(DataField::Bump);
+        }
+        return Ok(template);
+    }
+    if include_bump {
+        return Err(anyhow::anyhow!(
+            "include_bump cannot be combined with instruction_template, add a bump field instead"
+        ));
+    }
+    if discriminator.is_some() {
+        return Err(anyhow::anyhow!(
+            "instruction_discriminator cannot be combined with instruction_template, add a literal field instead"
+        ));
+    }
+
+    fields
+        .iter()
+        .enumerate()
+        .map(|(i, field)| {
+            if field.bytes.is_some() && field.source != InstructionSource::Literal {
+                return Err(anyhow::anyhow!(
+                    "Instruction field {}: bytes are only allowed on literal fields",
+                    i
+                ));
+            }
+            let width = |natural: usize, allowed: &[usize]| {
+                let width = field.width.unwrap_or(natural);
+                if allowed.contains(&width) {
+                    Ok(width)
+                } else {
+                    Err(anyhow::anyhow!(
+                        "Instruction field {}: unsupported {:?} width {}",
+                        i,
+                        field.source,
+                        width
+                    ))
+                }
+            };
+
+            Ok(match field.source {
+                InstructionSource::Amount => DataField::Amount(width(8, &[1, 2, 4, 8])?),
+                InstructionSource::Nonce => DataField::Nonce(width(8, &[1, 2, 4, 8])?),
+                InstructionSource::Destination => {
+                    width(32, &[32])?;
+                    DataField::Destination
+                }
+                InstructionSource::Bump => {
+                    width(1, &[1])?;
+                    DataField::Bump
+                }
+                InstructionSource::Literal => {
+                    let bytes = parse_hex(field.bytes.as_deref().ok_or_else(|| {
+                        anyhow::anyhow!("Instruction field {}: literal fields need bytes", i)
+                    })?)?;
+                    if bytes.is_empty() || field.width.is_some_and(|w| w != bytes.len()) {
+                        return Err(anyhow::anyhow!(
+                            "Instruction field {}: literal width doesn't match its bytes",
+                            i
+                        ));
+                    }
+                    DataField::Literal(bytes)
+                }
+            })
+        })
+        .collect()
+}
+
+/// Adds the instruction version byte to a compiled template
+pub fn add_version(template: &mut Vec<DataField>, version: u8, position: VersionPosition) {
+    let field = DataField::Literal(vec![version]);
+    match position {
+        VersionPosition::Prepend => template.insert(0, field),
+        VersionPosition::Append => template.push(field),
+    }
+}
+
+/// Appends `value` little-endian in `width` bytes, rejecting values that don't fit
+fn push_int(data: &mut Vec<u8>, name: &str, value: u64, width: usize) -> Result<()> {
+    if width < 8 && value >> (width * 8) != 0 {
+        return Err(anyhow::anyhow!(
+            "{} {} doesn't fit in {} bytes",
+            name,
+            value,
+            width
+        ));
+    }
+    data.extend_from_slice(&value.to_le_bytes()[..width]);
+    Ok(())
+}
 
 pub struct TransactionBuilder {
     pub program_id: Pubkey,
-    pub nonce_account: Pubkey,
+    /// Nonce account shards, nonce N is relayed through shard `N % len`
+    pub nonce_accounts: Vec<Pubkey>,
+    pub options: BuildOptions,
 }
 
 impl TransactionBuilder {
-    pub fn new(program_id: Pubkey, nonce_account: Pubkey) -> Self {
+    pub fn new(program_id: Pubkey, nonce_accounts: Vec<Pubkey>, options: BuildOptions) -> Self {
         Self {
             program_id,
-            nonce_account,
+            nonce_accounts,
+            options,
         }
     }
 
+    /// Shard index that relays the given nonce
+    pub fn shard_for(&self, nonce: u64) -> usize {
+        (nonce % self.nonce_accounts.len() as u64) as usize
+    }
+
+    /// Nonce account that relays the given nonce
+    pub fn nonce_account_for(&self, nonce: u64) -> Pubkey {
+        self.nonce_accounts[self.shard_for(nonce)]
+    }
+
     pub fn build_transfer_transaction(
         &self,
         amount: u64,
         nonce: u64,
+        bump: u8,
         to_address: &Pubkey,
-        payer: &impl Signer,
+        signers: &[&dyn Signer],
         client: &RpcClient,
     ) -> Result<Transaction> {
+        let mut instructions =
+            vec![self.transfer_instruction(amount, nonce, bump, to_address, signers)?];
+        instructions.extend(self.memo_instruction(amount, nonce, to_address));
+        self.build_transaction(&instructions, signers, client, Duration::ZERO)
+    }
+
+    /// HMAC memo of one relay, with `memo_hmac_key_env`
+    pub fn memo_instruction(
+        &self,
+        amount: u64,
+        nonce: u64,
+        to_address: &Pubkey,
+    ) -> Option<Instruction> {
+        self.options
+            .memo_key
+            .as_ref()
+            .map(|key| key.instruction(nonce, amount, to_address))
+    }
+
+    /// Relay instruction for one transfer
+    pub fn transfer_instruction(
+        &self,
+        amount: u64,
+        nonce: u64,
+        bump: u8,
+        to_address: &Pubkey,
+        signers: &[&dyn Signer],
+    ) -> Result<Instruction> {
+        // 第一个签名者支付手续费，其余为多签 authority
+        let (payer, authorities) = signers
+            .split_first()
+            .ok_or_else(|| anyhow::anyhow!("The fee payer must sign the relay transaction"))?;
         let system_program = solana_sdk::system_program::id();
 
-        let accounts = vec![
-            AccountMeta::new(self.nonce_account, false),
+        let mut accounts = vec![
+            AccountMeta::new(self.nonce_account_for(nonce), false),
             AccountMeta::new(payer.pubkey(), true),
             AccountMeta::new(*to_address, false),
             AccountMeta::new_readonly(system_program, false),
         ];
+        // 多签 authority 以只读签名账户的形式附加在末尾
+        accounts.extend(
+            authorities
+                .iter()
+                .map(|authority| AccountMeta::new_readonly(authority.pubkey(), true)),
+        );
+
+        Ok(Instruction {
+            program_id: self.program_id,
+            accounts,
+            data: self.instruction_data(amount, nonce, bump, to_address)?,
+        })
+    }
+
+    /// Signs a relay transaction made of the given instructions, paid by the
+    /// first signer. `pending_for` is how long the relayed nonce has been
+    /// waiting, which sets its priority fee
+    pub fn build_transaction(
+        &self,
+        instructions: &[Instruction],
+        signers: &[&dyn Signer],
+        client: &RpcClient,
+        pending_for: Duration,
+    ) -> Result<Transaction> {
+        let payer = signers
+            .first()
+            .ok_or_else(|| anyhow::anyhow!("The fee payer must sign the relay transaction"))?;
+
+        // 使用持久 nonce 时，第一条指令必须推进 nonce
+        let mut all_instructions = Vec::with_capacity(instructions.len() + 2);
+        if let Some(durable_nonce) = &self.options.durable_nonce {
+            all_instructions.push(system_instruction::advance_nonce_account(
+                &durable_nonce.account,
+                &durable_nonce.authority,
+            ));
+        }
+        if let Some(priority_fee) = &self.options.priority_fee {
+            all_instructions.push(ComputeBudgetInstruction::set_compute_unit_price(
+                priority_fee.compute_unit_price(pending_for),
+            ));
+        }
+        all_instructions.extend_from_slice(instructions);
+
+        let recent_blockhash = self.recent_blockhash(client)?;
+        let mut transaction = Transaction::new_with_payer(&all_instructions, Some(&payer.pubkey()));
+        transaction.try_sign(&signers.to_vec(), recent_blockhash)?;
+        self.check_size(&transaction)?;
+
+        Ok(transaction)
+    }
+
+    /// L1 transaction closing a relayed message PDA, its rent going to the
+    /// signer. Only built once the L2 relay confirmed, so a failed relay
+    /// never loses its message
+    pub fn close_pda_transaction(
+        &self,
+        pda: &Pubkey,
+        nonce: u64,
+        signer: &dyn Signer,
+        recent_blockhash: Hash,
+    ) -> Result<Transaction> {
+        let close_pda = self
+            .options
+            .close_pda
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("close_pda_after_relay isn't enabled"))?;
+
+        let mut data = close_pda.discriminator.to_vec();
+        data.extend_from_slice(&nonce.to_le_bytes());
+        let instruction = Instruction {
+            program_id: close_pda.program_id,
+            accounts: vec![
+                AccountMeta::new(*pda, false),
+                AccountMeta::new(signer.pubkey(), true),
+            ],
+            data,
+        };
+
+        let mut transaction = Transaction::new_with_payer(&[instruction], Some(&signer.pubkey()));
+        transaction.try_sign(&[signer], recent_blockhash)?;
+        Ok(transaction)
+    }
 
-        let mut instruction_data = Vec::with_capacity(24);
-        instruction_data.extend_from_slice(&[187, 90, 182, 138, 51, 248, 175, 98]);
-        instruction_data.extend_from_slice(&amount.to_le_bytes());
-        instruction_data.extend_from_slice(&nonce.to_le_bytes());
+    /// L2 transaction creating the given nonce account, paid and signed by
+    /// `payer` over a recent blockhash
+    pub fn build_init_nonce_account(
+        &self,
+        nonce_account: &Pubkey,
+        payer: &dyn Signer,
+        recent_blockhash: Hash,
+    ) -> Result<Transaction> {
+        let init = self
+            .options
+            .init_nonce_account
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("auto_init_nonce_account isn't enabled"))?;
 
+        let mut accounts = vec![
+            AccountMeta::new(*nonce_account, false),
+            AccountMeta::new(payer.pubkey(), true),
+            AccountMeta::new_readonly(solana_sdk::system_program::id(), false),
+        ];
+        accounts.extend(
+            init.accounts
+                .iter()
+                .map(|account| AccountMeta::new_readonly(*account, false)),
+        );
         let instruction = Instruction {
             program_id: self.program_id,
             accounts,
-            data: instruction_data,
+            data: init.discriminator.to_vec(),
         };
 
-        let recent_blockhash = client.get_latest_blockhash()?;
-        let transaction = Transaction::new_signed_with_payer(
-            &[instruction],
+        // 一次性的初始化交易，不推进 durable nonce，也不加优先费
+        let mut transaction = Transaction::new_with_payer(&[instruction], Some(&payer.pubkey()));
+        transaction.try_sign(&[payer], recent_blockhash)?;
+        self.check_size(&transaction)?;
+        Ok(transaction)
+    }
+
+    /// Blockhash relay transactions are signed with: the value stored in the
+    /// durable nonce account if one is configured, else the latest blockhash
+    pub fn recent_blockhash(&self, client: &RpcClient) -> Result<Hash> {
+        match &self.options.durable_nonce {
+            Some(durable_nonce) => {
+                let account = nonce_utils::get_account_with_commitment(
+                    client,
+                    &durable_nonce.account,
+                    CommitmentConfig::confirmed(),
+                )?;
+                Ok(nonce_utils::data_from_account(&account)?.blockhash())
+            }
+            None => self.options.blockhash_cache.get(client),
+        }
+    }
+
+    /// Assembles the instruction data from the configured template
+    pub fn instruction_data(
+        &self,
+        amount: u64,
+        nonce: u64,
+        bump: u8,
+        to_address: &Pubkey,
+    ) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        for field in &self.options.data_template {
+            match field {
+                DataField::Amount(width) => push_int(&mut data, "amount", amount, *width)?,
+                DataField::Nonce(width) => push_int(&mut data, "nonce", nonce, *width)?,
+                DataField::Destination => data.extend_from_slice(to_address.as_ref()),
+                DataField::Bump => data.push(bump),
+                DataField::Literal(bytes) => data.extend_from_slice(bytes),
+                DataField::RelayArgs => RelayArgs { amount, nonce }.serialize(&mut data)?,
+            }
+        }
+        Ok(data)
+    }
+
+    /// Rejects transactions that wouldn't fit in a single packet
+    fn check_size(&self, transaction: &Transaction) -> Result<()> {
+        let size = bincode::serialized_size(transaction)? as usize;
+        if size > self.options.max_transaction_size {
+            return Err(anyhow::anyhow!(
+                "transaction too large ({} > {} bytes)",
+                size,
+                self.options.max_transaction_size
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_client::rpc_request::RpcRequest;
+    use solana_sdk::{
+        nonce::state::{Data, DurableNonce as NonceValue, State, Versions},
+        signature::Keypair,
+        system_instruction::SystemInstruction,
+        system_program,
+    };
+    use std::collections::HashMap;
+
+    fn build_options(data_template: Vec<DataField>) -> BuildOptions {
+        BuildOptions {
+            max_transaction_size: solana_sdk::packet::PACKET_DATA_SIZE,
+            data_template,
+            durable_nonce: None,
+            blockhash_cache: Arc::new(BlockhashCache::new(60, None, CommitmentConfig::confirmed())),
+            priority_fee: None,
+            close_pda: None,
+            init_nonce_account: None,
+            memo_key: None,
+        }
+    }
+
+    fn builder(data_template: Vec<DataField>) -> TransactionBuilder {
+        TransactionBuilder::new(
+            Pubkey::new_unique(),
+            vec![Pubkey::new_unique()],
+            build_options(data_template),
+        )
+    }
+
+    #[test]
+    fn appends_bump_when_enabled() {
+        let to = Pubkey::new_unique();
+        let without = builder(compile_template(&[], false, None).unwrap())
+            .instruction_data(1000, 7, 254, &to)
+            .unwrap();
+        let with = builder(compile_template(&[], true, None).unwrap())
+            .instruction_data(1000, 7, 254, &to)
+            .unwrap();
+
+        assert_eq!(without.len(), 24);
+        assert_eq!(with.len(), 25);
+        assert_eq!(&with[..24], &without[..]);
+        assert_eq!(with[24], 254);
+    }
+
+    #[test]
+    fn escalates_fees_up_to_the_cap() {
+        let escalation = FeeEscalation {
+            step: 500,
+            max_attempts: 5,
+            max: Some(2_000),
+        };
+        assert_eq!(escalation.compute_unit_price(100, 1), 600);
+        assert_eq!(escalation.compute_unit_price(100, 3), 1_600);
+        assert_eq!(escalation.compute_unit_price(100, 4), 2_000);
+        assert_eq!(escalation.compute_unit_price(100, 5), 2_000);
+
+        let uncapped = FeeEscalation {
+            max: None,
+            ..escalation
+        };
+        assert_eq!(uncapped.compute_unit_price(100, 5), 2_600);
+        assert_eq!(uncapped.compute_unit_price(u64::MAX, 1), u64::MAX);
+    }
+
+    #[test]
+    fn rewrites_the_compute_unit_price() {
+        let payer = Keypair::new();
+        let transfer = system_instruction::transfer(&payer.pubkey(), &Pubkey::new_unique(), 1);
+        let mut priced = Transaction::new_with_payer(
+            &[
+                ComputeBudgetInstruction::set_compute_unit_limit(200_000),
+                ComputeBudgetInstruction::set_compute_unit_price(1_000),
+                transfer.clone(),
+            ],
             Some(&payer.pubkey()),
-            &[payer],
-            recent_blockhash,
+        );
+        assert_eq!(compute_unit_price(&priced), Some(1_000));
+        assert!(set_compute_unit_price(&mut priced, 4_500));
+        assert_eq!(compute_unit_price(&priced), Some(4_500));
+        // 其余计算预算指令保持不变
+        assert_eq!(
+            priced.message.instructions[0].data,
+            ComputeBudgetInstruction::set_compute_unit_limit(200_000).data
         );
 
-        Ok(transaction)
+        let mut unpriced = Transaction::new_with_payer(&[transfer], Some(&payer.pubkey()));
+        assert_eq!(compute_unit_price(&unpriced), None);
+        assert!(!set_compute_unit_price(&mut unpriced, 4_500));
+        assert_eq!(compute_unit_price(&unpriced), None);
+    }
+
+    #[test]
+    fn refreshes_stale_or_replaced_blockhashes() {
+        let cache = BlockhashCache::new(60, None, CommitmentConfig::confirmed());
+        let blockhash = Hash::new_unique();
+        assert!(cache.is_stale(&blockhash));
+
+        let cache_entry = |age_at_fetch, fetched_ago| CachedBlockhash {
+            blockhash,
+            age_at_fetch,
+            fetched_at: Instant::now() - fetched_ago,
+        };
+        *cache.cached.lock().unwrap() = Some(cache_entry(10, Duration::ZERO));
+        assert!(!cache.is_stale(&blockhash));
+        // 缓存已换成新的 blockhash，旧交易需要重新签名
+        assert!(cache.is_stale(&Hash::new_unique()));
+
+        *cache.cached.lock().unwrap() = Some(cache_entry(60, Duration::ZERO));
+        assert!(cache.is_stale(&blockhash));
+
+        // 取回时 10 个 slot，之后约 50 个 slot 的时间过去
+        let elapsed = Duration::from_millis(50 * DEFAULT_MS_PER_SLOT);
+        *cache.cached.lock().unwrap() = Some(cache_entry(10, elapsed));
+        assert!(cache.is_stale(&blockhash));
+    }
+
+    #[test]
+    fn refetches_aged_blockhashes_before_building() {
+        let aged = Hash::new_unique();
+        // 默认 mock 返回的 blockhash 为 150 个 slot 之前生成
+        let node = || {
+            RpcClient::new_mock_with_mocks(
+                "succeeds".to_string(),
+                HashMap::from([(
+                    RpcRequest::GetLatestBlockhash,
+                    serde_json::json!({
+                        "context": { "slot": 1 },
+                        "value": { "blockhash": aged.to_string(), "lastValidBlockHeight": 1000 },
+                    }),
+                )]),
+            )
+        };
+        let builder_with = |max_fetch_age_slots| {
+            let mut builder = builder(Vec::new());
+            builder.options.blockhash_cache = Arc::new(BlockhashCache::new(
+                400,
+                max_fetch_age_slots,
+                CommitmentConfig::finalized(),
+            ));
+            builder
+        };
+
+        assert_eq!(builder_with(None).recent_blockhash(&node()).unwrap(), aged);
+        let refetched = builder_with(Some(160)).recent_blockhash(&node()).unwrap();
+        assert_ne!(refetched, aged);
+        let err = builder_with(Some(100))
+            .recent_blockhash(&node())
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Latest blockhash is 150 slots old, above max_blockhash_age_slots (100)"
+        );
+    }
+
+    #[test]
+    fn relay_args_match_the_hand_packed_layout() {
+        let to = Pubkey::new_unique();
+        for include_bump in [false, true] {
+            let borsh = builder(compile_template(&[], include_bump, None).unwrap())
+                .instruction_data(u64::MAX - 1, 0x0102_0304_0506_0708, 254, &to)
+                .unwrap();
+
+            // 迁移到 Borsh 之前的手工拼接布局
+            let mut packed = RELAY_MESSAGE_DISCRIMINATOR.to_vec();
+            packed.extend_from_slice(&(u64::MAX - 1).to_le_bytes());
+            packed.extend_from_slice(&0x0102_0304_0506_0708u64.to_le_bytes());
+            if include_bump {
+                packed.push(254);
+            }
+            assert_eq!(borsh, packed);
+        }
+    }
+
+    #[test]
+    fn older_nonces_get_higher_compute_unit_prices() {
+        let payer = Keypair::new();
+        let mut options = build_options(compile_template(&[], false, None).unwrap());
+        options.priority_fee = Some(PriorityFee {
+            base: 1_000,
+            increment_per_sec: 50,
+            max: Some(5_000),
+        });
+        let builder =
+            TransactionBuilder::new(Pubkey::new_unique(), vec![Pubkey::new_unique()], options);
+        let client = RpcClient::new_mock("succeeds".to_string());
+        let relay = builder
+            .transfer_instruction(1000, 7, 254, &Pubkey::new_unique(), &[&payer])
+            .unwrap();
+
+        let prices: Vec<Option<u64>> = [0, 10, 60, 3600]
+            .into_iter()
+            .map(|secs| {
+                let transaction = builder
+                    .build_transaction(
+                        std::slice::from_ref(&relay),
+                        &[&payer],
+                        &client,
+                        Duration::from_secs(secs),
+                    )
+                    .unwrap();
+                compute_unit_price(&transaction)
+            })
+            .collect();
+        assert_eq!(
+            prices,
+            vec![Some(1_000), Some(1_500), Some(4_000), Some(5_000)]
+        );
+    }
+
+    #[test]
+    fn init_nonce_account_skips_durable_nonce_and_priority_fee() {
+        let payer = Keypair::new();
+        let mut options = build_options(compile_template(&[], false, None).unwrap());
+        options.durable_nonce = Some(DurableNonce {
+            account: Pubkey::new_unique(),
+            authority: payer.pubkey(),
+        });
+        options.priority_fee = Some(PriorityFee {
+            base: 1_000,
+            increment_per_sec: 0,
+            max: None,
+        });
+        options.init_nonce_account = Some(InitNonceAccount {
+            discriminator: initialize_discriminator(),
+            accounts: vec![],
+        });
+        let nonce_account = Pubkey::new_unique();
+        let builder = TransactionBuilder::new(Pubkey::new_unique(), vec![nonce_account], options);
+
+        let blockhash = Hash::new_unique();
+        let transaction = builder
+            .build_init_nonce_account(&nonce_account, &payer, blockhash)
+            .unwrap();
+        let message = &transaction.message;
+        assert_eq!(message.recent_blockhash, blockhash);
+        assert_eq!(message.account_keys[0], payer.pubkey());
+        assert_eq!(message.instructions.len(), 1);
+        assert_eq!(
+            message.account_keys[message.instructions[0].program_id_index as usize],
+            builder.program_id
+        );
+        assert_eq!(message.instructions[0].data, initialize_discriminator());
+        transaction.verify().unwrap();
+    }
+
+    fn transaction_with_data(len: usize) -> Transaction {
+        let instruction = Instruction::new_with_bytes(Pubkey::new_unique(), &vec![0; len], vec![]);
+        Transaction::new_with_payer(&[instruction], Some(&Pubkey::new_unique()))
+    }
+
+    #[test]
+    fn rejects_transactions_over_the_packet_size() {
+        let builder = builder(compile_template(&[], false, None).unwrap());
+        builder.check_size(&transaction_with_data(100)).unwrap();
+
+        let oversized = transaction_with_data(1300);
+        let size = bincode::serialized_size(&oversized).unwrap();
+        assert!(size > 1232);
+        let err = builder.check_size(&oversized).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("transaction too large ({} > 1232 bytes)", size)
+        );
+    }
+
+    #[test]
+    fn relays_each_nonce_through_its_shard() {
+        let shards = vec![
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        ];
+        let builder = TransactionBuilder::new(
+            Pubkey::new_unique(),
+            shards.clone(),
+            build_options(compile_template(&[], false, None).unwrap()),
+        );
+        for (nonce, shard) in [(0, 0), (1, 1), (2, 2), (3, 0), (7, 1), (u64::MAX, 0)] {
+            assert_eq!(builder.shard_for(nonce), shard, "nonce {}", nonce);
+            assert_eq!(builder.nonce_account_for(nonce), shards[shard]);
+        }
+
+        let payer = Keypair::new();
+        let instruction = builder
+            .transfer_instruction(1000, 5, 254, &Pubkey::new_unique(), &[&payer])
+            .unwrap();
+        assert_eq!(instruction.accounts[0].pubkey, shards[2]);
+        assert!(instruction.accounts[0].is_writable);
+    }
+
+    #[test]
+    fn multisig_relays_are_signed_by_every_authority() {
+        let builder = builder(compile_template(&[], false, None).unwrap());
+        let payer = Keypair::new();
+        let authority = Keypair::new();
+        let client = RpcClient::new_mock("succeeds".to_string());
+
+        let transaction = builder
+            .build_transfer_transaction(
+                1000,
+                7,
+                254,
+                &Pubkey::new_unique(),
+                &[&payer, &authority],
+                &client,
+            )
+            .unwrap();
+        transaction.verify().unwrap();
+        assert_eq!(transaction.signatures.len(), 2);
+        assert_eq!(transaction.message.header.num_required_signatures, 2);
+        assert_eq!(transaction.message.account_keys[0], payer.pubkey());
+        assert!(transaction.message.account_keys[..2].contains(&authority.pubkey()));
+        let relay = &transaction.message.instructions[transaction.message.instructions.len() - 1];
+        let authority_index = transaction
+            .message
+            .account_keys
+            .iter()
+            .position(|key| *key == authority.pubkey())
+            .unwrap();
+        assert_eq!(*relay.accounts.last().unwrap() as usize, authority_index);
+
+        let err = builder
+            .transfer_instruction(1000, 7, 254, &Pubkey::new_unique(), &[])
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The fee payer must sign the relay transaction"
+        );
+    }
+
+    fn template(fields: serde_json::Value) -> Result<Vec<DataField>> {
+        let fields: Vec<InstructionField> = serde_json::from_value(fields).unwrap();
+        compile_template(&fields, false, None)
+    }
+
+    #[test]
+    fn places_the_instruction_version_byte() {
+        let to = Pubkey::new_unique();
+        let data = |version: Option<(u8, VersionPosition)>| {
+            let mut data_template = compile_template(&[], true, None).unwrap();
+            if let Some((version, position)) = version {
+                add_version(&mut data_template, version, position);
+            }
+            builder(data_template)
+                .instruction_data(1000, 7, 254, &to)
+                .unwrap()
+        };
+
+        let unversioned = data(None);
+        assert_eq!(&unversioned[..8], &RELAY_MESSAGE_DISCRIMINATOR);
+
+        let prepended = data(Some((2, VersionPosition::Prepend)));
+        assert_eq!(prepended[0], 2);
+        assert_eq!(&prepended[1..], &unversioned[..]);
+
+        let appended = data(Some((3, VersionPosition::Append)));
+        assert_eq!(appended.last(), Some(&3));
+        assert_eq!(&appended[..unversioned.len()], &unversioned[..]);
+    }
+
+    #[test]
+    fn assembles_instruction_data_from_templates() {
+        let to = Pubkey::new_unique();
+
+        // 单字节指令号 + u32 nonce + u64 amount
+        let compact = template(serde_json::json!([
+            { "source": "literal", "bytes": "0x07" },
+            { "source": "nonce", "width": 4 },
+            { "source": "amount" },
+        ]))
+        .unwrap();
+        let data = builder(compact)
+            .instruction_data(1000, 0x0102_0304, 254, &to)
+            .unwrap();
+        let mut expected = vec![0x07, 0x04, 0x03, 0x02, 0x01];
+        expected.extend_from_slice(&1000u64.to_le_bytes());
+        assert_eq!(data, expected);
+
+        // 目标地址 + u16 amount + bump
+        let with_destination = template(serde_json::json!([
+            { "source": "destination" },
+            { "source": "amount", "width": 2 },
+            { "source": "bump" },
+        ]))
+        .unwrap();
+        let builder = builder(with_destination);
+        let data = builder.instruction_data(0x0a0b, 7, 254, &to).unwrap();
+        let mut expected = to.to_bytes().to_vec();
+        expected.extend_from_slice(&[0x0b, 0x0a, 254]);
+        assert_eq!(data, expected);
+        assert_eq!(
+            builder
+                .instruction_data(0x1_0000, 7, 254, &to)
+                .unwrap_err()
+                .to_string(),
+            "amount 65536 doesn't fit in 2 bytes"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_templates() {
+        for (fields, message) in [
+            (
+                serde_json::json!([{ "source": "nonce", "width": 3 }]),
+                "Instruction field 0: unsupported Nonce width 3",
+            ),
+            (
+                serde_json::json!([{ "source": "amount", "bytes": "01" }]),
+                "Instruction field 0: bytes are only allowed on literal fields",
+            ),
+            (
+                serde_json::json!([{ "source": "nonce" }, { "source": "literal" }]),
+                "Instruction field 1: literal fields need bytes",
+            ),
+            (
+                serde_json::json!([{ "source": "literal", "bytes": "0102", "width": 1 }]),
+                "Instruction field 0: literal width doesn't match its bytes",
+            ),
+            (
+                serde_json::json!([{ "source": "destination", "width": 8 }]),
+                "Instruction field 0: unsupported Destination width 8",
+            ),
+        ] {
+            assert_eq!(template(fields).unwrap_err().to_string(), message);
+        }
+    }
+
+    #[test]
+    fn durable_nonce_relays_advance_the_nonce_first() {
+        let payer = Keypair::new();
+        let account = Pubkey::new_unique();
+        let stored = NonceValue::from_blockhash(&Hash::new_unique());
+        let state = Versions::new(State::Initialized(Data::new(payer.pubkey(), stored, 5000)));
+        let client = RpcClient::new_mock_with_mocks(
+            "succeeds".to_string(),
+            HashMap::from([(
+                RpcRequest::GetAccountInfo,
+                serde_json::json!({
+                    "context": { "slot": 1 },
+                    "value": {
+                        "lamports": 1_000_000,
+                        "data": [base64::encode(bincode::serialize(&state).unwrap()), "base64"],
+                        "owner": system_program::id().to_string(),
+                        "executable": false,
+                        "rentEpoch": 0,
+                    },
+                }),
+            )]),
+        );
+        let mut options = build_options(compile_template(&[], false, None).unwrap());
+        options.durable_nonce = Some(DurableNonce {
+            account,
+            authority: payer.pubkey(),
+        });
+        let builder =
+            TransactionBuilder::new(Pubkey::new_unique(), vec![Pubkey::new_unique()], options);
+
+        let transaction = builder
+            .build_transfer_transaction(1000, 7, 254, &Pubkey::new_unique(), &[&payer], &client)
+            .unwrap();
+        transaction.verify().unwrap();
+        assert_eq!(transaction.message.recent_blockhash, *stored.as_hash());
+        let message = &transaction.message;
+        let advance = &message.instructions[0];
+        assert_eq!(
+            message.account_keys[advance.program_id_index as usize],
+            system_program::id()
+        );
+        assert_eq!(
+            bincode::deserialize::<SystemInstruction>(&advance.data).unwrap(),
+            SystemInstruction::AdvanceNonceAccount
+        );
+        assert_eq!(message.account_keys[advance.accounts[0] as usize], account);
+        assert_eq!(message.instructions.len(), 2);
     }
 }